@@ -1,63 +1,273 @@
-use crate::{ATTRIBUTE, COMMENT, DOCDECL, IGNORABLE_WHITESPACE, PROCESSING_INSTRUCTION};
-use crate::{AbxError, PROTOCOL_MAGIC_VERSION_0, Result};
-use crate::{CDSECT, END_DOCUMENT, END_TAG, ENTITY_REF, START_DOCUMENT, START_TAG, TEXT};
-use crate::{TYPE_BOOLEAN_FALSE, TYPE_BOOLEAN_TRUE};
-use crate::{TYPE_BYTES_BASE64, TYPE_BYTES_HEX, TYPE_STRING, TYPE_STRING_INTERNED};
-use crate::{TYPE_DOUBLE, TYPE_FLOAT, TYPE_INT, TYPE_INT_HEX, TYPE_LONG, TYPE_LONG_HEX};
-use crate::Policy;
+use crate::describe_token;
+use crate::hash::value_hash;
+use crate::{
+    ATTRIBUTE, AbxError, CDSECT, Result, START_DOCUMENT, START_TAG, TEXT, TYPE_STRING,
+    TYPE_STRING_INTERNED,
+};
+use crate::{
+    Attribute, AttributeValue, Command, NoopHandler, command_of, decode_attribute_value, type_of,
+};
+use crate::{
+    AttributeWrap, Base64Alphabet, DeserializerOptions, DuplicateAttributePolicy, FloatFormat,
+};
+use crate::{BooleanStyle, LineEnding, QuoteStyle, WarningPolicy, WhitespacePolicy};
+use crate::{
+    CoalesceText, Endianness, FlushPolicy, NamespaceMode, NumericStyle, REDACTED_PLACEHOLDER,
+    StringEncoding,
+};
+use crate::{
+    InternId, InternRefContext, InternRefSite, Interner, MetadataKind, Policy, TokenHandler,
+    VecInterner, Warning,
+};
+use crate::{TYPE_BYTES_BASE64, TYPE_BYTES_HEX};
 use base64::Engine;
-use hex;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/// Interned string reference marking a string that is defined inline
+const NEW_INTERNED_STRING: u16 = 0xFFFF;
 
 /// Fast data input reader for binary ABX format
-pub struct FastDataInput<R: Read + Seek> {
+///
+/// # String pool
+///
+/// ABX has no separate string pool section. Android's `BinaryXmlSerializer`
+/// writes the magic header and then tokens straight away, and
+/// `FastDataOutput.writeInternedUTF` defines each interned string inline the
+/// first time it is used (`0xFFFF` followed by the string), referring back to
+/// it by index afterwards. The interned table is therefore built only from
+/// those inline definitions, and there is no front-loaded pool to detect.
+/// The resource-compiled binary XML inside APKs (AXML) does start with a
+/// string pool, but it is a different format with its own header and isn't
+/// read by this crate.
+pub struct FastDataInput<R: Read, I: Interner = VecInterner> {
     reader: R,
-    interned_strings: Vec<String>,
+    /// Offset of the next unread byte, counting a peeked byte as unread
+    position: u64,
+    interner: I,
+    interned_ids: Vec<InternId>,
+    string_encoding: StringEncoding,
+    endianness: Endianness,
+    max_interned_strings: usize,
+    reference_shift: i32,
+    /// Byte read ahead by `peek`, logically still unread
+    peeked: Option<u8>,
+    /// `(offset, string)` of each interned string defined, when recording
+    definitions: Option<Vec<(u64, String)>>,
+    /// Report references past the table as `InternedIndexOutOfRange`
+    validate_indices: bool,
+    /// `(offset, index)` of each reference read since the last take, when recording
+    reference_sites: Option<Vec<(u64, u16)>>,
 }
 
 impl<R: Read + Seek> FastDataInput<R> {
     /// Create a new FastDataInput reader
+    ///
+    /// Offsets are those of the underlying reader, starting from its current
+    /// position.
     pub fn new(reader: R) -> Self {
+        Self::with_interner(reader, VecInterner::new())
+    }
+}
+
+impl<R: Read> FastDataInput<R> {
+    /// Get the interned strings table (for debugging)
+    pub fn interned_strings(&self) -> &[String] {
+        self.interner.strings()
+    }
+}
+
+impl<R: Read + Seek, I: Interner> FastDataInput<R, I> {
+    /// Create a new FastDataInput reader that stores interned strings in `interner`
+    pub fn with_interner(mut reader: R, interner: I) -> Self {
+        let position = reader.stream_position().unwrap_or(0);
+        Self::at_position(reader, interner, position)
+    }
+
+    /// Seek to a specific position in the stream
+    pub fn seek(&mut self, pos: u64) -> Result<()> {
+        self.peeked = None;
+        self.reader.seek(SeekFrom::Start(pos))?;
+        self.position = pos;
+        Ok(())
+    }
+}
+
+impl<R: Read, I: Interner> FastDataInput<R, I> {
+    /// Create a reader for a stream that can't report its position, whose
+    /// next byte is at offset `position`
+    pub(crate) fn at_position(reader: R, interner: I, position: u64) -> Self {
         Self {
             reader,
-            interned_strings: Vec::new(),
+            position,
+            interner,
+            interned_ids: Vec::new(),
+            string_encoding: StringEncoding::default(),
+            endianness: Endianness::default(),
+            max_interned_strings: usize::MAX,
+            reference_shift: 0,
+            peeked: None,
+            definitions: None,
+            validate_indices: false,
+            reference_sites: None,
+        }
+    }
+
+    /// Set how string payloads are encoded (modified UTF-8 by default)
+    pub fn with_string_encoding(mut self, encoding: StringEncoding) -> Self {
+        self.string_encoding = encoding;
+        self
+    }
+
+    /// Set the byte order of multi-byte values (big-endian, as Android writes, by default)
+    pub fn with_endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    /// Fail with a `ParseError` when the stream defines more than `limit`
+    /// interned strings (unlimited by default)
+    pub fn with_max_interned_strings(mut self, limit: usize) -> Self {
+        self.max_interned_strings = limit;
+        self
+    }
+
+    /// Record the offset of each interned string's `0xFFFF` definition, see
+    /// [`interned_string_definitions`](Self::interned_string_definitions)
+    pub fn with_definition_offsets(mut self, enabled: bool) -> Self {
+        self.definitions = enabled.then(Vec::new);
+        self
+    }
+
+    /// Fail references to strings not defined yet with
+    /// `AbxError::InternedIndexOutOfRange`, which has the reference's offset
+    /// and the table size, instead of `InvalidInternedStringIndex`
+    pub fn with_index_validation(mut self, enabled: bool) -> Self {
+        self.validate_indices = enabled;
+        self
+    }
+
+    /// Record the offset and index of every back-reference read, to be
+    /// collected with `take_reference_sites`
+    pub(crate) fn with_reference_sites(mut self, enabled: bool) -> Self {
+        self.reference_sites = enabled.then(Vec::new);
+        self
+    }
+
+    /// Back-references read since the last call, as `(offset, index)`
+    pub(crate) fn take_reference_sites(&mut self) -> Vec<(u64, u16)> {
+        self.reference_sites
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Read interned references as `index - shift`, to undo references that
+    /// were all written off by `shift`
+    pub(crate) fn with_reference_shift(mut self, shift: i32) -> Self {
+        self.reference_shift = shift;
+        self
+    }
+
+    /// The interner holding this stream's strings
+    pub fn interner(&self) -> &I {
+        &self.interner
+    }
+
+    /// Number of strings this stream has interned so far
+    pub fn interned_count(&self) -> usize {
+        self.interned_ids.len()
+    }
+
+    /// Where each interned string was defined, as `(offset, string)` in table order
+    ///
+    /// The offset is that of the `0xFFFF` reference preceding the string.
+    /// Empty unless enabled with
+    /// [`with_definition_offsets`](Self::with_definition_offsets); strings
+    /// added by `with_interned_strings` have no definition site and aren't listed.
+    pub fn interned_string_definitions(&self) -> &[(u64, String)] {
+        self.definitions.as_deref().unwrap_or_default()
+    }
+
+    /// Fill `buf` from the stream, starting with any peeked byte
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match self.peeked.take() {
+            Some(byte) => {
+                buf[0] = byte;
+                self.reader.read_exact(&mut buf[1..])?;
+            }
+            None => self.reader.read_exact(buf)?,
+        }
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Look at the next byte without consuming it
+    ///
+    /// Returns `None` at the end of the stream. Unlike a `read_byte` and a
+    /// seek back, this doesn't touch the underlying reader's position, so it
+    /// costs nothing on readers where seeking is slow.
+    pub fn peek(&mut self) -> Result<Option<u8>> {
+        if self.peeked.is_none() {
+            let mut buf = [0u8; 1];
+            match self.reader.read(&mut buf)? {
+                0 => return Ok(None),
+                _ => self.peeked = Some(buf[0]),
+            }
         }
+        Ok(self.peeked)
     }
 
     /// Read a single byte
     pub fn read_byte(&mut self) -> Result<u8> {
         let mut buf = [0u8; 1];
-        self.reader
-            .read_exact(&mut buf)
+        self.fill(&mut buf)
             .map_err(|_| AbxError::ReadError("byte".to_string()))?;
         Ok(buf[0])
     }
 
-    /// Read a 16-bit unsigned integer (big-endian)
+    /// Read a 16-bit unsigned integer
+    ///
+    /// Same as [`read_unsigned_short`](Self::read_unsigned_short).
     pub fn read_short(&mut self) -> Result<u16> {
+        self.read_unsigned_short()
+    }
+
+    /// Read a 16-bit unsigned integer, as used for lengths
+    pub fn read_unsigned_short(&mut self) -> Result<u16> {
         let mut buf = [0u8; 2];
-        self.reader
-            .read_exact(&mut buf)
+        self.fill(&mut buf)
             .map_err(|_| AbxError::ReadError("short".to_string()))?;
-        Ok(u16::from_be_bytes(buf))
+        Ok(match self.endianness {
+            Endianness::Big => u16::from_be_bytes(buf),
+            Endianness::Little => u16::from_le_bytes(buf),
+        })
     }
 
-    /// Read a 32-bit signed integer (big-endian)
+    /// Read a 32-bit signed integer
     pub fn read_int(&mut self) -> Result<i32> {
         let mut buf = [0u8; 4];
-        self.reader
-            .read_exact(&mut buf)
+        self.fill(&mut buf)
             .map_err(|_| AbxError::ReadError("int".to_string()))?;
-        Ok(i32::from_be_bytes(buf))
+        Ok(match self.endianness {
+            Endianness::Big => i32::from_be_bytes(buf),
+            Endianness::Little => i32::from_le_bytes(buf),
+        })
     }
 
-    /// Read a 64-bit signed integer (big-endian)
+    /// Read a 64-bit signed integer
     pub fn read_long(&mut self) -> Result<i64> {
         let mut buf = [0u8; 8];
-        self.reader
-            .read_exact(&mut buf)
+        self.fill(&mut buf)
             .map_err(|_| AbxError::ReadError("long".to_string()))?;
-        Ok(i64::from_be_bytes(buf))
+        Ok(match self.endianness {
+            Endianness::Big => i64::from_be_bytes(buf),
+            Endianness::Little => i64::from_le_bytes(buf),
+        })
     }
 
     /// Read a 32-bit float
@@ -72,72 +282,230 @@ impl<R: Read + Seek> FastDataInput<R> {
         Ok(f64::from_bits(int_value))
     }
 
-    /// Read a UTF-8 string
+    /// Read a length-prefixed string in the configured encoding
     pub fn read_utf(&mut self) -> Result<String> {
-        let length = self.read_short()?;
+        if self.string_encoding == StringEncoding::Utf16 {
+            return self.read_utf16();
+        }
+        let length = self.read_unsigned_short()?;
         let mut buffer = vec![0u8; length as usize];
-        self.reader
-            .read_exact(&mut buffer)
+        self.fill(&mut buffer)
             .map_err(|_| AbxError::ReadError("UTF string".to_string()))?;
         String::from_utf8(buffer)
             .map_err(|_| AbxError::ReadError("UTF string (invalid UTF-8)".to_string()))
     }
 
-    /// Read an interned UTF-8 string
+    /// Read a string stored as a code unit count and big-endian UTF-16
+    fn read_utf16(&mut self) -> Result<String> {
+        let length = self.read_unsigned_short()?;
+        let mut buffer = vec![0u8; length as usize * 2];
+        self.fill(&mut buffer)
+            .map_err(|_| AbxError::ReadError("UTF-16 string".to_string()))?;
+        let units: Vec<u16> = buffer
+            .chunks_exact(2)
+            .map(|pair| match self.endianness {
+                Endianness::Big => u16::from_be_bytes([pair[0], pair[1]]),
+                Endianness::Little => u16::from_le_bytes([pair[0], pair[1]]),
+            })
+            .collect();
+        String::from_utf16(&units)
+            .map_err(|_| AbxError::ReadError("UTF-16 string (invalid UTF-16)".to_string()))
+    }
+
+    /// Read the reference that precedes an interned string
+    ///
+    /// Returns `None` for `0xFFFF` (Java's `-1`), meaning the string follows
+    /// inline and is added to the table, or `Some(index)` into the table.
+    /// Android reads this as an *unsigned* short, so indices of `0x8000` and
+    /// above are ordinary table entries, not negative values.
+    pub fn read_interned_index(&mut self) -> Result<Option<u16>> {
+        match self.read_unsigned_short()? {
+            NEW_INTERNED_STRING => Ok(None),
+            index => Ok(Some(index)),
+        }
+    }
+
+    /// Read the string payload of a token other than a tag or attribute,
+    /// whose byte `token` was just read
+    ///
+    /// Android always writes these as `TYPE_STRING`. Some serializers intern
+    /// repeated text content, so TEXT and CDSECT also accept
+    /// `TYPE_STRING_INTERNED`. Any other type would leave its payload unread
+    /// and desync the stream, so it is rejected outright.
+    pub(crate) fn read_token_payload(&mut self, token: u8) -> Result<String> {
+        match (command_of(token), type_of(token)) {
+            (TEXT | CDSECT, TYPE_STRING_INTERNED) => self.read_interned_utf(),
+            (_, TYPE_STRING) => self.read_utf(),
+            _ => Err(AbxError::ParseError(format!(
+                "Unsupported type 0x{:02X} for token 0x{:02X} at offset {}",
+                type_of(token),
+                token,
+                self.position.saturating_sub(1)
+            ))),
+        }
+    }
+
+    /// Read an interned string
+    ///
+    /// Like Android, once the table holds `0xFFFF` strings further new
+    /// strings are still read but no longer added to it.
     pub fn read_interned_utf(&mut self) -> Result<String> {
-        let index = self.read_short()?;
-        if index == 0xFFFF {
-            let string = self.read_utf()?;
-            self.interned_strings.push(string.clone());
-            Ok(string)
-        } else {
-            self.interned_strings
-                .get(index as usize)
-                .cloned()
-                .ok_or(AbxError::InvalidInternedStringIndex(index))
+        let index = self.read_interned_index()?;
+        if let Some(index) = index
+            && self.reference_sites.is_some()
+        {
+            let offset = self.tell()? - 2;
+            self.reference_sites
+                .get_or_insert_default()
+                .push((offset, index));
+        }
+        match index {
+            None => {
+                let offset = self.tell()? - 2;
+                if self.interned_ids.len() >= self.max_interned_strings {
+                    return Err(AbxError::ParseError(format!(
+                        "interned string limit exceeded ({}) at offset {}",
+                        self.max_interned_strings, offset
+                    )));
+                }
+                let string = self.read_utf()?;
+                if self.interned_ids.len() < NEW_INTERNED_STRING as usize
+                    && let Some(definitions) = &mut self.definitions
+                {
+                    definitions.push((offset, string.clone()));
+                }
+                self.define_interned(string.clone());
+                Ok(string)
+            }
+            Some(index)
+                if self.validate_indices
+                    && index as i64 - self.reference_shift as i64
+                        >= self.interned_ids.len() as i64 =>
+            {
+                Err(AbxError::InternedIndexOutOfRange {
+                    index,
+                    offset: self.tell()? - 2,
+                    defined: self.interned_ids.len(),
+                })
+            }
+            Some(index) => usize::try_from(index as i32 - self.reference_shift)
+                .ok()
+                .and_then(|index| self.interned_ids.get(index))
+                .and_then(|id| self.interner.resolve(*id))
+                .map(str::to_string)
+                .ok_or(AbxError::InvalidInternedStringIndex(index)),
+        }
+    }
+
+    /// Add a string to the interned table as if the stream had defined it
+    ///
+    /// Ignored once the table is full, see `read_interned_utf`.
+    pub(crate) fn define_interned(&mut self, string: String) {
+        if self.interned_ids.len() < NEW_INTERNED_STRING as usize {
+            let id = self.interner.intern(string);
+            self.interned_ids.push(id);
         }
     }
 
     /// Read a byte array of specified length
     pub fn read_bytes(&mut self, length: u16) -> Result<Vec<u8>> {
         let mut data = vec![0u8; length as usize];
-        self.reader
-            .read_exact(&mut data)
+        self.fill(&mut data)
             .map_err(|_| AbxError::ReadError("bytes".to_string()))?;
         Ok(data)
     }
 
-    /// Get current position in the stream
-    pub fn tell(&mut self) -> Result<u64> {
-        self.reader.stream_position().map_err(AbxError::Io)
+    /// Read up to `length` bytes, stopping early at the end of the stream
+    pub fn read_bytes_partial(&mut self, length: u16) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; length as usize];
+        let mut filled = 0;
+        if length > 0
+            && let Some(byte) = self.peeked.take()
+        {
+            data[0] = byte;
+            filled = 1;
+        }
+        while filled < data.len() {
+            match self.reader.read(&mut data[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(AbxError::Io(e)),
+            }
+        }
+        data.truncate(filled);
+        self.position += filled as u64;
+        Ok(data)
     }
 
-    /// Seek to a specific position in the stream
-    pub fn seek(&mut self, pos: u64) -> Result<()> {
-        self.reader.seek(SeekFrom::Start(pos))?;
-        Ok(())
+    /// Get current position in the stream
+    ///
+    /// Positions are counted as bytes are read, so this never touches the
+    /// underlying reader.
+    pub fn tell(&mut self) -> Result<u64> {
+        Ok(self.position)
     }
 
     /// Check if we've reached the end of the stream
+    ///
+    /// Peeks at the next byte rather than seeking to the end and back, which
+    /// would throw away a `BufReader`'s buffer on every call. A read error
+    /// counts as the end.
     pub fn is_eof(&mut self) -> bool {
-        let current_pos = match self.reader.stream_position() {
-            Ok(pos) => pos,
-            Err(_) => return true,
-        };
+        !matches!(self.peek(), Ok(Some(_)))
+    }
+}
 
-        let end_pos = match self.reader.seek(SeekFrom::End(0)) {
-            Ok(pos) => pos,
-            Err(_) => return true,
-        };
+/// Read the 4-byte magic header and check it is one of `accepted`
+pub(crate) fn read_magic<R: Read>(reader: &mut R, accepted: &[[u8; 4]]) -> Result<[u8; 4]> {
+    let mut magic = [0u8; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| AbxError::ReadError("magic header".to_string()))?;
 
-        let _ = self.reader.seek(SeekFrom::Start(current_pos));
-        current_pos >= end_pos
+    if !accepted.contains(&magic) {
+        return Err(AbxError::InvalidMagicHeader {
+            expected: accepted.to_vec(),
+            actual: magic,
+        });
     }
+    Ok(magic)
+}
 
-    /// Get the interned strings table (for debugging)
-    pub fn interned_strings(&self) -> &[String] {
-        &self.interned_strings
+/// `NameStartChar` from the XML 1.0 `Name` production
+fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        ':' | 'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}')
+}
+
+/// `NameChar` from the XML 1.0 `Name` production
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+            '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+}
+
+/// Whether `name` matches the XML 1.0 `Name` production
+pub fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(is_name_start_char) && chars.all(is_name_char)
+}
+
+/// Turn `name` into a valid XML `Name`: illegal characters become `_`, and a
+/// name that can't start a `Name` (e.g. a leading digit) gets a `_` prefix
+pub fn sanitize_xml_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if is_name_char(c) { c } else { '_' })
+        .collect();
+    if !sanitized.starts_with(is_name_start_char) {
+        sanitized.insert(0, '_');
     }
+    sanitized
 }
 
 /// XML entity encoder for safe XML output
@@ -149,261 +517,3149 @@ pub fn encode_xml_entities(text: &str) -> String {
         .replace('\'', "&apos;")
 }
 
+/// Render an attribute value between the quotes of `style`
+///
+/// Double quotes escape everything `encode_xml_entities` does. Single quotes
+/// leave `"` as is, since only the delimiting quote needs escaping.
+fn quote_attribute_value(value: &str, style: QuoteStyle) -> String {
+    match style {
+        QuoteStyle::Double => format!("\"{}\"", encode_xml_entities(value)),
+        QuoteStyle::Single => format!(
+            "'{}'",
+            value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('\'', "&apos;")
+        ),
+    }
+}
+
+/// Render a float or double according to the configured `FloatFormat`
+fn format_float<T: std::fmt::Display + std::fmt::LowerExp>(
+    value: T,
+    format: FloatFormat,
+) -> String {
+    match format {
+        FloatFormat::Rust => format!("{}", value),
+        FloatFormat::JavaCompatible => java_float_string(value),
+    }
+}
+
+/// Render a float the way Java's `Float.toString`/`Double.toString` does
+///
+/// Java picks the shortest digit string that round-trips (but never fewer than
+/// two significant digits), then writes values in `[1e-3, 1e7)` as plain
+/// decimals and everything else in computerized scientific notation, always
+/// with at least one fractional digit.
+fn java_float_string<T: std::fmt::LowerExp>(value: T) -> String {
+    let mut scientific = format!("{:e}", value);
+    if scientific == "NaN" {
+        return "NaN".to_string();
+    }
+    if !scientific.contains('.') {
+        // Among two-digit decimals, Java takes the one closest to the value
+        scientific = format!("{:.1e}", value);
+    }
+
+    let (sign, unsigned) = match scientific.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", scientific.as_str()),
+    };
+    if unsigned == "inf" {
+        return format!("{}Infinity", sign);
+    }
+
+    let (mantissa, exponent) = unsigned.split_once('e').unwrap_or((unsigned, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let digits = digits.trim_end_matches('0');
+
+    if digits.is_empty() {
+        return format!("{}0.0", sign);
+    }
+
+    if (-3..7).contains(&exponent) {
+        if exponent < 0 {
+            let zeros = "0".repeat((-exponent - 1) as usize);
+            return format!("{}0.{}{}", sign, zeros, digits);
+        }
+
+        let int_len = exponent as usize + 1;
+        if digits.len() <= int_len {
+            let zeros = "0".repeat(int_len - digits.len());
+            format!("{}{}{}.0", sign, digits, zeros)
+        } else {
+            format!("{}{}.{}", sign, &digits[..int_len], &digits[int_len..])
+        }
+    } else {
+        let fraction = if digits.len() > 1 { &digits[1..] } else { "0" };
+        format!("{}{}.{}E{}", sign, &digits[..1], fraction, exponent)
+    }
+}
+
+/// Written at the start of every document
+pub(crate) const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+
+/// Output size over input size, or 0 for empty input
+pub(crate) fn expansion_ratio(input_bytes: u64, output_bytes: u64) -> f64 {
+    if input_bytes == 0 {
+        return 0.0;
+    }
+    output_bytes as f64 / input_bytes as f64
+}
+
+/// Render a decoded attribute value as it appears in XML, before escaping
+pub(crate) fn render_attribute_value(
+    value: &AttributeValue,
+    options: &DeserializerOptions,
+) -> String {
+    let float_format = options.float_format;
+    let long_suffix = match options.numeric_style {
+        NumericStyle::Bare => "",
+        NumericStyle::JavaLiteral => "L",
+    };
+    match value {
+        AttributeValue::String(s) | AttributeValue::InternedString(s) => s.clone(),
+        AttributeValue::Int(v) => format!("{}", v),
+        // Two's complement, like Java's `Integer.toHexString`
+        AttributeValue::IntHex(v) => format!("0x{:X}", *v as u32),
+        AttributeValue::Long(v) => format!("{}{}", v, long_suffix),
+        AttributeValue::LongHex(v) => format!("0x{:X}{}", *v as u64, long_suffix),
+        AttributeValue::Float(v) => format_float(*v, float_format),
+        AttributeValue::Double(v) => format_float(*v, float_format),
+        AttributeValue::Boolean(v) => format!("{}", v),
+        AttributeValue::BytesHex(bytes) => hex::encode_upper(bytes),
+        AttributeValue::BytesBase64(bytes) => encode_base64(bytes, options),
+    }
+}
+
+/// Encode `bytes` in the configured base64 dialect
+fn encode_base64(bytes: &[u8], options: &DeserializerOptions) -> String {
+    use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+
+    let config = options.base64;
+    let encoded = match (config.alphabet, config.padding) {
+        (Base64Alphabet::Standard, true) => STANDARD.encode(bytes),
+        (Base64Alphabet::Standard, false) => STANDARD_NO_PAD.encode(bytes),
+        (Base64Alphabet::UrlSafe, true) => URL_SAFE.encode(bytes),
+        (Base64Alphabet::UrlSafe, false) => URL_SAFE_NO_PAD.encode(bytes),
+    };
+    match config.wrap {
+        Some(width) if width > 0 && encoded.len() > width => {
+            let newline = match options.line_ending {
+                LineEnding::Lf => "\n",
+                LineEnding::CrLf => "\r\n",
+            };
+            // Base64 output is ASCII, so any byte index is a char boundary
+            encoded
+                .as_bytes()
+                .chunks(width)
+                .map(|line| std::str::from_utf8(line).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(newline)
+        }
+        _ => encoded,
+    }
+}
+
+/// Markup on either side of a run of text, for the `Compact` whitespace policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Boundary {
+    /// A start tag
+    Open,
+    /// An end tag, or the start or end of the document
+    Close,
+    /// Anything else: text, CDATA, comments and the like
+    Content,
+}
+
+impl Boundary {
+    fn of(token: u8) -> Self {
+        match Command::try_from(token) {
+            Ok(Command::StartTag) => Boundary::Open,
+            Ok(Command::EndTag | Command::StartDocument | Command::EndDocument) => Boundary::Close,
+            _ => Boundary::Content,
+        }
+    }
+}
+
+/// Writer wrapper that counts the bytes passing through it
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 /// Binary XML deserializer that converts ABX format to XML
-pub struct BinaryXmlDeserializer<R: Read + Seek, W: Write> {
-    input: FastDataInput<R>,
-    output: W,
+pub struct BinaryXmlDeserializer<R: Read, W: Write, I: Interner = VecInterner> {
+    input: FastDataInput<R, I>,
+    output: CountingWriter<W>,
     collect_policies: bool,
+    /// Attributes of user restrictions, when collecting policies
     policies: Vec<Policy>,
-    restriction_node_offset: u64,
-    already_read_restrictions_user: bool
+    restriction_node_offset: Option<u64>,
+    options: DeserializerOptions,
+    warnings: Vec<Warning>,
+    element_stack: Vec<(String, u64)>,
+    subtree_ranges: Vec<(Vec<String>, Range<u64>)>,
+    version: u8,
+    element_count: usize,
+    attribute_count: usize,
+    attribute_type_counts: BTreeMap<u8, usize>,
+    end_document_seen: bool,
+    /// `(prefix, uri)` bindings declared by each open element, when expanding
+    namespace_scopes: Vec<Vec<(String, String)>>,
+    metadata: Vec<(u64, MetadataKind, String)>,
+    /// Number of top-level elements started
+    root_count: usize,
+    omitted_attributes: Vec<(u64, String, String)>,
+    attribute_hashes: HashMap<String, [u8; 32]>,
+    /// Started mid-stream by `from_offset`: no declaration, and parsing stops
+    /// once the first element closes
+    fragment: bool,
+    /// Stop before starting more than this many elements, see `with_element_limit`
+    element_limit: Option<usize>,
+    /// Input position once converting finished
+    input_bytes: u64,
+    /// Number of warnings already written as inline comments
+    warnings_written: usize,
+    /// Attributes of the first root element, see `root_attributes`
+    root_attributes: Vec<Attribute>,
+    /// The input ended inside a bytes attribute value
+    truncated_attribute: bool,
+    /// Bytes after `END_DOCUMENT`, with the `capture_trailer` option
+    trailer: Vec<u8>,
+    /// Interned references read, with the `record_intern_refs` option
+    intern_ref_sites: Vec<InternRefSite>,
+    /// Merged text not written yet, and whether it is CDATA, see `coalesce_text`
+    pending_text: Option<(bool, String)>,
+    /// What came before the pending text
+    text_preceded_by: Boundary,
+    /// The last token that wasn't text or ignorable whitespace
+    last_boundary: Boundary,
+    /// Depth of the outermost open element that has text in it, which
+    /// indentation must stay out of
+    text_depth: Option<usize>,
+    /// Tokens processed since the last flush, for `FlushPolicy::EveryTokens`
+    tokens_since_flush: usize,
 }
 
 impl<R: Read + Seek, W: Write> BinaryXmlDeserializer<R, W> {
     /// Create a new deserializer with the given reader and writer
-    pub fn new(mut reader: R, output: W, collect_policies: bool) -> Result<Self> {
-        // Check magic header
-        let mut magic = [0u8; 4];
-        reader
-            .read_exact(&mut magic)
-            .map_err(|_| AbxError::ReadError("magic header".to_string()))?;
-
-        if magic != PROTOCOL_MAGIC_VERSION_0 {
-            return Err(AbxError::InvalidMagicHeader {
-                expected: PROTOCOL_MAGIC_VERSION_0,
-                actual: magic,
-            });
-        }
-
-        Ok(Self {
-            input: FastDataInput::new(reader),
+    pub fn new(reader: R, output: W, collect_policies: bool) -> Result<Self> {
+        Self::with_options(
+            reader,
             output,
             collect_policies,
-            policies: Vec::new(),
-            restriction_node_offset: 0,
-            already_read_restrictions_user: false
-        })
+            DeserializerOptions::default(),
+        )
     }
 
-    /// Deserialize the binary XML to text XML
-    pub fn deserialize(&mut self) -> Result<()> {
-        write!(self.output, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    /// Create a new deserializer with explicit rendering options
+    pub fn with_options(
+        reader: R,
+        output: W,
+        collect_policies: bool,
+        options: DeserializerOptions,
+    ) -> Result<Self> {
+        BinaryXmlDeserializer::with_interner(
+            reader,
+            output,
+            collect_policies,
+            options,
+            VecInterner::new(),
+        )
+    }
 
-        while !self.input.is_eof() {
-            match self.process_token() {
-                Ok(should_continue) => {
-                    if !should_continue {
-                        break;
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Warning: Error parsing token: {}", e);
-                    break;
-                }
-            }
-        }
+    /// Create a deserializer that starts at `offset` instead of the header
+    ///
+    /// The magic header isn't read; `version` stands in for its last byte.
+    /// `offset` should be a token boundary, typically the start of a subtree
+    /// from `subtree_byte_range`. No XML declaration is written, and parsing
+    /// stops when the first element started from `offset` ends.
+    ///
+    /// Strings interned before `offset` are unknown to the new deserializer,
+    /// so references to them fail unless the table is seeded with
+    /// `with_interned_strings`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, BinaryXmlDeserializer, DeserializerOptions};
+    /// use std::fs::File;
+    ///
+    /// let path = ["user", "restrictions_user", "restrictions"];
+    /// let range = AbxToXmlConverter::subtree_byte_range(File::open("0.xml").unwrap(), &path)
+    ///     .unwrap()
+    ///     .unwrap();
+    /// let mut output = Vec::new();
+    /// let mut deserializer = BinaryXmlDeserializer::from_offset(
+    ///     File::open("0.xml").unwrap(),
+    ///     &mut output,
+    ///     range.start,
+    ///     0,
+    ///     DeserializerOptions::default(),
+    /// )
+    /// .unwrap()
+    /// .with_interned_strings(&["user".to_string(), "restrictions_user".to_string()]);
+    /// deserializer.deserialize().unwrap();
+    /// ```
+    pub fn from_offset(
+        mut reader: R,
+        output: W,
+        offset: u64,
+        version: u8,
+        options: DeserializerOptions,
+    ) -> Result<Self> {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut deserializer = Self::from_parts(
+            reader,
+            output,
+            false,
+            options,
+            VecInterner::new(),
+            version,
+            offset,
+        );
+        deserializer.fragment = true;
+        Ok(deserializer)
+    }
+}
 
-        Ok(())
+impl<R: Read, W: Write> BinaryXmlDeserializer<R, W> {
+    /// Create a deserializer for a reader that can't seek, such as a pipe
+    ///
+    /// The input is only ever read forward, nothing is buffered beyond what
+    /// the reader does itself, and offsets count from the start of the
+    /// stream. Policy collection needs the offsets of a seekable input and
+    /// isn't available here; use [`new`](Self::new) for that.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{BinaryXmlDeserializer, DeserializerOptions};
+    /// use std::io;
+    ///
+    /// let mut deserializer = BinaryXmlDeserializer::from_stream(
+    ///     io::stdin().lock(),
+    ///     io::stdout(),
+    ///     DeserializerOptions::default(),
+    /// )
+    /// .unwrap();
+    /// deserializer.deserialize().unwrap();
+    /// ```
+    pub fn from_stream(reader: R, output: W, options: DeserializerOptions) -> Result<Self> {
+        Self::from_stream_at(reader, output, options, 0)
     }
-    /// Process a single token from the binary stream
-    fn process_token(&mut self) -> Result<bool> {
-        let token = self.input.read_byte()?;
-        let command = token & 0x0F;
-        let type_info = token & 0xF0;
 
-        match command {
-            START_DOCUMENT => Ok(true),
+    /// Like `from_stream`, for a stream whose magic header is at offset `start`
+    pub(crate) fn from_stream_at(
+        reader: R,
+        output: W,
+        options: DeserializerOptions,
+        start: u64,
+    ) -> Result<Self> {
+        Self::read_header(reader, output, false, options, VecInterner::new(), start)
+    }
+}
 
-            END_DOCUMENT => Ok(false),
+impl<R: Read + Seek, W: Write, I: Interner> BinaryXmlDeserializer<R, W, I> {
+    /// Create a new deserializer that stores interned strings in `interner`
+    ///
+    /// Pass `&mut interner` to share one pool across several files.
+    pub fn with_interner(
+        mut reader: R,
+        output: W,
+        collect_policies: bool,
+        options: DeserializerOptions,
+        interner: I,
+    ) -> Result<Self> {
+        let start = reader.stream_position()?;
+        Self::read_header(reader, output, collect_policies, options, interner, start)
+    }
+}
 
-            START_TAG => {
-                let tag_name = self.input.read_interned_utf()?;
+impl<R: Read, W: Write, I: Interner> BinaryXmlDeserializer<R, W, I> {
+    /// Read the magic header, which is at offset `start`, and set up the
+    /// deserializer for the tokens after it
+    fn read_header(
+        mut reader: R,
+        output: W,
+        collect_policies: bool,
+        options: DeserializerOptions,
+        interner: I,
+        start: u64,
+    ) -> Result<Self> {
+        let magic = read_magic(&mut reader, &options.accepted_magics)?;
+        Ok(Self::from_parts(
+            reader,
+            output,
+            collect_policies,
+            options,
+            interner,
+            magic[3],
+            start + magic.len() as u64,
+        ))
+    }
 
-                if tag_name == "restrictions_user" {
-                    self.already_read_restrictions_user = true;
-                }
+    fn from_parts(
+        reader: R,
+        output: W,
+        collect_policies: bool,
+        options: DeserializerOptions,
+        interner: I,
+        version: u8,
+        position: u64,
+    ) -> Self {
+        Self {
+            input: FastDataInput::at_position(reader, interner, position)
+                .with_string_encoding(options.string_encoding)
+                .with_endianness(options.endianness)
+                .with_max_interned_strings(options.max_interned_strings)
+                .with_definition_offsets(options.record_interned_definitions)
+                .with_index_validation(options.validate_interned_indices)
+                .with_reference_sites(options.record_intern_refs),
+            output: CountingWriter {
+                inner: output,
+                count: 0,
+            },
+            collect_policies,
+            policies: Vec::new(),
+            restriction_node_offset: None,
+            options,
+            warnings: Vec::new(),
+            element_stack: Vec::new(),
+            subtree_ranges: Vec::new(),
+            version,
+            element_count: 0,
+            attribute_count: 0,
+            attribute_type_counts: BTreeMap::new(),
+            end_document_seen: false,
+            namespace_scopes: Vec::new(),
+            metadata: Vec::new(),
+            root_count: 0,
+            root_attributes: Vec::new(),
+            truncated_attribute: false,
+            trailer: Vec::new(),
+            intern_ref_sites: Vec::new(),
+            pending_text: None,
+            text_preceded_by: Boundary::Close,
+            last_boundary: Boundary::Close,
+            text_depth: None,
+            omitted_attributes: Vec::new(),
+            attribute_hashes: HashMap::new(),
+            fragment: false,
+            element_limit: None,
+            input_bytes: 0,
+            warnings_written: 0,
+            tokens_since_flush: 0,
+        }
+    }
 
-                if tag_name == "restrictions" && self.already_read_restrictions_user {
-                    self.restriction_node_offset = self.input.tell()?;
-                }
+    /// Define `strings` as the first entries of the interned table
+    ///
+    /// For deserializers created with `from_offset`: pass the strings the
+    /// stream interned before the offset, in order.
+    pub fn with_interned_strings(mut self, strings: &[String]) -> Self {
+        for string in strings {
+            self.input.define_interned(string.clone());
+        }
+        self
+    }
 
-                write!(self.output, "<{}", tag_name)?;
+    /// Stop converting once `limit` elements have been written
+    ///
+    /// When the next element would go over the limit, the open elements are
+    /// closed, so the output stays well-formed, and `<!-- truncated -->` is
+    /// appended. The rest of the input isn't read.
+    pub fn with_element_limit(mut self, limit: usize) -> Self {
+        self.element_limit = Some(limit);
+        self
+    }
 
-                // Process attributes
-                while let Ok(pos) = self.input.tell() {
-                    match self.input.read_byte() {
-                        Ok(next_token) => {
-                            if (next_token & 0x0F) == ATTRIBUTE {
-                                self.process_attribute(next_token)?;
-                            } else {
-                                self.input.seek(pos)?;
-                                break;
-                            }
-                        }
-                        Err(_) => {
-                            self.input.seek(pos)?;
-                            break;
-                        }
-                    }
-                }
+    /// Deserialize the binary XML to text XML
+    pub fn deserialize(&mut self) -> Result<()> {
+        self.deserialize_with_handler(&mut NoopHandler)
+    }
 
-                write!(self.output, ">")?;
-                Ok(true)
-            }
+    /// Deserialize the binary XML to text XML, reporting each token to `handler`
+    ///
+    /// Output is written to `W` as each token is decoded; nothing is held back
+    /// beyond whatever buffering `W` does itself. Use the `flush` option to also
+    /// flush `W` at regular points.
+    pub fn deserialize_with_handler(&mut self, handler: &mut dyn TokenHandler) -> Result<()> {
+        self.begin()?;
+        while self.step(handler)? {}
+        Ok(())
+    }
 
-            END_TAG => {
-                let tag_name = self.input.read_interned_utf()?;
-                write!(self.output, "</{}>", tag_name)?;
-                Ok(true)
-            }
+    /// Check the start of the document and write the XML declaration
+    ///
+    /// Called once, before the first `step`.
+    pub(crate) fn begin(&mut self) -> Result<()> {
+        if self.options.strict && !self.fragment {
+            self.check_start_document()?;
+        }
+        if !self.fragment {
+            write!(self.output, "{}", XML_DECLARATION)?;
+        }
+        Ok(())
+    }
 
-            TEXT => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    if !text.is_empty() {
-                        write!(self.output, "{}", encode_xml_entities(&text))?;
+    /// Process the next token, reporting it to `handler`
+    ///
+    /// Returns `false` once the document is finished, after which `step`
+    /// must not be called again.
+    pub(crate) fn step(&mut self, handler: &mut dyn TokenHandler) -> Result<bool> {
+        if self.input.is_eof() {
+            self.end_of_stream(false)?;
+            return Ok(false);
+        }
+        if self
+            .element_limit
+            .is_some_and(|limit| self.element_count >= limit)
+            && self.next_is_start_tag()?
+        {
+            self.truncate()?;
+            self.finish()?;
+            return Ok(false);
+        }
+        match self.process_token(handler) {
+            Ok(should_continue) => {
+                self.check_fail_fast()?;
+                self.write_warning_comments()?;
+                if let FlushPolicy::EveryTokens(n) = self.options.flush {
+                    self.tokens_since_flush += 1;
+                    if self.tokens_since_flush >= n {
+                        self.output.flush()?;
+                        self.tokens_since_flush = 0;
                     }
                 }
-                Ok(true)
-            }
-
-            CDSECT => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    write!(self.output, "<![CDATA[{}]]>", text)?;
+                if !should_continue {
+                    self.end_document_seen = true;
+                    if self.options.capture_trailer {
+                        self.read_trailer()?;
+                    }
+                    self.end_of_stream(false)?;
+                    return Ok(false);
                 }
-                Ok(true)
-            }
-
-            COMMENT => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    write!(self.output, "<!--{}-->", text)?;
+                if self.fragment && self.root_count > 0 && self.element_stack.is_empty() {
+                    self.finish()?;
+                    return Ok(false);
                 }
                 Ok(true)
             }
-
-            PROCESSING_INSTRUCTION => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    write!(self.output, "<?{}?>", text)?;
+            // Structural errors abort; read errors on damaged input are
+            // reported and whatever was converted so far is kept
+            Err(e @ (AbxError::ParseError(_) | AbxError::InternedIndexOutOfRange { .. })) => Err(e),
+            Err(e) => {
+                let offset = self.input.tell().unwrap_or(0);
+                let hint = self.endianness_hint();
+                if self.options.strict {
+                    return Err(AbxError::ParseError(format!(
+                        "{} at offset {}{}",
+                        e, offset, hint
+                    )));
                 }
-                Ok(true)
+                self.warn(offset, format!("Error parsing token: {}{}", e, hint));
+                self.check_fail_fast()?;
+                self.end_of_stream(true)?;
+                Ok(false)
             }
+        }
+    }
 
-            DOCDECL => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    write!(self.output, "<!DOCTYPE {}>", text)?;
-                }
-                Ok(true)
+    /// Wrap up once no more tokens will be read
+    ///
+    /// `stopped_early` is set when a read error ended the token loop.
+    fn end_of_stream(&mut self, stopped_early: bool) -> Result<()> {
+        // The stream ran out cleanly between tokens, but never said it was done.
+        // A single, fully closed root is a complete document that only lacks
+        // the trailer, as in profiles captured mid-write; anything else lost
+        // content.
+        if !self.end_document_seen && !stopped_early {
+            let offset = self.input.tell()?;
+            if self.element_stack.is_empty() && self.root_count == 1 {
+                self.warn(
+                    offset,
+                    "Missing END_DOCUMENT after a complete root element".to_string(),
+                );
+                return self.finish();
             }
-
-            ENTITY_REF => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    write!(self.output, "&{};", text)?;
-                }
-                Ok(true)
+            let message = format!(
+                "Document truncated: stream ended without END_DOCUMENT with {} element(s) open",
+                self.element_stack.len()
+            );
+            if self.options.strict {
+                return Err(AbxError::ParseError(format!(
+                    "{} at offset {}",
+                    message, offset
+                )));
             }
-
-            IGNORABLE_WHITESPACE => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    write!(self.output, "{}", text)?;
-                }
-                Ok(true)
+            self.warn(offset, message);
+            if self.truncated_attribute {
+                self.truncate()?;
             }
+        }
 
-            _ => {
-                eprintln!("Warning: Unknown token: {}", command);
-                Ok(true)
+        self.finish()
+    }
+
+    /// Fail unless the stream opens with `START_DOCUMENT`, as Android always writes
+    ///
+    /// A body that starts with anything else most likely isn't ABX, even if
+    /// the magic happened to match.
+    fn check_start_document(&mut self) -> Result<()> {
+        let offset = self.input.tell()?;
+        match self.input.peek()? {
+            Some(token) if command_of(token) != START_DOCUMENT => {
+                Err(AbxError::ParseError(format!(
+                    "Expected START_DOCUMENT at offset {}, found 0x{:02X}",
+                    offset, token
+                )))
             }
+            _ => Ok(()),
         }
     }
 
-    /// Process an attribute token
-    fn process_attribute(&mut self, token: u8) -> Result<()> {
-        let start_offset = self.input.tell()? as u32 - 1;
-        let type_info = token & 0xF0;
-        let name = self.input.read_interned_utf()?;
-        write!(self.output, " {}=\"", name)?;
+    /// Suggest little-endian when a big-endian read fails before the first
+    /// element is complete
+    ///
+    /// Byte-swapped lengths and references are implausibly large, so such a
+    /// dump fails almost at once.
+    fn endianness_hint(&self) -> &'static str {
+        if self.options.endianness == Endianness::Big && self.element_count <= 1 {
+            " (if the input is byte-swapped, try Endianness::Little)"
+        } else {
+            ""
+        }
+    }
 
-        match type_info {
-            TYPE_STRING => {
-                let value = self.input.read_utf()?;
-                write!(self.output, "{}", encode_xml_entities(&value))?;
-            }
-            TYPE_STRING_INTERNED => {
-                let value = self.input.read_interned_utf()?;
-                write!(self.output, "{}", encode_xml_entities(&value))?;
-            }
-            TYPE_INT => {
-                let value = self.input.read_int()?;
-                write!(self.output, "{}", value)?;
-            }
-            TYPE_INT_HEX => {
-                let value = self.input.read_int()?;
-                write!(self.output, "0x{:X}", value)?;
-            }
-            TYPE_LONG => {
-                let value = self.input.read_long()?;
-                write!(self.output, "{}", value)?;
-            }
-            TYPE_LONG_HEX => {
-                let value = self.input.read_long()?;
-                write!(self.output, "0x{:X}", value)?;
-            }
-            TYPE_FLOAT => {
-                let value = self.input.read_float()?;
-                write!(self.output, "{}", value)?;
-            }
-            TYPE_DOUBLE => {
-                let value = self.input.read_double()?;
-                write!(self.output, "{}", value)?;
-            }
-            TYPE_BOOLEAN_TRUE => {
-                write!(self.output, "true")?;
-            }
-            TYPE_BOOLEAN_FALSE => {
-                write!(self.output, "false")?;
-            }
-            TYPE_BYTES_HEX => {
-                let length = self.input.read_short()?;
-                let bytes = self.input.read_bytes(length)?;
-                write!(self.output, "{}", hex::encode_upper(&bytes))?;
-            }
-            TYPE_BYTES_BASE64 => {
-                let length = self.input.read_short()?;
-                let bytes = self.input.read_bytes(length)?;
-                let encoded = base64::engine::general_purpose::STANDARD.encode(&bytes);
-                write!(self.output, "{}", encoded)?;
-            }
-            _ => {
-                return Err(AbxError::UnknownAttributeType(type_info));
+    /// Read everything after `END_DOCUMENT` into `trailer`
+    fn read_trailer(&mut self) -> Result<()> {
+        loop {
+            let chunk = self.input.read_bytes_partial(u16::MAX)?;
+            let done = chunk.len() < u16::MAX as usize;
+            self.trailer.extend_from_slice(&chunk);
+            if done {
+                return Ok(());
             }
         }
+    }
 
-        let end_offset = self.input.tell()? as u32;
+    /// Whether the next token starts an element, without consuming it
+    fn next_is_start_tag(&mut self) -> Result<bool> {
+        Ok(self
+            .input
+            .peek()?
+            .is_some_and(|token| command_of(token) == START_TAG))
+    }
 
-        if self.collect_policies {
+    /// Close every open element and mark the output as cut short
+    fn truncate(&mut self) -> Result<()> {
+        self.flush_pending_text(Boundary::Close)?;
+        while let Some((name, offset)) = self.element_stack.pop() {
+            let written_name = self.expand_name(&name, false, offset);
+            self.namespace_scopes.pop();
+            write!(self.output, "</{}>", written_name)?;
+        }
+        write!(self.output, "<!-- truncated -->")?;
+        Ok(())
+    }
+
+    /// Turn the first warning into an error under `WarningPolicy::FailFast`
+    fn check_fail_fast(&self) -> Result<()> {
+        if self.options.warning_policy == WarningPolicy::FailFast
+            && let Some(warning) = self.warnings.first()
+        {
+            return Err(AbxError::ParseError(format!(
+                "{} at offset {}",
+                warning.message, warning.offset
+            )));
+        }
+        Ok(())
+    }
+
+    /// Final flush once the token loop is done
+    fn finish(&mut self) -> Result<()> {
+        self.flush_pending_text(Boundary::Close)?;
+        self.input_bytes = self.input.tell()?;
+        self.check_expansion_ratio();
+        self.check_fail_fast()?;
+        self.write_warning_comments()?;
+        if self.options.flush != FlushPolicy::Never {
+            self.output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the warnings recorded since the last call as XML comments, when
+    /// `inline_warning_comments` is set
+    ///
+    /// Only called between tokens, so a comment never lands inside a start tag.
+    fn write_warning_comments(&mut self) -> Result<()> {
+        if !self.options.inline_warning_comments || self.warnings_written == self.warnings.len() {
+            return Ok(());
+        }
+        // Text held back belongs before the comment
+        self.flush_pending_text(Boundary::Content)?;
+        for warning in &self.warnings[self.warnings_written..] {
+            // `--` can't appear inside a comment
+            let message = warning.message.replace("--", "- -");
+            write!(
+                self.output,
+                "<!-- honeycomb: {} (input offset 0x{:X}) -->",
+                message, warning.offset
+            )?;
+        }
+        self.warnings_written = self.warnings.len();
+        Ok(())
+    }
+
+    /// Warn if the output grew more than `max_expansion_ratio` times the input
+    fn check_expansion_ratio(&mut self) {
+        let Some(limit) = self.options.max_expansion_ratio else {
+            return;
+        };
+        let ratio = expansion_ratio(self.input_bytes, self.output.count);
+        if ratio > limit {
+            self.warn(
+                self.input_bytes,
+                format!(
+                    "Output is {:.1}x the size of the input ({} bytes from {}), over the limit of {}x",
+                    ratio, self.output.count, self.input_bytes, limit
+                ),
+            );
+        }
+    }
+
+    /// Process a single token from the binary stream
+    fn process_token(&mut self, handler: &mut dyn TokenHandler) -> Result<bool> {
+        let token = self.input.read_byte()?;
+        if !matches!(
+            Command::try_from(token),
+            Ok(Command::Text | Command::Cdsect | Command::IgnorableWhitespace)
+        ) {
+            self.flush_pending_text(Boundary::of(token))?;
+        }
+        let preceding = self.last_boundary;
+        if !matches!(
+            Command::try_from(token),
+            Ok(Command::Text | Command::Cdsect | Command::IgnorableWhitespace)
+        ) {
+            self.last_boundary = Boundary::of(token);
+        }
+
+        match Command::try_from(token) {
+            Ok(Command::StartDocument) => Ok(true),
+
+            Ok(Command::EndDocument) => Ok(false),
+
+            Ok(Command::StartTag) => {
+                let token_offset = self.input.tell()? - 1;
+                let tag_name = self.read_name(token_offset)?;
+                self.record_intern_refs(InternRefContext::StartTag);
+                if self.element_stack.len() >= self.options.max_depth {
+                    return Err(AbxError::ParseError(format!(
+                        "max nesting depth exceeded ({}) at offset {}",
+                        self.options.max_depth, token_offset
+                    )));
+                }
+                if self.element_stack.is_empty() {
+                    self.root_count += 1;
+                }
+                self.element_stack.push((tag_name.clone(), token_offset));
+                self.element_count += 1;
+
+                if tag_name == "restrictions" && self.restriction_node_offset.is_none() {
+                    let depth = self.element_stack.len();
+                    let parent = depth.checked_sub(2).map(|i| &self.element_stack[i].0);
+                    if parent.is_some_and(|p| p == "restrictions_user") {
+                        self.restriction_node_offset = Some(self.input.tell()?);
+                    }
+                }
+
+                let tag_offset = self.input.tell()?;
+                // The declaration, if any, is all that can precede the root
+                let depth = self.element_stack.len() - 1;
+                if depth > 0 || self.output.count > 0 {
+                    self.write_indent(depth, depth)?;
+                }
+                handler.start_tag(&tag_name)?;
+                // An expanded tag name depends on the element's own declarations,
+                // so it can only be written once the attributes are read
+                let expand_namespaces = self.options.namespaces != NamespaceMode::Preserve;
+                if !expand_namespaces {
+                    let written_name = self.expand_name(&tag_name, false, token_offset);
+                    write!(self.output, "<{}", written_name)?;
+                }
+
+                // Process attributes
+                let mut attributes = Vec::new();
+                while let Ok(Some(next_token)) = self.input.peek() {
+                    if command_of(next_token) != ATTRIBUTE {
+                        break;
+                    }
+                    self.input.read_byte()?;
+                    attributes.extend(self.process_attribute(next_token)?);
+                }
+
+                let attributes =
+                    self.resolve_duplicate_attributes(&tag_name, tag_offset, attributes);
+                if expand_namespaces {
+                    self.push_namespace_scope(&attributes);
+                }
+                let written_name = self.expand_name(&tag_name, false, tag_offset);
+                if expand_namespaces {
+                    write!(self.output, "<{}", written_name)?;
+                }
+                let rendered: Vec<String> = attributes
+                    .iter()
+                    .map(|(name, value)| {
+                        format!(
+                            "{}={}",
+                            self.expand_name(name, true, tag_offset),
+                            quote_attribute_value(value, self.options.quote_style)
+                        )
+                    })
+                    .collect();
+                let separator = if self.should_wrap_attributes(&written_name, &rendered) {
+                    // Each attribute on its own line, aligned under the tag name
+                    match self.options.line_ending {
+                        LineEnding::Lf => "\n ",
+                        LineEnding::CrLf => "\r\n ",
+                    }
+                } else {
+                    " "
+                };
+                self.attribute_count += attributes.len();
+                for (name, value) in &attributes {
+                    handler.attribute(name, value)?;
+                }
+                for attribute in &rendered {
+                    write!(self.output, "{}{}", separator, attribute)?;
+                }
+
+                write!(self.output, ">")?;
+                if self.options.emit_offset_comments {
+                    write!(self.output, "<!-- @0x{:X} -->", token_offset)?;
+                }
+                Ok(true)
+            }
+
+            Ok(Command::EndTag) => {
+                let token_offset = self.input.tell()? - 1;
+                let tag_name = self.read_name(token_offset)?;
+                self.record_intern_refs(InternRefContext::EndTag);
+                // Children were put on lines of their own, so the end tag is too
+                let depth = self.element_stack.len();
+                if preceding == Boundary::Close && depth > 0 {
+                    self.write_indent(depth, depth - 1)?;
+                }
+                self.close_element(&tag_name)?;
+                if self.text_depth.is_some_and(|d| d >= depth) {
+                    self.text_depth = None;
+                }
+                handler.end_tag(&tag_name)?;
+                let written_name = self.expand_name(&tag_name, false, token_offset);
+                self.namespace_scopes.pop();
+                write!(self.output, "</{}>", written_name)?;
+                if self.options.flush == FlushPolicy::TopLevelElement
+                    && self.element_stack.len() <= 1
+                {
+                    self.output.flush()?;
+                }
+                Ok(true)
+            }
+
+            Ok(Command::Text) => {
+                let text = self.read_payload(token)?;
+                handler.text(&text)?;
+                // Whether whitespace can be dropped depends on all of the
+                // text up to the next markup, however it was split
+                if self.options.coalesce_text == CoalesceText::Adjacent || self.compacting() {
+                    self.buffer_text(false, &text)?;
+                } else {
+                    self.write_text(&text)?;
+                }
+                self.last_boundary = Boundary::Content;
+                Ok(true)
+            }
+
+            Ok(Command::Cdsect) => {
+                let text = self.read_payload(token)?;
+                handler.cdata(&text)?;
+                if self.options.coalesce_text == CoalesceText::Adjacent {
+                    self.buffer_text(true, &text)?;
+                } else {
+                    self.flush_pending_text(Boundary::Content)?;
+                    self.mark_text();
+                    write!(self.output, "<![CDATA[{}]]>", text)?;
+                }
+                self.last_boundary = Boundary::Content;
+                Ok(true)
+            }
+
+            Ok(Command::Comment) => {
+                let offset = self.input.tell()? - 1;
+                let text = self.read_payload(token)?;
+                handler.comment(&text)?;
+                if !self.divert_metadata(offset, MetadataKind::Comment, &text) {
+                    write!(self.output, "<!--{}-->", text)?;
+                }
+                Ok(true)
+            }
+
+            Ok(Command::ProcessingInstruction) => {
+                let offset = self.input.tell()? - 1;
+                let text = self.read_payload(token)?;
+                handler.processing_instruction(&text)?;
+                if !self.divert_metadata(offset, MetadataKind::ProcessingInstruction, &text) {
+                    write!(self.output, "<?{}?>", text)?;
+                }
+                Ok(true)
+            }
+
+            Ok(Command::Docdecl) => {
+                let offset = self.input.tell()? - 1;
+                let text = self.read_payload(token)?;
+                handler.docdecl(&text)?;
+                if !self.divert_metadata(offset, MetadataKind::Docdecl, &text) {
+                    write!(self.output, "<!DOCTYPE {}>", text)?;
+                }
+                Ok(true)
+            }
+
+            Ok(Command::EntityRef) => {
+                let offset = self.input.tell()? - 1;
+                let text = self.read_payload(token)?;
+                handler.entity_ref(&text)?;
+                self.mark_text();
+                let replacement = self.expand_entity(&text, offset);
+                match replacement {
+                    Some(replacement) => {
+                        write!(self.output, "{}", encode_xml_entities(&replacement))?
+                    }
+                    None => write!(self.output, "&{};", text)?,
+                }
+                Ok(true)
+            }
+
+            Ok(Command::IgnorableWhitespace) => {
+                let text = self.read_payload(token)?;
+                handler.ignorable_whitespace(&text)?;
+                // Dropped by `Compact` without ending the text around it
+                if !self.compacting() {
+                    self.flush_pending_text(Boundary::Content)?;
+                    self.last_boundary = Boundary::Content;
+                    write!(self.output, "{}", text)?;
+                }
+                Ok(true)
+            }
+
+            Ok(Command::Attribute) => {
+                // Attributes directly after a start tag are consumed there, so
+                // this one belongs to no element
+                let offset = self.input.tell()? - 1;
+                if self.options.strict {
+                    return Err(AbxError::ParseError(format!(
+                        "attribute outside of a start tag at offset {}",
+                        offset
+                    )));
+                }
+                // Still read the name and value, to stay in sync with the
+                // interned table and the token stream
+                let name = self.input.read_interned_utf()?;
+                self.record_intern_refs(InternRefContext::AttributeName);
+                decode_attribute_value(token, &mut self.input)?;
+                self.record_intern_refs(InternRefContext::AttributeValue);
+                self.warn(
+                    offset,
+                    format!(
+                        "Skipped attribute '{}' outside of a start tag at offset {}",
+                        name, offset
+                    ),
+                );
+                Ok(true)
+            }
+
+            Err(_) => {
+                if self.options.strict {
+                    return Err(AbxError::UnknownCommand(command_of(token)));
+                }
+                let offset = self.input.tell()?.saturating_sub(1);
+                self.warn(offset, format!("Unknown token: {}", describe_token(token)));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Process an attribute token, returning its name and unescaped value, or
+    /// `None` if the attribute is left out of the output
+    fn process_attribute(&mut self, token: u8) -> Result<Option<(String, String)>> {
+        let start_offset = self.input.tell()? as u32 - 1;
+        let name = self.read_name(start_offset as u64)?;
+        self.record_intern_refs(InternRefContext::AttributeName);
+        *self
+            .attribute_type_counts
+            .entry(type_of(token))
+            .or_insert(0) += 1;
+
+        let value = match type_of(token) {
+            TYPE_BYTES_HEX | TYPE_BYTES_BASE64 if !self.options.strict => {
+                self.read_bytes_attribute(token, &name, start_offset as u64)?
+            }
+            _ => decode_attribute_value(token, &mut self.input)?,
+        };
+        self.record_intern_refs(InternRefContext::AttributeValue);
+        if let Some(&expected) = self.options.schema.get(&name)
+            && !expected.matches(value.attribute_type())
+        {
+            self.warn(
+                start_offset as u64,
+                format!(
+                    "Attribute '{}' is stored as {}, expected {:?}",
+                    name,
+                    value.attribute_type().name(),
+                    expected
+                ),
+            );
+        }
+        if self.options.hash_attributes {
+            self.record_attribute_hash(&name, &value);
+        }
+        let value = if self.is_redacted(&name) {
+            AttributeValue::String(REDACTED_PLACEHOLDER.to_string())
+        } else {
+            value
+        };
+        if self.root_count == 1 && self.element_stack.len() == 1 {
+            self.root_attributes.push(Attribute {
+                name: name.clone(),
+                value: value.clone(),
+            });
+        }
+        let value = match (self.options.boolean_style, value) {
+            (BooleanStyle::Presence, AttributeValue::Boolean(true)) => Some(String::new()),
+            (BooleanStyle::Presence, AttributeValue::Boolean(false)) => None,
+            (_, value) => Some(render_attribute_value(&value, &self.options)),
+        };
+
+        let end_offset = self.input.tell()? as u32;
+
+        if self.collect_policies && self.in_user_restrictions() {
             self.policies.push(Policy {
-                name,
+                name: name.clone(),
                 start_offset,
                 end_offset,
             });
-            // println!("{:?}", self.policies);
         }
 
-        write!(self.output, "\"")?;
+        match value {
+            Some(value) => Ok(Some((name, value))),
+            None => {
+                let element = self
+                    .element_stack
+                    .last()
+                    .map(|(n, _)| n.clone())
+                    .unwrap_or_default();
+                self.omitted_attributes
+                    .push((start_offset as u64, element, name));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Replacement text of entity `name` from the `entity_map` option
+    ///
+    /// Warns about entities missing from a given map, other than the ones
+    /// every XML parser knows.
+    fn expand_entity(&mut self, name: &str, offset: u64) -> Option<String> {
+        let entities = self.options.entity_map.as_ref()?;
+        if let Some(replacement) = entities.get(name) {
+            return Some(replacement.clone());
+        }
+        if !matches!(name, "amp" | "lt" | "gt" | "quot" | "apos") {
+            self.warn(
+                offset,
+                format!(
+                    "Entity '{}' is not in the entity map, written as a reference",
+                    name
+                ),
+            );
+        }
+        None
+    }
+
+    /// Read a bytes attribute value, keeping what there is if the input ends
+    /// inside it
+    ///
+    /// Captures cut off mid-write often end in a long base64 value; the
+    /// partial value is written with a warning, and the document is closed
+    /// once the input runs out.
+    fn read_bytes_attribute(
+        &mut self,
+        token: u8,
+        name: &str,
+        offset: u64,
+    ) -> Result<AttributeValue> {
+        let length = self.input.read_unsigned_short()?;
+        let bytes = self.input.read_bytes_partial(length)?;
+        if bytes.len() < length as usize {
+            self.warn(
+                offset,
+                format!(
+                    "Attribute '{}' truncated: expected {} bytes, found {}",
+                    name,
+                    length,
+                    bytes.len()
+                ),
+            );
+            self.truncated_attribute = true;
+        }
+        Ok(match type_of(token) {
+            TYPE_BYTES_HEX => AttributeValue::BytesHex(bytes),
+            _ => AttributeValue::BytesBase64(bytes),
+        })
+    }
+
+    /// Whether the `redact` option covers attribute `name` of the open element
+    fn is_redacted(&self, name: &str) -> bool {
+        if self.options.redact.is_empty() {
+            return false;
+        }
+        if self.options.redact.contains(name) {
+            return true;
+        }
+        let path: Vec<&str> = self.element_stack.iter().map(|(n, _)| n.as_str()).collect();
+        self.options
+            .redact
+            .contains(&format!("{}@{}", path.join("/"), name))
+    }
+
+    /// Record the hash of an attribute's value under `path/to/element@name`
+    ///
+    /// Repeats of a key, e.g. from sibling elements with the same name, get
+    /// `#2`, `#3`, ... appended.
+    fn record_attribute_hash(&mut self, name: &str, value: &AttributeValue) {
+        let path: Vec<&str> = self.element_stack.iter().map(|(n, _)| n.as_str()).collect();
+        let key = format!("{}@{}", path.join("/"), name);
+        let mut unique_key = key.clone();
+        let mut occurrence = 1;
+        while self.attribute_hashes.contains_key(&unique_key) {
+            occurrence += 1;
+            unique_key = format!("{}#{}", key, occurrence);
+        }
+        self.attribute_hashes.insert(unique_key, value_hash(value));
+    }
+
+    /// Write character data, escaped
+    fn write_text(&mut self, text: &str) -> Result<()> {
+        if !text.is_empty() {
+            self.mark_text();
+            write!(self.output, "{}", encode_xml_entities(text))?;
+        }
+        Ok(())
+    }
+
+    /// Note that the current element has text, so nothing inside it is indented
+    fn mark_text(&mut self) {
+        let depth = self.element_stack.len();
+        if self.text_depth.is_none_or(|d| d > depth) {
+            self.text_depth = Some(depth);
+        }
+    }
+
+    /// Start a new line indented `level` steps, when configured, inside the
+    /// element at depth `container` (0 outside the root)
+    ///
+    /// Nothing is written in elements deeper than `max_indent_depth`, or in
+    /// an element that has text, where the whitespace would become part of it.
+    fn write_indent(&mut self, container: usize, level: usize) -> Result<()> {
+        let Some(width) = self.options.indent else {
+            return Ok(());
+        };
+        if self
+            .options
+            .max_indent_depth
+            .is_some_and(|max| container > max)
+            || self.text_depth.is_some_and(|d| d <= container)
+        {
+            return Ok(());
+        }
+        let newline = match self.options.line_ending {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        };
+        write!(self.output, "{}{:2$}", newline, "", level * width)?;
+        Ok(())
+    }
+
+    /// Add to the merged text, writing what was pending first if it was of
+    /// the other kind
+    fn buffer_text(&mut self, is_cdata: bool, text: &str) -> Result<()> {
+        if self
+            .pending_text
+            .as_ref()
+            .is_some_and(|(pending_cdata, _)| *pending_cdata != is_cdata)
+        {
+            self.flush_pending_text(Boundary::Content)?;
+        }
+        if self.pending_text.is_none() {
+            self.text_preceded_by = self.last_boundary;
+        }
+        self.pending_text
+            .get_or_insert_with(|| (is_cdata, String::new()))
+            .1
+            .push_str(text);
+        Ok(())
+    }
+
+    /// Whether stored whitespace between tags is dropped, either by the
+    /// `Compact` policy or to make way for indentation
+    fn compacting(&self) -> bool {
+        self.options.whitespace == WhitespacePolicy::Compact || self.options.indent.is_some()
+    }
+
+    /// Write the merged text held back by `buffer_text`, given what follows it
+    ///
+    /// With the `Compact` policy, whitespace-only text between two tags is
+    /// dropped, unless it is all there is inside an element.
+    fn flush_pending_text(&mut self, next: Boundary) -> Result<()> {
+        match self.pending_text.take() {
+            Some((false, text)) => {
+                let before = self.text_preceded_by;
+                let droppable = self.compacting()
+                    && before != Boundary::Content
+                    && next != Boundary::Content
+                    && !(before == Boundary::Open && next == Boundary::Close)
+                    && text.chars().all(|c| matches!(c, ' ' | '\t' | '\r' | '\n'));
+                if droppable {
+                    // As if the whitespace had never been there
+                    self.last_boundary = before;
+                    return Ok(());
+                }
+                self.write_text(&text)
+            }
+            Some((true, text)) => {
+                // `]]>` can't appear in a section; end it after `]]` and
+                // start another for the `>`
+                let text = text.replace("]]>", "]]]]><![CDATA[>");
+                write!(self.output, "<![CDATA[{}]]>", text)?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Tag the references just read with where they appeared
+    fn record_intern_refs(&mut self, context: InternRefContext) {
+        if !self.options.record_intern_refs {
+            return;
+        }
+        for (offset, index) in self.input.take_reference_sites() {
+            self.intern_ref_sites.push(InternRefSite {
+                offset,
+                index,
+                context,
+            });
+        }
+    }
+
+    /// Read the string payload of a text-like token (TEXT, CDSECT, COMMENT, ...),
+    /// see `FastDataInput::read_token_payload`
+    fn read_payload(&mut self, token: u8) -> Result<String> {
+        let text = self.input.read_token_payload(token)?;
+        if type_of(token) == TYPE_STRING_INTERNED {
+            self.record_intern_refs(InternRefContext::Text);
+        }
+        Ok(text)
+    }
+
+    /// Read the interned name of the token at `offset` and check it
+    ///
+    /// Names are checked as they are interned: a misaligned read tends to
+    /// define a nonsense string or land on an interned attribute value, and
+    /// either is caught here rather than written as a plausible tag.
+    fn read_name(&mut self, offset: u64) -> Result<String> {
+        let interned_before = self.input.interned_count();
+        let name = self.input.read_interned_utf()?;
+        let newly_interned = self.input.interned_count() > interned_before;
+        self.check_name(name, offset, newly_interned)
+    }
+
+    /// Make sure a tag or attribute name can be written as an XML `Name`
+    ///
+    /// Invalid names are an error in strict mode and are otherwise sanitized
+    /// with a warning, so the output stays parseable.
+    fn check_name(&mut self, name: String, offset: u64, newly_interned: bool) -> Result<String> {
+        if is_xml_name(&name) {
+            return Ok(name);
+        }
+        // A stored invalid name is unusual; a newly defined one usually
+        // means the stream is being read out of step
+        let hint = if newly_interned {
+            " (new interned string; the stream may be misaligned)"
+        } else {
+            ""
+        };
+        if self.options.strict {
+            return Err(AbxError::ParseError(format!(
+                "Invalid XML name {:?} at offset {}{}",
+                name, offset, hint
+            )));
+        }
+        let sanitized = sanitize_xml_name(&name);
+        self.warn(
+            offset,
+            format!(
+                "Invalid XML name {:?} written as \"{}\"{}",
+                name, sanitized, hint
+            ),
+        );
+        Ok(sanitized)
+    }
+
+    /// Record the `xmlns`/`xmlns:prefix` declarations of a start tag
+    fn push_namespace_scope(&mut self, attributes: &[(String, String)]) {
+        let bindings = attributes
+            .iter()
+            .filter_map(|(name, uri)| {
+                let prefix = match name.strip_prefix("xmlns") {
+                    Some("") => "",
+                    Some(rest) => rest.strip_prefix(':')?,
+                    None => return None,
+                };
+                Some((prefix.to_string(), uri.clone()))
+            })
+            .collect();
+        self.namespace_scopes.push(bindings);
+    }
+
+    /// Rewrite `prefix:local` as `{uri}local` when namespace expansion is on
+    ///
+    /// Unprefixed elements take the default namespace; unprefixed attributes
+    /// and the `xmlns` declarations themselves are never in a namespace.
+    /// Names with an undeclared prefix are written as stored, with a warning.
+    fn expand_name(&mut self, name: &str, is_attribute: bool, offset: u64) -> String {
+        let renamed = self.options.rename.get(name).cloned();
+        let name = renamed.as_deref().unwrap_or(name);
+        if self.options.namespaces == NamespaceMode::Preserve {
+            return name.to_string();
+        }
+        let (prefix, local) = match name.split_once(':') {
+            Some(("xmlns", _)) => return name.to_string(),
+            Some((prefix, local)) => (prefix, local),
+            None if is_attribute || name == "xmlns" => return name.to_string(),
+            None => ("", name),
+        };
+        let uri = match prefix {
+            "xml" => Some("http://www.w3.org/XML/1998/namespace"),
+            _ => self
+                .namespace_scopes
+                .iter()
+                .rev()
+                .flatten()
+                .find(|(p, _)| p == prefix)
+                .map(|(_, uri)| uri.as_str()),
+        };
+        match uri {
+            // An empty default namespace undeclares it
+            Some("") => local.to_string(),
+            Some(uri) => format!("{{{}}}{}", uri, local),
+            None if prefix.is_empty() => name.to_string(),
+            None => {
+                self.warn(
+                    offset,
+                    format!("Undeclared namespace prefix \"{}\" in {}", prefix, name),
+                );
+                name.to_string()
+            }
+        }
+    }
+
+    /// Pop the element stack at an `END_TAG`, recording the subtree's byte range
+    fn close_element(&mut self, tag_name: &str) -> Result<()> {
+        let end_offset = self.input.tell()?;
+        let Some((name, start_offset)) = self.element_stack.pop() else {
+            self.warn(end_offset, format!("Unmatched end tag </{}>", tag_name));
+            return Ok(());
+        };
+
+        if name != tag_name {
+            self.warn(
+                end_offset,
+                format!("End tag </{}> closes <{}>", tag_name, name),
+            );
+        }
+
+        if self.collect_policies {
+            let mut path: Vec<String> = self.element_stack.iter().map(|(n, _)| n.clone()).collect();
+            path.push(name);
+            self.subtree_ranges.push((path, start_offset..end_offset));
+        }
         Ok(())
     }
 
+    /// Whether a start tag's attributes should be written one per line
+    fn should_wrap_attributes(&self, tag_name: &str, rendered: &[String]) -> bool {
+        match self.options.attribute_wrap {
+            AttributeWrap::Never => false,
+            AttributeWrap::MoreThan(count) => rendered.len() > count,
+            AttributeWrap::Width(width) => {
+                // "<" + name + " attr"... + ">"
+                let line_len =
+                    tag_name.len() + 2 + rendered.iter().map(|a| a.len() + 1).sum::<usize>();
+                rendered.len() > 1 && line_len > width
+            }
+        }
+    }
+
+    /// Apply the configured duplicate attribute policy to a start tag's attributes
+    fn resolve_duplicate_attributes(
+        &mut self,
+        tag_name: &str,
+        tag_offset: u64,
+        attributes: Vec<(String, String)>,
+    ) -> Vec<(String, String)> {
+        let policy = self.options.duplicate_attributes;
+        if policy == DuplicateAttributePolicy::Keep {
+            return attributes;
+        }
+
+        // Names written so far plus every stored name, so a renamed duplicate
+        // can't collide with an attribute that comes later in the tag
+        let mut taken: HashSet<String> = attributes.iter().map(|(n, _)| n.clone()).collect();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut next_suffix: HashMap<String, usize> = HashMap::new();
+        let mut resolved: Vec<(String, String)> = Vec::with_capacity(attributes.len());
+
+        for (name, value) in attributes {
+            let Some(&position) = positions.get(&name) else {
+                positions.insert(name.clone(), resolved.len());
+                resolved.push((name, value));
+                continue;
+            };
+
+            self.warn(
+                tag_offset,
+                format!("Duplicate attribute \"{}\" on <{}>", name, tag_name),
+            );
+            match policy {
+                DuplicateAttributePolicy::Keep | DuplicateAttributePolicy::First => {}
+                DuplicateAttributePolicy::RenameSuffix => {
+                    let suffix = next_suffix.entry(name.clone()).or_insert(2);
+                    let mut renamed = format!("{}_{}", name, suffix);
+                    while taken.contains(&renamed) {
+                        *suffix += 1;
+                        renamed = format!("{}_{}", name, suffix);
+                    }
+                    *suffix += 1;
+                    taken.insert(renamed.clone());
+                    resolved.push((renamed, value));
+                }
+                DuplicateAttributePolicy::Last => resolved[position].1 = value,
+            }
+        }
+
+        resolved
+    }
+
+    /// Collect a comment, PI or doctype instead of writing it, if configured
+    ///
+    /// Returns whether the token was diverted.
+    fn divert_metadata(&mut self, offset: u64, kind: MetadataKind, text: &str) -> bool {
+        if !self.options.collect_metadata {
+            return false;
+        }
+        self.metadata.push((offset, kind, text.to_string()));
+        true
+    }
+
+    /// Record a non-fatal problem
+    fn warn(&mut self, offset: u64, message: String) {
+        let warning = Warning { offset, message };
+        if let Some(callback) = &self.options.on_warning {
+            (callback.0)(&warning);
+        }
+        self.warnings.push(warning);
+    }
+
+    /// Number of bytes of XML written to the output so far
+    pub fn output_bytes_written(&self) -> u64 {
+        self.output.count
+    }
+
+    /// The policies set in the profile, in document order
+    ///
+    /// Policies are the attributes of a `<restrictions>` element directly
+    /// inside `<restrictions_user>`; other attributes aren't collected.
     pub fn get_policies(&self) -> &[Policy] {
         &self.policies
     }
 
-    pub fn get_restriction_node_offset(&self) -> &u64 {
-        &self.restriction_node_offset
+    /// The policies set in the profile, sorted by name without duplicates
+    ///
+    /// A policy set more than once keeps the offsets of its first occurrence.
+    pub fn get_restrictions(&self) -> Vec<Policy> {
+        let mut restrictions = self.policies.clone();
+        // Stable, so equal names stay in document order
+        restrictions.sort_by(|a, b| a.name.cmp(&b.name));
+        restrictions.dedup_by(|a, b| a.name == b.name);
+        restrictions
+    }
+
+    /// Whether the innermost open element is `<restrictions_user><restrictions>`
+    fn in_user_restrictions(&self) -> bool {
+        matches!(
+            self.element_stack.as_slice(),
+            [.., (parent, _), (element, _)] if parent == "restrictions_user" && element == "restrictions"
+        )
+    }
+
+    /// Offset just past the name of the first `<restrictions>` start tag
+    /// directly inside `<restrictions_user>`, where a new attribute can go
+    ///
+    /// `None` if there is no such element.
+    pub fn get_restriction_node_offset(&self) -> Option<u64> {
+        self.restriction_node_offset
+    }
+
+    /// Byte range of the first element whose path from the root matches `name_path`
+    ///
+    /// The range runs from the element's `START_TAG` token to just past its
+    /// matching `END_TAG`, so it can be drained or spliced as a whole. Ranges
+    /// are only recorded when the deserializer collects policies.
+    pub fn subtree_byte_range(&self, name_path: &[&str]) -> Option<Range<u64>> {
+        self.subtree_ranges
+            .iter()
+            .filter(|(path, _)| {
+                path.iter()
+                    .map(String::as_str)
+                    .eq(name_path.iter().copied())
+            })
+            .min_by_key(|(_, range)| range.start)
+            .map(|(_, range)| range.clone())
+    }
+
+    /// Warnings recorded during deserialization
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Comments, processing instructions and doctypes collected instead of
+    /// written, as `(token offset, kind, content)`
+    pub fn metadata(&self) -> &[(u64, MetadataKind, String)] {
+        &self.metadata
+    }
+
+    /// Attributes of the root element, in document order
+    ///
+    /// Filled in as the root's start tag is read, so this is complete once
+    /// the first child or the root's end has been reached. Android keeps
+    /// metadata such as the schema `version` here. Redacted values are
+    /// replaced as in the output.
+    pub fn root_attributes(&self) -> &[Attribute] {
+        &self.root_attributes
+    }
+
+    /// Attributes left out of the output, as `(token offset, element, attribute)`
+    ///
+    /// With [`BooleanStyle::Presence`] these are the `false` booleans.
+    pub fn omitted_attributes(&self) -> &[(u64, String, String)] {
+        &self.omitted_attributes
+    }
+
+    /// SHA-256 of each attribute's value, keyed by `path/to/element@name`
+    ///
+    /// Only filled in with the `hash_attributes` option. Values are hashed in
+    /// a canonical form, so e.g. `TYPE_INT` and `TYPE_INT_HEX` encodings of the
+    /// same number hash the same, independent of float formatting options.
+    pub fn attribute_hashes(&self) -> &HashMap<String, [u8; 32]> {
+        &self.attribute_hashes
+    }
+
+    /// Whether the stream was terminated by an `END_DOCUMENT` token
+    pub fn end_document_seen(&self) -> bool {
+        self.end_document_seen
+    }
+
+    /// ABX format version, taken from the last byte of the magic header
+    ///
+    /// With custom `accepted_magics` this tells which of them the input used.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Input bytes converted, header included, once `deserialize` returns
+    pub fn input_bytes_consumed(&self) -> u64 {
+        self.input_bytes
+    }
+
+    /// Number of elements started so far
+    pub fn element_count(&self) -> usize {
+        self.element_count
+    }
+
+    /// Number of strings the input has interned so far
+    pub fn interned_string_count(&self) -> usize {
+        self.input.interned_count()
+    }
+
+    /// Every reference to an earlier interned string, in document order
+    ///
+    /// Only filled in with the `record_intern_refs` option. Definitions
+    /// (`0xFFFF` followed by the string) aren't references; see
+    /// `interned_string_definitions` for those. Together they are what a
+    /// tool needs to renumber the table after removing a definition.
+    pub fn intern_ref_sites(&self) -> &[InternRefSite] {
+        &self.intern_ref_sites
+    }
+
+    /// Bytes found after `END_DOCUMENT`, with the `capture_trailer` option
+    ///
+    /// Android writes nothing after `END_DOCUMENT`, so this is empty for
+    /// profiles it wrote. The byte five from the end of a user profile that
+    /// changes when a policy is removed isn't a trailer either: it is the low
+    /// byte of the interned index in the `END_TAG` before `</user>`, which
+    /// shifts when a removed policy defined a string.
+    pub fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+
+    /// Where each interned string was defined in the input, as `(offset, string)`
+    ///
+    /// Only filled in with the `record_interned_definitions` option.
+    pub fn interned_string_definitions(&self) -> &[(u64, String)] {
+        self.input.interned_string_definitions()
+    }
+
+    /// Number of attributes written so far
+    pub fn attribute_count(&self) -> usize {
+        self.attribute_count
+    }
+
+    /// How many attributes of each raw type byte (`TYPE_*`) were encountered,
+    /// including unknown types
+    pub fn attribute_type_counts(&self) -> &BTreeMap<u8, usize> {
+        &self.attribute_type_counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+    use std::io::Cursor;
+
+    /// Build a single-element ABX document with the given boolean attributes
+    fn element_with_attributes(tag: &str, attributes: &[&str]) -> Vec<u8> {
+        let mut fixture = AbxFixture::new();
+        fixture.start_tag(tag);
+        for name in attributes {
+            fixture.attribute_bool(name, true);
+        }
+        fixture.end_tag(tag).build()
+    }
+
+    fn convert_with(data: &[u8], options: DeserializerOptions) -> (String, Vec<Warning>) {
+        let mut output = Vec::new();
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(data), &mut output, false, options)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+        let warnings = deserializer.warnings().to_vec();
+        (String::from_utf8(output).unwrap(), warnings)
+    }
+
+    #[test]
+    fn test_compact_whitespace() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .raw(&[crate::IGNORABLE_WHITESPACE | TYPE_STRING, 0x00, 0x03])
+            .raw(b"\n  ")
+            .start_tag("b")
+            .text(" x ")
+            .end_tag("b")
+            .text("\n")
+            .end_tag("a")
+            .build();
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<a>\n  <b> x </b>\n</a>"));
+        let options = DeserializerOptions::new().whitespace(WhitespacePolicy::Compact);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<a><b> x </b></a>"));
+    }
+
+    #[test]
+    fn test_indent() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .text("\n")
+            .start_tag("b")
+            .start_tag("c")
+            .end_tag("c")
+            .end_tag("b")
+            .start_tag("p")
+            .text("x")
+            .start_tag("c")
+            .end_tag("c")
+            .end_tag("p")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new().indent(2));
+        assert_eq!(
+            xml,
+            format!(
+                "{}\n<a>\n  <b>\n    <c></c>\n  </b>\n  <p>x<c></c></p>\n  <b></b>\n</a>",
+                XML_DECLARATION
+            )
+        );
+
+        let options = DeserializerOptions::new()
+            .indent(1)
+            .max_indent_depth(1)
+            .line_ending(LineEnding::CrLf);
+        let (xml, _) = convert_with(&data, options);
+        assert!(
+            xml.ends_with("?>\r\n<a>\r\n <b><c></c></b>\r\n <p>x<c></c></p>\r\n <b></b>\r\n</a>")
+        );
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new().max_indent_depth(1));
+        assert!(xml.ends_with("?><a>\n<b><c></c></b><p>x<c></c></p><b></b></a>"));
+    }
+
+    #[test]
+    fn test_compact_whitespace_keeps_significant_text() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .text("\n")
+            .start_tag("name")
+            .text(" ")
+            .end_tag("name")
+            .text("\n ")
+            .start_tag("p")
+            .text("x")
+            .text(" ")
+            .start_tag("b")
+            .end_tag("b")
+            .text(" ")
+            .text(" ")
+            .raw(&[crate::COMMENT | TYPE_STRING, 0x00, 0x01, b'c'])
+            .end_tag("p")
+            .text("\n")
+            .end_tag("a")
+            .build();
+
+        let options = DeserializerOptions::new().whitespace(WhitespacePolicy::Compact);
+        let (xml, _) = convert_with(&data, options);
+        assert!(
+            xml.ends_with("<a><name> </name><p>x <b></b>  <!--c--></p></a>"),
+            "{}",
+            xml
+        );
+    }
+
+    #[test]
+    fn test_quote_style() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_string("x", "it's \"q\" & <b>")
+            .end_tag("a")
+            .build();
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<a x=\"it&apos;s &quot;q&quot; &amp; &lt;b&gt;\"></a>"));
+        let options = DeserializerOptions::new().quote_style(QuoteStyle::Single);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<a x='it&apos;s \"q\" &amp; &lt;b&gt;'></a>"));
+    }
+
+    #[test]
+    fn test_attribute_wrap() {
+        let data = element_with_attributes("restrictions", &["a", "b", "c"]);
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<restrictions a=\"true\" b=\"true\" c=\"true\"></restrictions>"));
+
+        let options = DeserializerOptions::new().attribute_wrap(AttributeWrap::MoreThan(2));
+        let (xml, _) = convert_with(&data, options);
+        assert!(
+            xml.ends_with("<restrictions\n a=\"true\"\n b=\"true\"\n c=\"true\"></restrictions>")
+        );
+
+        let options = DeserializerOptions::new()
+            .attribute_wrap(AttributeWrap::MoreThan(2))
+            .line_ending(LineEnding::CrLf);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with(
+            "<restrictions\r\n a=\"true\"\r\n b=\"true\"\r\n c=\"true\"></restrictions>"
+        ));
+
+        let options = DeserializerOptions::new().attribute_wrap(AttributeWrap::MoreThan(3));
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<restrictions a=\"true\" b=\"true\" c=\"true\"></restrictions>"));
+
+        // `<restrictions a="true" b="true" c="true">` is 41 columns wide
+        let options = DeserializerOptions::new().attribute_wrap(AttributeWrap::Width(40));
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.contains("<restrictions\n a="));
+        let options = DeserializerOptions::new().attribute_wrap(AttributeWrap::Width(41));
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.contains("<restrictions a="));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut fixture = AbxFixture::new();
+        for _ in 0..5000 {
+            fixture.start_tag("a");
+        }
+        let data = fixture.build_unterminated();
+
+        let mut output = Vec::new();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), &mut output, false).unwrap();
+        match deserializer.deserialize() {
+            Err(AbxError::ParseError(msg)) => assert!(msg.contains("max nesting depth exceeded")),
+            other => panic!("Expected ParseError, got {:?}", other),
+        }
+
+        let mut fixture = AbxFixture::new();
+        fixture
+            .start_tag("a")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a");
+        let data = fixture.build();
+        let options = DeserializerOptions::new().max_depth(2);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<a><b></b></a>"));
+
+        let options = DeserializerOptions::new().max_depth(1);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), Vec::new(), false, options)
+                .unwrap();
+        assert!(deserializer.deserialize().is_err());
+    }
+
+    #[test]
+    fn test_stray_attribute_is_skipped() {
+        let mut fixture = AbxFixture::new();
+        fixture.start_tag("a").text("x");
+        let offset = fixture.offset();
+        let data = fixture
+            .attribute_int("b", 1)
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<a>x<b></b></a>"));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].offset, offset);
+
+        let options = DeserializerOptions::new().strict(true);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), Vec::new(), false, options)
+                .unwrap();
+        match deserializer.deserialize() {
+            Err(AbxError::ParseError(msg)) => assert_eq!(
+                msg,
+                format!("attribute outside of a start tag at offset {}", offset)
+            ),
+            other => panic!("Expected ParseError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_offset_parses_one_subtree() {
+        let mut fixture = AbxFixture::new();
+        fixture.start_tag("a").start_tag("b").end_tag("b");
+        let offset = fixture.offset();
+        let data = fixture
+            .start_tag("b")
+            .start_tag("c")
+            .end_tag("c")
+            .end_tag("b")
+            .start_tag("d")
+            .end_tag("d")
+            .end_tag("a")
+            .build();
+
+        let mut output = Vec::new();
+        let mut deserializer = BinaryXmlDeserializer::from_offset(
+            Cursor::new(&data),
+            &mut output,
+            offset,
+            0,
+            DeserializerOptions::new().strict(true),
+        )
+        .unwrap()
+        .with_interned_strings(&["a".to_string(), "b".to_string()]);
+        deserializer.deserialize().unwrap();
+        assert!(deserializer.warnings().is_empty());
+        assert_eq!(String::from_utf8(output).unwrap(), "<b><c></c></b>");
+
+        // Without the earlier strings the reference to "b" can't resolve
+        let mut deserializer = BinaryXmlDeserializer::from_offset(
+            Cursor::new(&data),
+            Vec::new(),
+            offset,
+            0,
+            DeserializerOptions::new().strict(true),
+        )
+        .unwrap();
+        assert!(deserializer.deserialize().is_err());
+    }
+
+    #[test]
+    fn test_inline_warning_comments() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_bool("x", true)
+            .raw(&[0x0B])
+            .end_tag("a")
+            .build();
+
+        let options = DeserializerOptions::new().inline_warning_comments(true);
+        let (xml, warnings) = convert_with(&data, options);
+        assert_eq!(warnings.len(), 1);
+        assert!(xml.ends_with(
+            "<a x=\"true\"><!-- honeycomb: Unknown token: unknown command 0x0B (input offset 0x11) --></a>"
+        ));
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(!xml.contains("<!--"));
+    }
+
+    #[test]
+    fn test_on_warning_callback() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .raw(&[0x0B])
+            .build_unterminated();
+
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let options = DeserializerOptions::new()
+            .on_warning(move |warning| sink.lock().unwrap().push(warning.clone()));
+        let (_, warnings) = convert_with(&data, options);
+        assert!(!warnings.is_empty());
+        assert_eq!(*seen.lock().unwrap(), warnings);
+    }
+
+    #[test]
+    fn test_fail_fast_on_first_warning() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .end_tag("b")
+            .start_tag("c")
+            .end_tag("c")
+            .build();
+
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<a></b><c></c>"));
+        assert!(!warnings.is_empty());
+
+        let options = DeserializerOptions::new().warning_policy(WarningPolicy::FailFast);
+        let mut output = Vec::new();
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), &mut output, false, options)
+                .unwrap();
+        match deserializer.deserialize() {
+            Err(AbxError::ParseError(msg)) => {
+                assert_eq!(
+                    msg,
+                    format!("{} at offset {}", warnings[0].message, warnings[0].offset)
+                )
+            }
+            other => panic!("Expected ParseError, got {:?}", other),
+        }
+        assert_eq!(deserializer.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_max_interned_strings() {
+        let mut fixture = AbxFixture::new();
+        fixture.start_tag("a").start_tag("b");
+        let offset = fixture.offset();
+        let data = fixture
+            .start_tag("c")
+            .end_tag("c")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new().max_interned_strings(3));
+        assert!(xml.ends_with("<a><b><c></c></b></a>"));
+
+        let options = DeserializerOptions::new().max_interned_strings(2);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), Vec::new(), false, options)
+                .unwrap();
+        match deserializer.deserialize() {
+            Err(AbxError::ParseError(msg)) => assert_eq!(
+                msg,
+                format!(
+                    "interned string limit exceeded (2) at offset {}",
+                    offset + 1
+                )
+            ),
+            other => panic!("Expected ParseError, got {:?}", other),
+        }
+    }
+
+    /// Records the output length at each flush
+    struct FlushRecorder {
+        data: Vec<u8>,
+        flushed_at: Vec<usize>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushed_at.push(self.data.len());
+            Ok(())
+        }
+    }
+
+    fn flushes_with(data: &[u8], flush: FlushPolicy) -> Vec<usize> {
+        let mut recorder = FlushRecorder {
+            data: Vec::new(),
+            flushed_at: Vec::new(),
+        };
+        let options = DeserializerOptions::new().flush(flush);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(data), &mut recorder, false, options)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+        recorder.flushed_at
+    }
+
+    #[test]
+    fn test_flush_policy() {
+        let data = AbxFixture::new()
+            .start_tag("root")
+            .start_tag("a")
+            .end_tag("a")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("root")
+            .build();
+
+        assert!(flushes_with(&data, FlushPolicy::Never).is_empty());
+
+        // Both children of the root, the root itself, then the final flush
+        let prefix = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><root>".len();
+        let flushes = flushes_with(&data, FlushPolicy::TopLevelElement);
+        assert_eq!(flushes[0], prefix + "<a></a>".len());
+        assert_eq!(flushes[1], prefix + "<a></a><b></b>".len());
+        assert_eq!(flushes.len(), 4);
+
+        // 8 tokens including START_DOCUMENT and END_DOCUMENT
+        assert_eq!(flushes_with(&data, FlushPolicy::EveryTokens(3)).len(), 3);
+    }
+
+    #[test]
+    fn test_interned_text_content() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .text_interned("on")
+            .end_tag("a")
+            .start_tag("b")
+            // A reference to "on", interned by the first text
+            .raw(&[crate::CDSECT | TYPE_STRING_INTERNED, 0x00, 0x01])
+            .text_interned("on")
+            .end_tag("b")
+            .build();
+
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(warnings.is_empty());
+        assert!(xml.ends_with("<a>on</a><b><![CDATA[on]]>on</b>"));
+
+        // The other scanners read the same payloads
+        let document = crate::AbxDocument::parse(Cursor::new(&data)).unwrap();
+        assert_eq!(document.nodes.len(), 2);
+        assert_eq!(
+            crate::AbxToXmlConverter::estimate_output_size(Cursor::new(&data)).unwrap(),
+            xml.len() as u64
+        );
+        assert_eq!(
+            crate::AbxToXmlConverter::read_root(Cursor::new(&data))
+                .unwrap()
+                .name,
+            "a"
+        );
+        crate::for_each_policy(Cursor::new(&data), |_| std::ops::ControlFlow::Continue(()))
+            .unwrap();
+        let mut dump = Vec::new();
+        crate::dump_tokens(Cursor::new(&data), &mut dump, true).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert!(dump.contains("[idx=1 -> \"on\"]"), "{}", dump);
+    }
+
+    #[test]
+    fn test_text_token_with_unsupported_type_errors() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .raw(&[crate::COMMENT | crate::TYPE_INT, 0, 0, 0, 1])
+            .end_tag("a")
+            .build();
+
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), Vec::new(), false).unwrap();
+        match deserializer.deserialize() {
+            Err(AbxError::ParseError(msg)) => assert!(msg.contains("Unsupported type 0x60")),
+            other => panic!("Expected ParseError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_strict_mode_fails_on_truncated_input() {
+        let mut data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_string("k", "value")
+            .end_tag("a")
+            .build();
+        data.truncate(data.len() - 6);
+
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<a"));
+        assert_eq!(warnings.len(), 1);
+
+        let options = DeserializerOptions::new().strict(true);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), Vec::new(), false, options)
+                .unwrap();
+        match deserializer.deserialize() {
+            Err(AbxError::ParseError(msg)) => assert!(msg.contains("at offset")),
+            other => panic!("Expected ParseError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_truncation_at_token_boundaries() {
+        let mut fixture = AbxFixture::new();
+        let mut boundaries = vec![fixture.offset()];
+        fixture.start_tag("a");
+        boundaries.push(fixture.offset());
+        fixture.start_tag("b").attribute_bool("x", true);
+        boundaries.push(fixture.offset());
+        fixture.end_tag("b");
+        boundaries.push(fixture.offset());
+        fixture.end_tag("a");
+        boundaries.push(fixture.offset());
+        let data = fixture.build();
+
+        let (_, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(warnings.is_empty());
+
+        // The last boundary is a complete document, see below
+        let complete = boundaries.pop().unwrap();
+        for (open, boundary) in [0, 1, 2, 1].iter().zip(&boundaries) {
+            let truncated = &data[..*boundary as usize];
+            let mut deserializer =
+                BinaryXmlDeserializer::new(Cursor::new(truncated), Vec::new(), false).unwrap();
+            deserializer.deserialize().unwrap();
+            assert!(!deserializer.end_document_seen());
+            let warnings = deserializer.warnings();
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0].message.contains("Document truncated"));
+            assert!(
+                warnings[0]
+                    .message
+                    .contains(&format!("with {} element(s)", open))
+            );
+            assert_eq!(warnings[0].offset, *boundary);
+
+            let options = DeserializerOptions::new().strict(true);
+            let mut deserializer = BinaryXmlDeserializer::with_options(
+                Cursor::new(truncated),
+                Vec::new(),
+                false,
+                options,
+            )
+            .unwrap();
+            assert!(deserializer.deserialize().is_err());
+        }
+
+        let truncated = &data[..complete as usize];
+        let (xml, warnings) = convert_with(truncated, DeserializerOptions::new().strict(true));
+        assert!(xml.ends_with("<a><b x=\"true\"></b></a>"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("Missing END_DOCUMENT"));
+
+        // Two complete roots still don't make a document
+        let two_roots = [truncated, &truncated[5..]].concat();
+        let (_, warnings) = convert_with(&two_roots, DeserializerOptions::new());
+        assert!(warnings[0].message.contains("Document truncated"));
+    }
+
+    #[test]
+    fn test_utf16_strings() {
+        fn utf16(s: &str) -> Vec<u8> {
+            let units: Vec<u16> = s.encode_utf16().collect();
+            let mut data = (units.len() as u16).to_be_bytes().to_vec();
+            data.extend(units.iter().flat_map(|u| u.to_be_bytes()));
+            data
+        }
+        let mut data = crate::PROTOCOL_MAGIC_VERSION_0.to_vec();
+        data.push(crate::START_TAG | crate::TYPE_STRING_INTERNED);
+        data.extend_from_slice(&[0xFF, 0xFF]);
+        data.extend(utf16("élément"));
+        data.push(ATTRIBUTE | TYPE_STRING);
+        data.extend_from_slice(&[0xFF, 0xFF]);
+        data.extend(utf16("name"));
+        data.extend(utf16("日本 😀"));
+        data.push(crate::END_TAG | crate::TYPE_STRING_INTERNED);
+        data.extend_from_slice(&[0x00, 0x00]);
+        data.push(crate::END_DOCUMENT);
+
+        let options = DeserializerOptions::new().string_encoding(StringEncoding::Utf16);
+        let (xml, warnings) = convert_with(&data, options);
+        assert!(warnings.is_empty());
+        assert!(xml.ends_with("<élément name=\"日本 😀\"></élément>"));
+
+        let (_, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(warnings[0].message.contains("invalid UTF-8"));
+    }
+
+    #[test]
+    fn test_accepted_magics() {
+        let mut data = AbxFixture::new().start_tag("a").end_tag("a").build();
+        data[3] = 0x01;
+
+        let error = BinaryXmlDeserializer::new(Cursor::new(&data), Vec::new(), false)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("[41, 42, 58, 00]"));
+
+        let magics = vec![crate::PROTOCOL_MAGIC_VERSION_0, [0x41, 0x42, 0x58, 0x01]];
+        let options = DeserializerOptions::new().accepted_magics(magics);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), Vec::new(), false, options)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+        assert_eq!(deserializer.version(), 1);
+
+        let options = DeserializerOptions::new().accepted_magics(vec![[0; 4]]);
+        match BinaryXmlDeserializer::with_options(Cursor::new(&data), Vec::new(), false, options) {
+            Err(AbxError::InvalidMagicHeader { expected, actual }) => {
+                assert_eq!(expected, vec![[0; 4]]);
+                assert_eq!(actual, [0x41, 0x42, 0x58, 0x01]);
+            }
+            _ => panic!("expected a magic header error"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        assert!(is_xml_name("android:foo"));
+        assert!(is_xml_name("é-1.x"));
+        assert!(!is_xml_name(""));
+        assert!(!is_xml_name("1st"));
+        assert!(!is_xml_name("a b"));
+        assert_eq!(sanitize_xml_name("1st"), "_1st");
+        assert_eq!(sanitize_xml_name("a b<c"), "a_b_c");
+        assert_eq!(sanitize_xml_name(""), "_");
+
+        let data = AbxFixture::new()
+            .start_tag("9 lives")
+            .attribute_bool("a<b", true)
+            .end_tag("9 lives")
+            .build();
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<_9_lives a_b=\"true\"></_9_lives>"));
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[0].offset, 5);
+
+        let options = DeserializerOptions::new().strict(true);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), Vec::new(), false, options)
+                .unwrap();
+        let error = deserializer.deserialize().unwrap_err();
+        assert!(error.to_string().contains("\"9 lives\" at offset 5"));
+        assert!(error.to_string().contains("may be misaligned"));
+        // The end tag refers back to the stored name
+        assert!(!warnings[2].message.contains("misaligned"));
+    }
+
+    #[test]
+    fn test_namespace_expansion() {
+        let android = "http://schemas.android.com/apk/res/android";
+        let data = AbxFixture::new()
+            .start_tag("manifest")
+            .attribute_string("xmlns:android", android)
+            .attribute_string("package", "p")
+            .start_tag("app")
+            .attribute_bool("android:debuggable", true)
+            .attribute_bool("tools:ignore", true)
+            .end_tag("app")
+            .end_tag("manifest")
+            .build();
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.contains("<app android:debuggable=\"true\""));
+
+        let options = DeserializerOptions::new().namespaces(NamespaceMode::Clark);
+        let (xml, warnings) = convert_with(&data, options);
+        assert!(xml.contains(&format!(
+            "<manifest xmlns:android=\"{}\" package=\"p\">",
+            android
+        )));
+        assert!(xml.contains(&format!(
+            "<app {{{}}}debuggable=\"true\" tools:ignore=\"true\"></app></manifest>",
+            android
+        )));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("\"tools\""));
+
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_string("xmlns", "urn:x")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+        let options = DeserializerOptions::new().namespaces(NamespaceMode::Clark);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<{urn:x}a xmlns=\"urn:x\"><{urn:x}b></{urn:x}b></{urn:x}a>"));
+    }
+
+    #[test]
+    fn test_unsigned_short_and_interned_index() {
+        let mut input = FastDataInput::new(Cursor::new(vec![0xFF, 0xFE, 0xFF, 0xFF, 0x80, 0x00]));
+        assert_eq!(input.read_unsigned_short().unwrap(), 0xFFFE);
+        assert_eq!(input.read_interned_index().unwrap(), None);
+        assert_eq!(input.read_interned_index().unwrap(), Some(0x8000));
+    }
+
+    #[test]
+    fn test_peek_does_not_consume() {
+        let mut input = FastDataInput::new(Cursor::new(vec![0x12, 0x34, 0x56]));
+        assert_eq!(input.peek().unwrap(), Some(0x12));
+        assert_eq!(input.peek().unwrap(), Some(0x12));
+        assert_eq!(input.tell().unwrap(), 0);
+        assert_eq!(input.read_unsigned_short().unwrap(), 0x1234);
+        assert_eq!(input.peek().unwrap(), Some(0x56));
+        assert!(!input.is_eof());
+        input.seek(0).unwrap();
+        assert_eq!(input.read_byte().unwrap(), 0x12);
+        input.seek(3).unwrap();
+        assert_eq!(input.peek().unwrap(), None);
+        assert!(input.is_eof());
+    }
+
+    #[test]
+    fn test_conversion_does_not_seek_to_end() {
+        struct NoSeekToEnd<R>(R);
+
+        impl<R: Read> Read for NoSeekToEnd<R> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+
+        impl<R: Seek> Seek for NoSeekToEnd<R> {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                assert!(!matches!(pos, SeekFrom::End(_)), "seeked to the end");
+                self.0.seek(pos)
+            }
+        }
+
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_int("x", 1)
+            .text("body")
+            .end_tag("a")
+            .build();
+        let mut output = Vec::new();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(NoSeekToEnd(Cursor::new(&data)), &mut output, false)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .ends_with("<a x=\"1\">body</a>")
+        );
+        assert_eq!(
+            crate::AbxToXmlConverter::read_root(NoSeekToEnd(Cursor::new(&data)))
+                .unwrap()
+                .attributes
+                .len(),
+            1
+        );
+    }
+
+    /// A stream defining `count` interned strings `s0`, `s1`, ...
+    fn interned_definitions(count: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..count {
+            let s = format!("s{}", i);
+            data.extend_from_slice(&[0xFF, 0xFF]);
+            data.extend_from_slice(&(s.len() as u16).to_be_bytes());
+            data.extend_from_slice(s.as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_interned_index_above_0x8000() {
+        let mut data = interned_definitions(0x8001);
+        data.extend_from_slice(&[0x80, 0x00]);
+        let mut input = FastDataInput::new(Cursor::new(data));
+        for _ in 0..0x8001 {
+            input.read_interned_utf().unwrap();
+        }
+        assert_eq!(input.read_interned_utf().unwrap(), "s32768");
+    }
+
+    #[test]
+    fn test_full_interned_table_stops_growing() {
+        let mut data = interned_definitions(0x10000);
+        data.extend_from_slice(&[0xFF, 0xFE]);
+        let mut input = FastDataInput::new(Cursor::new(data));
+        for _ in 0..0xFFFF {
+            input.read_interned_utf().unwrap();
+        }
+        // Read, but not added to the full table
+        assert_eq!(input.read_interned_utf().unwrap(), "s65535");
+        assert_eq!(input.interned_count(), 0xFFFF);
+        assert_eq!(input.read_interned_utf().unwrap(), "s65534");
+    }
+
+    #[test]
+    fn test_metadata_side_channel() {
+        let mut fixture = AbxFixture::new();
+        fixture.start_tag("a");
+        let comment_offset = fixture.offset();
+        fixture
+            .raw(&[crate::COMMENT | TYPE_STRING, 0, 2, b'h', b'i'])
+            .raw(&[crate::PROCESSING_INSTRUCTION | TYPE_STRING, 0, 1, b'p'])
+            .end_tag("a");
+        let data = fixture.build();
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<a><!--hi--><?p?></a>"));
+
+        let options = DeserializerOptions::new().collect_metadata(true);
+        let mut output = Vec::new();
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), &mut output, false, options)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+        assert_eq!(
+            deserializer.metadata(),
+            [
+                (comment_offset, MetadataKind::Comment, "hi".to_string()),
+                (
+                    comment_offset + 5,
+                    MetadataKind::ProcessingInstruction,
+                    "p".to_string()
+                ),
+            ]
+        );
+        drop(deserializer);
+        assert!(String::from_utf8(output).unwrap().ends_with("<a></a>"));
+    }
+
+    #[test]
+    fn test_presence_only_booleans() {
+        let data = AbxFixture::new()
+            .start_tag("input")
+            .attribute_bool("checked", true)
+            .attribute_bool("disabled", false)
+            .attribute_int("size", 3)
+            .end_tag("input")
+            .build();
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<input checked=\"true\" disabled=\"false\" size=\"3\"></input>"));
+
+        let options = DeserializerOptions::new().boolean_style(BooleanStyle::Presence);
+        let mut output = Vec::new();
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), &mut output, false, options)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+        assert_eq!(
+            deserializer.omitted_attributes(),
+            [(27, "input".to_string(), "disabled".to_string())]
+        );
+        assert_eq!(deserializer.attribute_count(), 2);
+        drop(deserializer);
+        let xml = String::from_utf8(output).unwrap();
+        assert!(xml.ends_with("<input checked=\"\" size=\"3\"></input>"));
+    }
+
+    #[test]
+    fn test_attribute_hashes() {
+        let build = |value: i32| {
+            AbxFixture::new()
+                .start_tag("a")
+                .start_tag("b")
+                .attribute_int("n", value)
+                .attribute_bool("on", true)
+                .end_tag("b")
+                .start_tag("b")
+                .attribute_int("n", 7)
+                .end_tag("b")
+                .end_tag("a")
+                .build()
+        };
+        let hashes = |data: &[u8]| {
+            let options = DeserializerOptions::new().hash_attributes(true);
+            let mut deserializer =
+                BinaryXmlDeserializer::with_options(Cursor::new(data), Vec::new(), false, options)
+                    .unwrap();
+            deserializer.deserialize().unwrap();
+            deserializer.attribute_hashes().clone()
+        };
+
+        let before = hashes(&build(1));
+        let mut keys: Vec<&String> = before.keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["a/b@n", "a/b@n#2", "a/b@on"]);
+        assert_eq!(before["a/b@n"], value_hash(&AttributeValue::IntHex(1)));
+
+        let after = hashes(&build(2));
+        let changed: Vec<&String> = keys
+            .into_iter()
+            .filter(|k| before[*k] != after[*k])
+            .collect();
+        assert_eq!(changed, ["a/b@n"]);
+    }
+
+    #[test]
+    fn test_offset_comments() {
+        let mut fixture = AbxFixture::new();
+        let root = fixture.offset();
+        fixture.start_tag("a");
+        let child = fixture.offset();
+        let data = fixture.start_tag("b").end_tag("b").end_tag("a").build();
+
+        let options = DeserializerOptions::new().emit_offset_comments(true);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with(&format!(
+            "<a><!-- @0x{:X} --><b><!-- @0x{:X} --></b></a>",
+            root, child
+        )));
+    }
+
+    #[test]
+    fn test_subtree_byte_range() {
+        let mut fixture = AbxFixture::new();
+        fixture.start_tag("users").start_tag("user");
+        let start = fixture.offset();
+        fixture.start_tag("restrictions");
+        fixture.attribute_bool("no_camera", true).start_tag("inner");
+        fixture.end_tag("inner").end_tag("restrictions");
+        let end = fixture.offset();
+        fixture.end_tag("user").end_tag("users");
+        let data = fixture.build();
+
+        let mut output = Vec::new();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), &mut output, true).unwrap();
+        deserializer.deserialize().unwrap();
+
+        let path = ["users", "user", "restrictions"];
+        assert_eq!(deserializer.subtree_byte_range(&path), Some(start..end));
+        assert_eq!(deserializer.subtree_byte_range(&["restrictions"]), None);
+
+        let mut patched = data.clone();
+        patched.drain(start as usize..end as usize);
+        let xml = crate::AbxToXmlConverter::convert_bytes(&patched).unwrap();
+        assert!(xml.ends_with("<users><user></user></users>"));
+    }
+
+    #[test]
+    fn test_max_expansion_ratio() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .raw(&[
+                ATTRIBUTE | crate::TYPE_BYTES_HEX,
+                0xFF,
+                0xFF,
+                0x00,
+                0x01,
+                b'x',
+            ])
+            .raw(&64u16.to_be_bytes())
+            .raw(&[0xAB; 64])
+            .end_tag("a")
+            .build();
+
+        let (_, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(warnings.is_empty());
+        let (xml, warnings) =
+            convert_with(&data, DeserializerOptions::new().max_expansion_ratio(1.5));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.ends_with("over the limit of 1.5x"));
+        assert_eq!(warnings[0].offset, data.len() as u64);
+        assert!(expansion_ratio(data.len() as u64, xml.len() as u64) > 1.5);
+    }
+
+    #[test]
+    fn test_output_bytes_written() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_string("x", "<y>")
+            .text("z")
+            .end_tag("a")
+            .build();
+        let mut output = Vec::new();
+        let written = {
+            let mut deserializer =
+                BinaryXmlDeserializer::new(Cursor::new(&data), &mut output, false).unwrap();
+            assert_eq!(deserializer.output_bytes_written(), 0);
+            deserializer.deserialize().unwrap();
+            deserializer.output_bytes_written()
+        };
+        assert_eq!(written, output.len() as u64);
+    }
+
+    #[test]
+    fn test_redact_attributes() {
+        let data = AbxFixture::new()
+            .start_tag("accounts")
+            .start_tag("account")
+            .attribute_string("name", "me@example.com")
+            .attribute_string("token", "secret")
+            .attribute_int("id", 7)
+            .end_tag("account")
+            .end_tag("accounts")
+            .build();
+        let redact = std::collections::HashSet::from([
+            "accounts/account@token".to_string(),
+            "id".to_string(),
+        ]);
+
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new().redact(redact));
+        assert!(warnings.is_empty());
+        assert!(xml.ends_with(
+            "<accounts><account name=\"me@example.com\" token=\"[REDACTED]\" id=\"[REDACTED]\"></account></accounts>"
+        ));
+    }
+
+    #[test]
+    fn test_schema_type_mismatch() {
+        let data = AbxFixture::new()
+            .start_tag("restrictions")
+            .attribute_string("no_sms", "true")
+            .attribute_bool("no_config_wifi", true)
+            .end_tag("restrictions")
+            .build();
+        let schema = HashMap::from([
+            ("no_sms".to_string(), crate::ExpectedType::Boolean),
+            ("no_config_wifi".to_string(), crate::ExpectedType::Boolean),
+        ]);
+
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new().schema(schema));
+        assert!(xml.contains("no_sms=\"true\""));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message,
+            "Attribute 'no_sms' is stored as TYPE_STRING, expected Boolean"
+        );
+        assert_eq!(warnings[0].offset, 5 + 17);
+    }
+
+    #[test]
+    fn test_base64_config() {
+        let encode = |config, bytes: &[u8]| {
+            let options = DeserializerOptions::new().base64(config);
+            render_attribute_value(&AttributeValue::BytesBase64(bytes.to_vec()), &options)
+        };
+        assert_eq!(
+            encode(crate::Base64Config::default(), &[0xFB, 0xFF]),
+            "+/8="
+        );
+        let url_safe = crate::Base64Config {
+            alphabet: Base64Alphabet::UrlSafe,
+            padding: false,
+            wrap: None,
+        };
+        assert_eq!(encode(url_safe, &[0xFB, 0xFF]), "-_8");
+        let wrapped = crate::Base64Config {
+            wrap: Some(4),
+            ..crate::Base64Config::default()
+        };
+        assert_eq!(encode(wrapped, &[0; 7]), "AAAA\nAAAA\nAA==");
+    }
+
+    #[test]
+    fn test_negative_hex_values_render_unsigned() {
+        let options = DeserializerOptions::default();
+        let render = |value| render_attribute_value(&value, &options);
+        assert_eq!(render(AttributeValue::IntHex(-1)), "0xFFFFFFFF");
+        assert_eq!(render(AttributeValue::IntHex(i32::MIN)), "0x80000000");
+        assert_eq!(render(AttributeValue::LongHex(-1)), "0xFFFFFFFFFFFFFFFF");
+        assert_eq!(render(AttributeValue::LongHex(-256)), "0xFFFFFFFFFFFFFF00");
+
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .raw(&[
+                ATTRIBUTE | crate::TYPE_INT_HEX,
+                0xFF,
+                0xFF,
+                0x00,
+                0x01,
+                b'x',
+            ])
+            .raw(&(-2i32).to_be_bytes())
+            .end_tag("a")
+            .build();
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<a x=\"0xFFFFFFFE\"></a>"));
+    }
+
+    #[test]
+    fn test_java_float_string_matches_java() {
+        // Expected values from Float.toString / Double.toString on JDK 19+
+        let floats: [(f32, &str); 12] = [
+            (1.0, "1.0"),
+            (0.1, "0.1"),
+            (100.0, "100.0"),
+            (0.001, "0.001"),
+            (1.0e-4, "1.0E-4"),
+            (9999999.0, "9999999.0"),
+            (1.0e7, "1.0E7"),
+            (123456789.0, "1.2345679E8"),
+            (-2.5, "-2.5"),
+            (-0.0, "-0.0"),
+            (1.4e-45, "1.4E-45"),
+            (f32::MAX, "3.4028235E38"),
+        ];
+        for (value, expected) in floats {
+            assert_eq!(java_float_string(value), expected);
+        }
+
+        let doubles: [(f64, &str); 6] = [
+            (0.0, "0.0"),
+            (12345678912.345, "1.2345678912345E10"),
+            (1234567.125, "1234567.125"),
+            (f64::MAX, "1.7976931348623157E308"),
+            (4.9e-324, "4.9E-324"),
+            (f64::NEG_INFINITY, "-Infinity"),
+        ];
+        for (value, expected) in doubles {
+            assert_eq!(java_float_string(value), expected);
+        }
+        assert_eq!(java_float_string(f64::NAN), "NaN");
+    }
+
+    #[test]
+    fn test_duplicate_attributes_kept_by_default() {
+        let data = element_with_attributes("r", &["a", "a"]);
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<r a=\"true\" a=\"true\"></r>"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_attributes_policies() {
+        let data = element_with_attributes("r", &["a", "b", "a", "a"]);
+
+        let options =
+            DeserializerOptions::new().duplicate_attributes(DuplicateAttributePolicy::RenameSuffix);
+        let (xml, warnings) = convert_with(&data, options);
+        assert!(xml.ends_with("<r a=\"true\" b=\"true\" a_2=\"true\" a_3=\"true\"></r>"));
+        assert_eq!(warnings.len(), 2);
+
+        let options =
+            DeserializerOptions::new().duplicate_attributes(DuplicateAttributePolicy::First);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<r a=\"true\" b=\"true\"></r>"));
+
+        let options =
+            DeserializerOptions::new().duplicate_attributes(DuplicateAttributePolicy::Last);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<r a=\"true\" b=\"true\"></r>"));
+    }
+
+    #[test]
+    fn test_duplicate_attribute_renames_avoid_existing_names() {
+        let data = AbxFixture::new()
+            .start_tag("r")
+            .attribute_int("a", 1)
+            .attribute_int("a", 2)
+            .attribute_int("a_2", 3)
+            .attribute_int("a", 4)
+            .end_tag("r")
+            .build();
+
+        let options =
+            DeserializerOptions::new().duplicate_attributes(DuplicateAttributePolicy::RenameSuffix);
+        let (xml, warnings) = convert_with(&data, options);
+        assert!(xml.ends_with("<r a=\"1\" a_3=\"2\" a_2=\"3\" a_4=\"4\"></r>"));
+        assert_eq!(warnings.len(), 2);
+
+        let options =
+            DeserializerOptions::new().duplicate_attributes(DuplicateAttributePolicy::Last);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<r a=\"4\" a_2=\"3\"></r>"));
+    }
+
+    #[test]
+    fn test_root_attributes() {
+        let data = AbxFixture::new()
+            .start_tag("user")
+            .attribute_int("version", 10)
+            .start_tag("restrictions")
+            .attribute_bool("no_sms", true)
+            .end_tag("restrictions")
+            .end_tag("user")
+            .build();
+
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), io::sink(), false).unwrap();
+        deserializer.deserialize().unwrap();
+        assert_eq!(
+            deserializer.root_attributes(),
+            [Attribute {
+                name: "version".to_string(),
+                value: AttributeValue::Int(10)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_strict_requires_start_document() {
+        let data = AbxFixture::new().build();
+        let body = [&data[..4], &data[5..]].concat();
+
+        let mut deserializer = BinaryXmlDeserializer::with_options(
+            Cursor::new(&body),
+            io::sink(),
+            false,
+            DeserializerOptions::new().strict(true),
+        )
+        .unwrap();
+        let err = deserializer.deserialize().unwrap_err().to_string();
+        assert!(
+            err.contains("Expected START_DOCUMENT at offset 4, found 0x"),
+            "{}",
+            err
+        );
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new().strict(true));
+        assert!(xml.starts_with(XML_DECLARATION));
+    }
+
+    #[test]
+    fn test_interned_string_definitions() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+        let options = DeserializerOptions::new().record_interned_definitions(true);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), io::sink(), false, options)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+        // START_TAG at 5, its reference at 6; the next START_TAG at 11
+        assert_eq!(
+            deserializer.interned_string_definitions(),
+            [(6, "a".to_string()), (12, "b".to_string())]
+        );
+
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), io::sink(), false).unwrap();
+        deserializer.deserialize().unwrap();
+        assert!(deserializer.interned_string_definitions().is_empty());
+    }
+
+    #[test]
+    fn test_numeric_style() {
+        let render = |value, style| {
+            render_attribute_value(&value, &DeserializerOptions::new().numeric_style(style))
+        };
+        assert_eq!(render(AttributeValue::Long(5), NumericStyle::Bare), "5");
+        assert_eq!(
+            render(AttributeValue::Int(5), NumericStyle::JavaLiteral),
+            "5"
+        );
+        assert_eq!(
+            render(AttributeValue::Long(-5), NumericStyle::JavaLiteral),
+            "-5L"
+        );
+        assert_eq!(
+            render(AttributeValue::LongHex(31), NumericStyle::JavaLiteral),
+            "0x1FL"
+        );
+        assert_eq!(
+            render(AttributeValue::IntHex(31), NumericStyle::JavaLiteral),
+            "0x1F"
+        );
+    }
+
+    #[test]
+    fn test_line_ending_in_wrapped_base64() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            // Named by a reference to "a", so the fixture's indices still line up
+            .raw(&[ATTRIBUTE | TYPE_BYTES_BASE64, 0x00, 0x00])
+            .raw(&[0x00, 0x07, 0, 0, 0, 0, 0, 0, 0])
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+        let wrapped = crate::Base64Config {
+            wrap: Some(4),
+            ..crate::Base64Config::default()
+        };
+
+        let (xml, _) = convert_with(&data, DeserializerOptions::new().base64(wrapped));
+        assert!(xml.ends_with("<a a=\"AAAA\nAAAA\nAA==\"><b></b></a>"));
+
+        // Only the wrapped value changes; no newline goes between elements
+        let options = DeserializerOptions::new()
+            .base64(wrapped)
+            .line_ending(LineEnding::CrLf);
+        let (xml, _) = convert_with(&data, options);
+        assert!(xml.ends_with("<a a=\"AAAA\r\nAAAA\r\nAA==\"><b></b></a>"));
+    }
+
+    #[test]
+    fn test_truncated_bytes_attribute() {
+        let mut data = AbxFixture::new()
+            .start_tag("a")
+            .start_tag("b")
+            .raw(&[ATTRIBUTE | TYPE_BYTES_BASE64, 0xFF, 0xFF, 0x00, 0x01])
+            .raw(b"v")
+            .raw(&[0x00, 0x08, 1, 2, 3])
+            .build_unterminated();
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(
+            xml.ends_with("<a><b v=\"AQID\"></b></a><!-- truncated -->"),
+            "{}",
+            xml
+        );
+        assert_eq!(
+            warnings[0].message,
+            "Attribute 'v' truncated: expected 8 bytes, found 3"
+        );
+        assert_eq!(warnings.len(), 2);
+
+        let options = DeserializerOptions::new().strict(true);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), io::sink(), false, options)
+                .unwrap();
+        assert!(deserializer.deserialize().is_err());
+
+        // Nothing of the value at all
+        data.truncate(data.len() - 3);
+        let (xml, _) = convert_with(&data, DeserializerOptions::new());
+        assert!(
+            xml.ends_with("<a><b v=\"\"></b></a><!-- truncated -->"),
+            "{}",
+            xml
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let data = AbxFixture::new()
+            .start_tag("restrictions2")
+            .attribute_bool("no_sms", true)
+            .attribute_bool("kept", true)
+            .end_tag("restrictions2")
+            .build();
+        let rename = HashMap::from([
+            ("restrictions2".to_string(), "restrictions".to_string()),
+            ("no_sms".to_string(), "no_sms_v2".to_string()),
+        ]);
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new().rename(rename));
+        assert!(xml.ends_with("<restrictions no_sms_v2=\"true\" kept=\"true\"></restrictions>"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_little_endian() {
+        let mut data = crate::PROTOCOL_MAGIC_VERSION_0.to_vec();
+        data.extend_from_slice(&[crate::START_DOCUMENT | crate::TYPE_NULL]);
+        data.extend_from_slice(&[
+            START_TAG | TYPE_STRING_INTERNED,
+            0xFF,
+            0xFF,
+            0x01,
+            0x00,
+            b'a',
+        ]);
+        data.extend_from_slice(&[ATTRIBUTE | crate::TYPE_INT, 0xFF, 0xFF, 0x01, 0x00, b'n']);
+        data.extend_from_slice(&258i32.to_le_bytes());
+        data.extend_from_slice(&[crate::END_TAG | TYPE_STRING_INTERNED, 0x00, 0x00]);
+        data.push(crate::END_DOCUMENT | crate::TYPE_NULL);
+
+        let options = DeserializerOptions::new().endianness(Endianness::Little);
+        let (xml, warnings) = convert_with(&data, options);
+        assert!(xml.ends_with("<a n=\"258\"></a>"), "{}", xml);
+        assert!(warnings.is_empty());
+
+        let (_, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(warnings[0].message.ends_with("try Endianness::Little)"));
+    }
+
+    #[test]
+    fn test_capture_trailer() {
+        let mut data = AbxFixture::new().start_tag("a").end_tag("a").build();
+        data.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+        let options = DeserializerOptions::new().capture_trailer(true);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), io::sink(), false, options)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+        assert_eq!(deserializer.trailer(), [0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(deserializer.input_bytes_consumed(), data.len() as u64);
+
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), io::sink(), false).unwrap();
+        deserializer.deserialize().unwrap();
+        assert!(deserializer.trailer().is_empty());
+    }
+
+    #[test]
+    fn test_entity_map() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .raw(&[crate::ENTITY_REF | TYPE_STRING, 0x00, 0x04])
+            .raw(b"corp")
+            .raw(&[crate::ENTITY_REF | TYPE_STRING, 0x00, 0x03])
+            .raw(b"amp")
+            .raw(&[crate::ENTITY_REF | TYPE_STRING, 0x00, 0x04])
+            .raw(b"year")
+            .end_tag("a")
+            .build();
+
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(xml.ends_with("<a>&corp;&amp;&year;</a>"));
+        assert!(warnings.is_empty());
+
+        let entities = HashMap::from([("corp".to_string(), "A & B".to_string())]);
+        let (xml, warnings) = convert_with(&data, DeserializerOptions::new().entity_map(entities));
+        assert!(xml.ends_with("<a>A &amp; B&amp;&year;</a>"), "{}", xml);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.starts_with("Entity 'year'"));
+    }
+
+    #[test]
+    fn test_validate_interned_indices() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .raw(&[crate::END_TAG | TYPE_STRING_INTERNED, 0x00, 0x01])
+            .build();
+
+        let (_, warnings) = convert_with(&data, DeserializerOptions::new());
+        assert!(
+            warnings[0]
+                .message
+                .contains("Invalid interned string index: 1")
+        );
+
+        let options = DeserializerOptions::new().validate_interned_indices(true);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), io::sink(), false, options)
+                .unwrap();
+        match deserializer.deserialize() {
+            Err(AbxError::InternedIndexOutOfRange {
+                index,
+                offset,
+                defined,
+            }) => assert_eq!((index, offset, defined), (1, 12, 1)),
+            other => panic!("Expected InternedIndexOutOfRange, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_coalesce_text() {
+        let cdata = |text: &[u8]| {
+            let mut bytes = vec![crate::CDSECT | TYPE_STRING, 0x00, text.len() as u8];
+            bytes.extend_from_slice(text);
+            bytes
+        };
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .text(" ")
+            .text("x < y")
+            .raw(&cdata(b"ab"))
+            .raw(&cdata(b"c]"))
+            .raw(&cdata(b"]>d"))
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+
+        let options = DeserializerOptions::new().whitespace(WhitespacePolicy::Compact);
+        let (xml, _) = convert_with(&data, options.clone());
+        assert!(
+            xml.ends_with("<a> x &lt; y<![CDATA[ab]]><![CDATA[c]]]><![CDATA[]>d]]><b></b></a>")
+        );
+
+        let (xml, _) = convert_with(&data, options.coalesce_text(CoalesceText::Adjacent));
+        assert!(
+            xml.ends_with("<a> x &lt; y<![CDATA[abc]]]]><![CDATA[>d]]><b></b></a>"),
+            "{}",
+            xml
+        );
+    }
+
+    #[test]
+    fn test_intern_ref_sites() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .start_tag("b")
+            .raw(&[ATTRIBUTE | TYPE_STRING_INTERNED, 0x00, 0x00, 0x00, 0x01])
+            .text_interned("a")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+        let options = DeserializerOptions::new().record_intern_refs(true);
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(Cursor::new(&data), io::sink(), false, options)
+                .unwrap();
+        deserializer.deserialize().unwrap();
+
+        let site = |offset, index, context| InternRefSite {
+            offset,
+            index,
+            context,
+        };
+        assert_eq!(
+            deserializer.intern_ref_sites(),
+            [
+                site(18, 0, InternRefContext::AttributeName),
+                site(20, 1, InternRefContext::AttributeValue),
+                site(23, 0, InternRefContext::Text),
+                site(26, 1, InternRefContext::EndTag),
+                site(29, 0, InternRefContext::EndTag),
+            ]
+        );
     }
 }
@@ -0,0 +1,176 @@
+//! Building ABX documents from scratch
+
+use crate::{AbxDocument, AbxError, Attribute, AttributeValue, Element, Node, Result};
+
+/// Builds a valid ABX byte stream one token at a time
+///
+/// The output has the magic header, `START_DOCUMENT`/`END_DOCUMENT` and
+/// interned names the way Android's serializer writes them. Attributes go on
+/// the innermost open element. Mistakes such as a mismatched end tag are
+/// reported by [`build`](Self::build).
+///
+/// # Examples
+///
+/// ```
+/// use honeycomb::{AbxBuilder, AbxToXmlConverter};
+///
+/// let abx = AbxBuilder::new()
+///     .start_tag("root")
+///     .attribute_int("x", 5)
+///     .end_tag("root")
+///     .build()
+///     .unwrap();
+/// let xml = AbxToXmlConverter::convert_bytes(&abx).unwrap();
+/// assert!(xml.ends_with("<root x=\"5\"></root>"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AbxBuilder {
+    nodes: Vec<Node>,
+    /// Open elements, outermost first
+    stack: Vec<Element>,
+    /// First mistake made, reported by `build`
+    error: Option<String>,
+}
+
+impl AbxBuilder {
+    /// Start an empty document
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open an element
+    pub fn start_tag(mut self, name: &str) -> Self {
+        self.stack.push(Element::new(name));
+        self
+    }
+
+    /// Close the innermost open element, which must be called `name`
+    pub fn end_tag(mut self, name: &str) -> Self {
+        match self.stack.pop() {
+            Some(element) if element.name == name => self.push_node(Node::Element(element)),
+            Some(element) => self.fail(format!("</{}> closes <{}>", name, element.name)),
+            None => self.fail(format!("</{}> without an open element", name)),
+        }
+        self
+    }
+
+    /// Add an attribute with any value type
+    pub fn attribute(mut self, name: &str, value: AttributeValue) -> Self {
+        match self.stack.last_mut() {
+            Some(element) => element.attributes.push(Attribute {
+                name: name.to_string(),
+                value,
+            }),
+            None => self.fail(format!("attribute '{}' outside an element", name)),
+        }
+        self
+    }
+
+    /// Add a `TYPE_STRING` attribute
+    pub fn attribute_string(self, name: &str, value: &str) -> Self {
+        self.attribute(name, AttributeValue::String(value.to_string()))
+    }
+
+    /// Add a `TYPE_INT` attribute
+    pub fn attribute_int(self, name: &str, value: i32) -> Self {
+        self.attribute(name, AttributeValue::Int(value))
+    }
+
+    /// Add a `TYPE_LONG` attribute
+    pub fn attribute_long(self, name: &str, value: i64) -> Self {
+        self.attribute(name, AttributeValue::Long(value))
+    }
+
+    /// Add a `TYPE_BOOLEAN_TRUE` or `TYPE_BOOLEAN_FALSE` attribute
+    pub fn attribute_bool(self, name: &str, value: bool) -> Self {
+        self.attribute(name, AttributeValue::Boolean(value))
+    }
+
+    /// Add character data
+    pub fn text(mut self, text: &str) -> Self {
+        self.push_node(Node::Text(text.to_string()));
+        self
+    }
+
+    /// Add a comment
+    pub fn comment(mut self, text: &str) -> Self {
+        self.push_node(Node::Comment(text.to_string()));
+        self
+    }
+
+    /// Encode the document
+    ///
+    /// Fails on the first mistake made while building, on elements left
+    /// open, and on strings too long for the format.
+    pub fn build(self) -> Result<Vec<u8>> {
+        if let Some(error) = self.error {
+            return Err(AbxError::ParseError(error));
+        }
+        if let Some(element) = self.stack.last() {
+            return Err(AbxError::ParseError(format!(
+                "<{}> is still open",
+                element.name
+            )));
+        }
+        let mut document = AbxDocument::new();
+        document.nodes = self.nodes;
+        document.to_bytes()
+    }
+
+    fn push_node(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.nodes.push(node),
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        self.error.get_or_insert(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+
+    #[test]
+    fn test_builder_matches_fixture() {
+        let built = AbxBuilder::new()
+            .start_tag("user")
+            .attribute_int("id", 10)
+            .start_tag("restrictions")
+            .attribute_bool("no_sms", true)
+            .end_tag("restrictions")
+            .start_tag("name")
+            .text("Owner")
+            .end_tag("name")
+            .end_tag("user")
+            .build()
+            .unwrap();
+        let expected = AbxFixture::new()
+            .start_tag("user")
+            .attribute_int("id", 10)
+            .start_tag("restrictions")
+            .attribute_bool("no_sms", true)
+            .end_tag("restrictions")
+            .start_tag("name")
+            .text("Owner")
+            .end_tag("name")
+            .end_tag("user")
+            .build();
+        assert_eq!(built, expected);
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        let err = AbxBuilder::new()
+            .start_tag("a")
+            .end_tag("b")
+            .build()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Parse error: </b> closes <a>");
+        assert!(AbxBuilder::new().start_tag("a").build().is_err());
+        assert!(AbxBuilder::new().attribute_int("x", 1).build().is_err());
+    }
+}
@@ -1,5 +1,8 @@
-use crate::{AbxError, AbxToXmlConverter, Result};
+use crate::{AbxError, AbxToXmlConverter, DeserializerOptions, Result};
 use clap::{Arg, Command};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 
 pub struct Cli;
 
@@ -7,7 +10,7 @@ impl Cli {
     pub fn build_command() -> Command {
         Command::new("abx2xml")
             .about("Converts Android Binary XML (ABX) to human-readable XML")
-            .long_about("Converts between Android Binary XML and human-readable XML.\n\nWhen invoked with the '-i' argument, the output of a successful conversion will overwrite the original input file. Input can be '-' to use stdin, and output can be '-' to use stdout.")
+            .long_about("Converts between Android Binary XML and human-readable XML.\n\nWhen invoked with the '-i' argument, the output of a successful conversion will overwrite the original input file. Input can be '-' to use stdin, and output can be '-' to use stdout.\n\nWhen the input is a directory, every ABX file under it is converted to the same relative path under the output directory.")
             .arg(
                 Arg::new("in-place")
                     .short('i')
@@ -15,6 +18,18 @@ impl Cli {
                     .help("Overwrite input file with converted output")
                     .action(clap::ArgAction::SetTrue),
             )
+            .arg(
+                Arg::new("strict")
+                    .long("strict")
+                    .help("Fail on any parse error instead of converting as much as possible")
+                    .action(clap::ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("report")
+                    .long("report")
+                    .value_name("FILE")
+                    .help("Write a JSON summary of a directory conversion to FILE"),
+            )
             .arg(
                 Arg::new("input")
                     .help("Input file path (use '-' for stdin)")
@@ -37,6 +52,50 @@ impl Cli {
         let input_path = matches.get_one::<String>("input").unwrap();
         let output_path = matches.get_one::<String>("output");
         let in_place = matches.get_flag("in-place");
+        let strict = matches.get_flag("strict");
+        let report_path = matches.get_one::<String>("report");
+
+        if Path::new(input_path).is_dir() {
+            if in_place {
+                return Err(AbxError::ParseError(
+                    "Cannot use -i option with a directory input".to_string(),
+                ));
+            }
+            let Some(output_dir) = output_path else {
+                return Err(AbxError::ParseError(
+                    "Converting a directory requires an output directory".to_string(),
+                ));
+            };
+            let options = DeserializerOptions::new().strict(strict);
+            let report = AbxToXmlConverter::convert_dir(input_path, output_dir, &options)?;
+            for entry in &report.results {
+                match &entry.result {
+                    Ok(file_report) => {
+                        for warning in &file_report.warnings {
+                            eprintln!("{}: warning: {}", entry.input.display(), warning);
+                        }
+                    }
+                    Err(error) => eprintln!("{}: {}", entry.input.display(), error),
+                }
+            }
+            if let Some(report_path) = report_path {
+                report.write_json(BufWriter::new(File::create(report_path)?))?;
+            }
+            let failed = report.failures().count();
+            if failed > 0 {
+                return Err(AbxError::ParseError(format!(
+                    "{} of {} file(s) failed to convert",
+                    failed,
+                    report.results.len()
+                )));
+            }
+            return Ok(());
+        }
+        if report_path.is_some() {
+            return Err(AbxError::ParseError(
+                "--report is only supported when the input is a directory".to_string(),
+            ));
+        }
 
         if in_place && input_path == "-" {
             return Err(AbxError::ParseError(
@@ -55,12 +114,19 @@ impl Cli {
             }
         };
 
-        match (input_path.as_str(), output_path.as_str()) {
-            ("-", "-") => AbxToXmlConverter::convert_stdin_stdout(),
-            ("-", output) => AbxToXmlConverter::convert_stdin_to_file(output),
-            (input, "-") => AbxToXmlConverter::convert_file_to_stdout(input),
-            (input, output) => AbxToXmlConverter::convert_file(input, output),
+        let options = DeserializerOptions::new().strict(strict);
+        let report = AbxToXmlConverter::convert_paths(input_path, &output_path, &options)?;
+
+        for warning in &report.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        if !report.warnings.is_empty() {
+            eprintln!(
+                "Conversion finished with {} warning(s); output may be incomplete",
+                report.warnings.len()
+            );
         }
+        Ok(())
     }
 }
 
@@ -69,7 +135,6 @@ impl Cli {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use clap::ArgMatches;
 
     #[test]
     fn test_build_command() {
@@ -92,4 +157,71 @@ mod tests {
             panic!("Expected ParseError");
         }
     }
+
+    #[test]
+    fn test_strict_flag() {
+        let matches = Cli::build_command()
+            .try_get_matches_from(vec!["abx2xml", "--strict", "in.abx"])
+            .unwrap();
+        assert!(matches.get_flag("strict"));
+
+        let matches = Cli::build_command()
+            .try_get_matches_from(vec!["abx2xml", "in.abx"])
+            .unwrap();
+        assert!(!matches.get_flag("strict"));
+    }
+
+    #[test]
+    fn test_report_requires_directory_input() {
+        let matches = Cli::build_command()
+            .try_get_matches_from(vec!["abx2xml", "--report", "r.json", "in.abx"])
+            .unwrap();
+        let err = Cli::run_with_matches(matches).unwrap_err();
+        assert!(err.to_string().contains("--report is only supported"));
+    }
+
+    #[test]
+    fn test_directory_input_rejects_in_place() {
+        let dir =
+            std::env::temp_dir().join(format!("honeycomb-cli-in-place-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let matches = Cli::build_command()
+            .try_get_matches_from(vec!["abx2xml", "-i", dir.to_str().unwrap()])
+            .unwrap();
+        let err = Cli::run_with_matches(matches).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Cannot use -i option with a directory")
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directory_failures_are_an_error() {
+        let dir = std::env::temp_dir().join(format!("honeycomb-cli-dir-{}", std::process::id()));
+        let input = dir.join("system");
+        std::fs::create_dir_all(&input).unwrap();
+        let data = crate::test_support::AbxFixture::new()
+            .start_tag("a")
+            .end_tag("a")
+            .build();
+        std::fs::write(input.join("0.xml"), &data).unwrap();
+        std::fs::write(input.join("1.xml"), &data[..data.len() - 3]).unwrap();
+
+        let matches = Cli::build_command()
+            .try_get_matches_from(vec![
+                "abx2xml",
+                "--strict",
+                input.to_str().unwrap(),
+                dir.join("out").to_str().unwrap(),
+            ])
+            .unwrap();
+        let err = Cli::run_with_matches(matches).unwrap_err();
+        assert!(err.to_string().contains("1 of 2 file(s) failed"));
+        assert!(dir.join("out/0.xml").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
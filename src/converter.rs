@@ -1,6 +1,338 @@
-use crate::{BinaryXmlDeserializer, Result, SeekableReader};
-use std::fs::File;
+use crate::binary_xml::{XML_DECLARATION, read_magic, render_attribute_value};
+use crate::events::QuickXmlEvents;
+use crate::ndjson::write_string;
+use crate::{ATTRIBUTE, Attribute, Command, FastDataInput, KNOWN_MAGICS};
+use crate::{BinaryXmlDeserializer, DeserializerOptions, Result, TokenHandler};
+use crate::{MetadataKind, Policy, Warning, command_of, decode_attribute_value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Summary of a completed conversion
+#[derive(Debug, Clone, Default)]
+pub struct ConversionReport {
+    /// Bytes of ABX converted, including the magic header
+    pub input_bytes: u64,
+    /// Bytes of XML written
+    pub output_bytes: u64,
+    /// Number of elements converted
+    pub element_count: usize,
+    /// Number of attributes written
+    pub attribute_count: usize,
+    /// Warnings recorded while converting
+    pub warnings: Vec<Warning>,
+    /// ABX format version from the magic header
+    pub version: u8,
+    /// Comments, PIs and doctypes as `(offset, kind, content)`, when collected
+    /// with `collect_metadata`
+    pub metadata: Vec<(u64, MetadataKind, String)>,
+    /// Attribute value hashes by `path/to/element@name`, when collected with
+    /// `hash_attributes`
+    pub attribute_hashes: HashMap<String, [u8; 32]>,
+    /// IO and interning counters
+    #[cfg(feature = "metrics")]
+    pub metrics: Metrics,
+}
+
+/// Per-file results of a batch conversion, sorted by input path
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// Outcome of every file in the batch
+    pub results: Vec<BatchEntry>,
+}
+
+/// Outcome of converting one file of a batch
+#[derive(Debug)]
+pub struct BatchEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub result: Result<ConversionReport>,
+}
+
+impl ConversionReport {
+    /// Output size over input size, or 0 for empty input
+    pub fn expansion_ratio(&self) -> f64 {
+        crate::binary_xml::expansion_ratio(self.input_bytes, self.output_bytes)
+    }
+}
+
+impl BatchReport {
+    /// Number of files that converted successfully
+    pub fn succeeded(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|entry| entry.result.is_ok())
+            .count()
+    }
+
+    /// Input paths and errors of the files that failed, in path order
+    pub fn failures(&self) -> impl Iterator<Item = (&Path, &crate::AbxError)> {
+        self.results.iter().filter_map(|entry| {
+            entry
+                .result
+                .as_ref()
+                .err()
+                .map(|e| (entry.input.as_path(), e))
+        })
+    }
+
+    /// Write the report as a JSON array with one object per file
+    ///
+    /// Each object has `input`, `output`, `success` and either `error` or the
+    /// `version`, `warnings`, `elements` and `attributes` counts.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut out = String::from("[");
+        for (i, entry) in self.results.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str("\n  {\"input\":");
+            write_string(&mut out, &entry.input.to_string_lossy());
+            out.push_str(",\"output\":");
+            write_string(&mut out, &entry.output.to_string_lossy());
+            match &entry.result {
+                Ok(report) => out.push_str(&format!(
+                    ",\"success\":true,\"version\":{},\"warnings\":{},\"elements\":{},\"attributes\":{}}}",
+                    report.version,
+                    report.warnings.len(),
+                    report.element_count,
+                    report.attribute_count
+                )),
+                Err(error) => {
+                    out.push_str(",\"success\":false,\"error\":");
+                    write_string(&mut out, &error.to_string());
+                    out.push('}');
+                }
+            }
+        }
+        out.push_str(if self.results.is_empty() {
+            "]\n"
+        } else {
+            "\n]\n"
+        });
+        writer.write_all(out.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Name and attributes of a document's root element, from `read_root`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootInfo {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+}
+
+/// One element of a document's tree, from `outline`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineEntry {
+    /// Nesting depth, 0 for the root element
+    pub depth: usize,
+    pub name: String,
+    pub attribute_count: usize,
+}
+
+/// Low-level counters collected during a conversion
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Size of the interned string table at its largest
+    pub peak_interned_strings: usize,
+    /// Bytes read from the input, counting bytes read again after seeking back
+    pub bytes_read: u64,
+    /// Bytes written to the output
+    pub bytes_written: u64,
+    /// Calls to `seek` and `stream_position` on the input
+    pub seeks: u64,
+}
+
+/// Whether two existing paths name the same file
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Canonical form of a path that may not exist yet: its longest existing
+/// ancestor is resolved and the rest appended
+fn resolve_path(path: &Path) -> io::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    loop {
+        match fs::canonicalize(existing) {
+            Ok(resolved) => return Ok(rest.iter().rev().fold(resolved, |p, c| p.join(c))),
+            Err(e) => {
+                let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+                    return Err(e);
+                };
+                rest.push(name);
+                existing = parent;
+            }
+        }
+    }
+}
+
+/// Reader wrapper that counts bytes read and seeks
+#[cfg(feature = "metrics")]
+struct MeteredReader<R: Read> {
+    inner: R,
+    bytes_read: u64,
+    seeks: u64,
+}
+
+#[cfg(feature = "metrics")]
+impl<R: Read> MeteredReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            bytes_read: 0,
+            seeks: 0,
+        }
+    }
+
+    /// Fill in the counters of a finished conversion's report
+    fn record(&self, mut report: ConversionReport) -> ConversionReport {
+        report.metrics.bytes_read = self.bytes_read;
+        report.metrics.seeks = self.seeks;
+        report
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<R: Read> Read for MeteredReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<R: Read + Seek> Seek for MeteredReader<R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.seeks += 1;
+        self.inner.seek(pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        self.seeks += 1;
+        self.inner.stream_position()
+    }
+}
+
+/// Handler that keeps only text and CDATA content
+struct TextCollector {
+    text: String,
+    separate_elements: bool,
+}
+
+impl TextCollector {
+    fn element_boundary(&mut self) {
+        if self.separate_elements && !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+}
+
+impl TokenHandler for TextCollector {
+    fn start_tag(&mut self, _name: &str) -> Result<()> {
+        self.element_boundary();
+        Ok(())
+    }
+
+    fn end_tag(&mut self, _name: &str) -> Result<()> {
+        self.element_boundary();
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<()> {
+        self.text.push_str(text);
+        Ok(())
+    }
+
+    fn cdata(&mut self, text: &str) -> Result<()> {
+        self.text.push_str(text);
+        Ok(())
+    }
+
+    fn entity_ref(&mut self, name: &str) -> Result<()> {
+        match name {
+            "amp" => self.text.push('&'),
+            "lt" => self.text.push('<'),
+            "gt" => self.text.push('>'),
+            "quot" => self.text.push('"'),
+            "apos" => self.text.push('\''),
+            // Undeclared entities have no known replacement text
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Handler that records every element's depth, name and attribute count
+#[derive(Default)]
+struct OutlineCollector {
+    entries: Vec<OutlineEntry>,
+    depth: usize,
+}
+
+impl TokenHandler for OutlineCollector {
+    fn start_tag(&mut self, name: &str) -> Result<()> {
+        self.entries.push(OutlineEntry {
+            depth: self.depth,
+            name: name.to_string(),
+            attribute_count: 0,
+        });
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn attribute(&mut self, _name: &str, _value: &str) -> Result<()> {
+        // Attributes always directly follow their element's start tag
+        if let Some(entry) = self.entries.last_mut() {
+            entry.attribute_count += 1;
+        }
+        Ok(())
+    }
+
+    fn end_tag(&mut self, _name: &str) -> Result<()> {
+        self.depth = self.depth.saturating_sub(1);
+        Ok(())
+    }
+}
+
+/// Handler that writes each attribute as a `path/to/element@name=value` line
+struct FlatWriter<W: Write> {
+    writer: W,
+    path: Vec<String>,
+}
+
+impl<W: Write> TokenHandler for FlatWriter<W> {
+    fn start_tag(&mut self, name: &str) -> Result<()> {
+        self.path.push(name.to_string());
+        Ok(())
+    }
+
+    fn attribute(&mut self, name: &str, value: &str) -> Result<()> {
+        // Keep one line per attribute
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+        writeln!(self.writer, "{}@{}={}", self.path.join("/"), name, value)?;
+        Ok(())
+    }
+
+    fn end_tag(&mut self, _name: &str) -> Result<()> {
+        self.path.pop();
+        Ok(())
+    }
+}
 
 /// High-level converter for ABX to XML conversion
 pub struct AbxToXmlConverter;
@@ -14,7 +346,7 @@ impl AbxToXmlConverter {
     /// # Examples
     ///
     /// ```no_run
-    /// use abx2xml::AbxToXmlConverter;
+    /// use honeycomb::AbxToXmlConverter;
     /// use std::fs::File;
     ///
     /// let input = File::open("input.abx").unwrap();
@@ -22,8 +354,496 @@ impl AbxToXmlConverter {
     /// AbxToXmlConverter::convert(input, output).unwrap();
     /// ```
     pub fn convert<R: Read + Seek, W: Write>(reader: R, writer: W) -> Result<()> {
-        let mut deserializer = BinaryXmlDeserializer::new(reader, writer, false)?;
-        deserializer.deserialize()
+        Self::convert_reporting(reader, writer).map(|_| ())
+    }
+
+    /// Convert ABX from a reader to a writer, returning a summary of the conversion
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    ///
+    /// let input = File::open("input.abx").unwrap();
+    /// let output = File::create("output.xml").unwrap();
+    /// let report = AbxToXmlConverter::convert_reporting(input, output).unwrap();
+    /// println!("{} elements, {} warnings", report.element_count, report.warnings.len());
+    /// ```
+    pub fn convert_reporting<R: Read + Seek, W: Write>(
+        reader: R,
+        writer: W,
+    ) -> Result<ConversionReport> {
+        Self::convert_with_options(reader, writer, &DeserializerOptions::default())
+    }
+
+    /// Convert ABX from a reader to a writer with explicit options
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, DeserializerOptions};
+    /// use std::fs::File;
+    ///
+    /// let input = File::open("input.abx").unwrap();
+    /// let output = File::create("output.xml").unwrap();
+    /// let options = DeserializerOptions::new().strict(true);
+    /// AbxToXmlConverter::convert_with_options(input, output, &options).unwrap();
+    /// ```
+    pub fn convert_with_options<R: Read + Seek, W: Write>(
+        mut reader: R,
+        writer: W,
+        options: &DeserializerOptions,
+    ) -> Result<ConversionReport> {
+        let start = reader.stream_position()?;
+        #[cfg(feature = "metrics")]
+        let mut metered = MeteredReader::new(reader);
+        #[cfg(feature = "metrics")]
+        let reader = &mut metered;
+
+        let report = Self::convert_from(reader, writer, options, start)?;
+        #[cfg(feature = "metrics")]
+        let report = metered.record(report);
+        Ok(report)
+    }
+
+    /// Convert a stream whose magic header is at offset `start`, reading it
+    /// strictly forward
+    fn convert_from<R: Read, W: Write>(
+        reader: R,
+        writer: W,
+        options: &DeserializerOptions,
+        start: u64,
+    ) -> Result<ConversionReport> {
+        let mut writer = writer;
+        let report = {
+            let mut deserializer =
+                BinaryXmlDeserializer::from_stream_at(reader, &mut writer, options.clone(), start)?;
+            deserializer.deserialize()?;
+            ConversionReport {
+                input_bytes: deserializer.input_bytes_consumed(),
+                output_bytes: deserializer.output_bytes_written(),
+                element_count: deserializer.element_count(),
+                attribute_count: deserializer.attribute_count(),
+                warnings: deserializer.warnings().to_vec(),
+                version: deserializer.version(),
+                metadata: deserializer.metadata().to_vec(),
+                attribute_hashes: deserializer.attribute_hashes().clone(),
+                #[cfg(feature = "metrics")]
+                metrics: Metrics {
+                    // The table only ever grows, so its final size is the peak
+                    peak_interned_strings: deserializer.interned_string_count(),
+                    bytes_written: deserializer.output_bytes_written(),
+                    ..Metrics::default()
+                },
+            }
+        };
+        writer.flush()?;
+        Ok(report)
+    }
+
+    /// Convert an ABX document embedded in `reader` at `start..end`
+    ///
+    /// The magic header is expected at `start`, and nothing at or after `end`
+    /// is read, so trailing container data doesn't matter. Offsets in
+    /// warnings are relative to `start`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    /// use std::io;
+    ///
+    /// let backup = File::open("backup.bin").unwrap();
+    /// AbxToXmlConverter::convert_range(backup, 512, 4096, io::stdout()).unwrap();
+    /// ```
+    pub fn convert_range<R: Read + Seek, W: Write>(
+        reader: R,
+        start: u64,
+        end: u64,
+        writer: W,
+    ) -> Result<()> {
+        let window = crate::seekable_reader::RangeReader::new(reader, start, end)?;
+        Self::convert(window, writer)
+    }
+
+    /// Convert ABX from a reader that can't seek
+    ///
+    /// The input is only ever read forward and nothing is held back beyond
+    /// a single peeked byte, so pipes and sockets convert in constant memory.
+    /// Offsets in warnings count from the start of the stream.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, DeserializerOptions};
+    /// use std::io;
+    ///
+    /// let options = DeserializerOptions::default();
+    /// AbxToXmlConverter::convert_stream(io::stdin().lock(), io::stdout(), &options).unwrap();
+    /// ```
+    pub fn convert_stream<R: Read, W: Write>(
+        reader: R,
+        writer: W,
+        options: &DeserializerOptions,
+    ) -> Result<ConversionReport> {
+        #[cfg(feature = "metrics")]
+        let mut metered = MeteredReader::new(reader);
+        #[cfg(feature = "metrics")]
+        let reader = &mut metered;
+
+        let report = Self::convert_from(reader, writer, options, 0)?;
+        #[cfg(feature = "metrics")]
+        let report = metered.record(report);
+        Ok(report)
+    }
+
+    /// Walk ABX from a reader, reporting each token to a handler
+    ///
+    /// No XML is produced; use this when only the decoded events are needed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, Result, TokenHandler};
+    /// use std::fs::File;
+    ///
+    /// struct TagCounter(usize);
+    ///
+    /// impl TokenHandler for TagCounter {
+    ///     fn start_tag(&mut self, _name: &str) -> Result<()> {
+    ///         self.0 += 1;
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let mut counter = TagCounter(0);
+    /// AbxToXmlConverter::parse_with_handler(File::open("input.abx").unwrap(), &mut counter).unwrap();
+    /// ```
+    pub fn parse_with_handler<R: Read + Seek>(
+        reader: R,
+        handler: &mut dyn TokenHandler,
+    ) -> Result<()> {
+        Self::parse_with_handler_and_options(reader, handler, &DeserializerOptions::default())
+    }
+
+    fn parse_with_handler_and_options<R: Read + Seek>(
+        reader: R,
+        handler: &mut dyn TokenHandler,
+        options: &DeserializerOptions,
+    ) -> Result<()> {
+        let mut deserializer =
+            BinaryXmlDeserializer::with_options(reader, io::sink(), false, options.clone())?;
+        deserializer.deserialize_with_handler(handler)
+    }
+
+    /// Write each element at `depth` as one line of JSON
+    ///
+    /// Depth 0 is the root element, 1 its children, and so on. Every element
+    /// at that depth becomes a compact object of the form
+    /// `{"name":"..","attributes":{..},"children":[..]}`, where children are
+    /// nested objects or text strings, followed by `\n`. The writer is
+    /// flushed after each record, so records can be consumed as they come.
+    /// Comments, processing instructions and entity references are left out.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    /// use std::io;
+    ///
+    /// // One line per child of the root
+    /// AbxToXmlConverter::convert_ndjson(File::open("0.xml").unwrap(), io::stdout(), 1).unwrap();
+    /// ```
+    pub fn convert_ndjson<R: Read + Seek, W: Write>(
+        reader: R,
+        writer: W,
+        depth: usize,
+    ) -> Result<()> {
+        let mut handler = crate::ndjson::NdjsonWriter::new(writer, depth);
+        Self::parse_with_handler(reader, &mut handler)
+    }
+
+    /// Write each attribute as a `path/to/element@name=value` line
+    ///
+    /// The path is the element's tag names from the root, joined with `/`.
+    /// Values are rendered as in the XML output but not escaped, except that
+    /// `\`, newlines and carriage returns become `\\`, `\n` and `\r` so every
+    /// attribute stays on one line. Text content is left out. Handy for
+    /// `grep` and other line-based tools.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    /// use std::io;
+    ///
+    /// // user/restrictions_user/restrictions@no_sms=true
+    /// AbxToXmlConverter::convert_flat(File::open("0.xml").unwrap(), io::stdout()).unwrap();
+    /// ```
+    pub fn convert_flat<R: Read + Seek, W: Write>(reader: R, writer: W) -> Result<()> {
+        let mut handler = FlatWriter {
+            writer,
+            path: Vec::new(),
+        };
+        Self::parse_with_handler(reader, &mut handler)?;
+        handler.writer.flush()?;
+        Ok(())
+    }
+
+    /// Decode ABX straight into `quick_xml` events
+    ///
+    /// Yields the events a `quick_xml::Reader` would produce for the
+    /// converted XML, without writing and reparsing the text. Empty elements
+    /// come out as `Start`/`End` pairs. Tokens are decoded as the iterator is
+    /// advanced, so the document is never held in memory and dropping the
+    /// iterator stops reading. Problems that lenient conversion recovers from
+    /// are available from [`QuickXmlEvents::warnings`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use quick_xml::events::Event;
+    /// use std::fs::File;
+    ///
+    /// let input = File::open("input.abx").unwrap();
+    /// for event in AbxToXmlConverter::to_quick_xml_events(input) {
+    ///     if let Event::Start(start) = event.unwrap() {
+    ///         println!("{}", String::from_utf8_lossy(start.name().as_ref()));
+    ///     }
+    /// }
+    /// ```
+    pub fn to_quick_xml_events<R: Read + Seek>(reader: R) -> QuickXmlEvents<R> {
+        Self::to_quick_xml_events_with_options(reader, &DeserializerOptions::default())
+    }
+
+    /// Decode ABX straight into `quick_xml` events with explicit options
+    ///
+    /// See `to_quick_xml_events`. With `preserve_empty_text` every `TEXT`
+    /// token yields a `Text` event, even an empty one.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, DeserializerOptions};
+    /// use std::fs::File;
+    ///
+    /// let input = File::open("input.abx").unwrap();
+    /// let options = DeserializerOptions::new().preserve_empty_text(true);
+    /// let events = AbxToXmlConverter::to_quick_xml_events_with_options(input, &options)
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// ```
+    pub fn to_quick_xml_events_with_options<R: Read + Seek>(
+        reader: R,
+        options: &DeserializerOptions,
+    ) -> QuickXmlEvents<R> {
+        QuickXmlEvents::new(
+            BinaryXmlDeserializer::with_options(reader, io::sink(), false, options.clone()),
+            options.preserve_empty_text,
+        )
+    }
+
+    /// Extract the text and CDATA content of an ABX document
+    ///
+    /// Tags, attributes, comments and processing instructions are left out.
+    /// References to the predefined XML entities are replaced by their
+    /// characters. With `separate_elements`, element boundaries become line
+    /// breaks, so text from different elements doesn't run together.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    ///
+    /// let input = File::open("input.abx").unwrap();
+    /// let text = AbxToXmlConverter::extract_text(input, true).unwrap();
+    /// for line in text.lines() {
+    ///     println!("{}", line);
+    /// }
+    /// ```
+    pub fn extract_text<R: Read + Seek>(reader: R, separate_elements: bool) -> Result<String> {
+        let mut collector = TextCollector {
+            text: String::new(),
+            separate_elements,
+        };
+        Self::parse_with_handler(reader, &mut collector)?;
+        Ok(collector.text)
+    }
+
+    /// Count the attributes of each type in an ABX document
+    ///
+    /// Keys are raw type bytes (`TYPE_INT`, `TYPE_STRING`, ...), so types this
+    /// crate doesn't understand still show up. Conversion stops at the first
+    /// unknown type, since its payload length can't be known.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, TYPE_BOOLEAN_TRUE};
+    ///
+    /// let abx_data = std::fs::read("input.abx").unwrap();
+    /// let histogram = AbxToXmlConverter::type_histogram(&abx_data).unwrap();
+    /// println!("{:?} true booleans", histogram.get(&TYPE_BOOLEAN_TRUE));
+    /// ```
+    pub fn type_histogram(abx_data: &[u8]) -> Result<BTreeMap<u8, usize>> {
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(abx_data), io::sink(), false)?;
+        deserializer.deserialize()?;
+        Ok(deserializer.attribute_type_counts().clone())
+    }
+
+    /// Read the root element's name and attributes, and nothing after them
+    ///
+    /// Comments and other tokens before the root are skipped. Much cheaper
+    /// than a conversion for telling apart kinds of files, e.g. `users`
+    /// profiles from settings.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    ///
+    /// let root = AbxToXmlConverter::read_root(File::open("0.xml").unwrap()).unwrap();
+    /// if root.name == "user" {
+    ///     println!("user profile with {} attributes", root.attributes.len());
+    /// }
+    /// ```
+    pub fn read_root<R: Read + Seek>(mut reader: R) -> Result<RootInfo> {
+        read_magic(&mut reader, KNOWN_MAGICS)?;
+        let mut input = FastDataInput::new(reader);
+        while !input.is_eof() {
+            let token = input.read_byte()?;
+            match Command::try_from(token)? {
+                Command::StartDocument => {}
+                Command::StartTag => {
+                    let name = input.read_interned_utf()?;
+                    let mut attributes = Vec::new();
+                    while let Some(token) = input.peek()? {
+                        if command_of(token) != ATTRIBUTE {
+                            break;
+                        }
+                        input.read_byte()?;
+                        attributes.push(Attribute {
+                            name: input.read_interned_utf()?,
+                            value: decode_attribute_value(token, &mut input)?,
+                        });
+                    }
+                    return Ok(RootInfo { name, attributes });
+                }
+                Command::EndDocument | Command::EndTag | Command::Attribute => break,
+                _ => {
+                    input.read_token_payload(token)?;
+                }
+            }
+        }
+        Err(crate::AbxError::ParseError(
+            "no root element before the end of the document".to_string(),
+        ))
+    }
+
+    /// List every element in document order with its depth and attribute count
+    ///
+    /// A quick look at the shape of an unfamiliar file without its values or
+    /// text. Indenting each name by its depth gives a readable tree.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    ///
+    /// for entry in AbxToXmlConverter::outline(File::open("0.xml").unwrap()).unwrap() {
+    ///     println!("{:indent$}{} ({})", "", entry.name, entry.attribute_count, indent = entry.depth * 2);
+    /// }
+    /// ```
+    pub fn outline<R: Read + Seek>(reader: R) -> Result<Vec<OutlineEntry>> {
+        let mut collector = OutlineCollector::default();
+        Self::parse_with_handler(reader, &mut collector)?;
+        Ok(collector.entries)
+    }
+
+    /// Estimate how many bytes of XML `convert` would write, without writing it
+    ///
+    /// Walks the tokens adding up tag, attribute and text lengths. Values are
+    /// rendered with the default options but not escaped, so the estimate is
+    /// exact for most files and slightly low for text heavy in `&`, `<` or
+    /// quotes. Useful to size an output buffer up front.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    ///
+    /// let size = AbxToXmlConverter::estimate_output_size(File::open("0.xml").unwrap()).unwrap();
+    /// let mut buffer = Vec::with_capacity(size as usize);
+    /// AbxToXmlConverter::convert_into(File::open("0.xml").unwrap(), &mut buffer).unwrap();
+    /// ```
+    pub fn estimate_output_size<R: Read + Seek>(mut reader: R) -> Result<u64> {
+        read_magic(&mut reader, KNOWN_MAGICS)?;
+        let options = DeserializerOptions::default();
+        let mut input = FastDataInput::new(reader);
+        let mut size = XML_DECLARATION.len();
+        while !input.is_eof() {
+            let token = input.read_byte()?;
+            size += match Command::try_from(token)? {
+                Command::StartDocument => 0,
+                Command::EndDocument => break,
+                // `<name>`
+                Command::StartTag => input.read_interned_utf()?.len() + 2,
+                // `</name>`
+                Command::EndTag => input.read_interned_utf()?.len() + 3,
+                // ` name="value"`
+                Command::Attribute => {
+                    let name = input.read_interned_utf()?;
+                    let value = decode_attribute_value(token, &mut input)?;
+                    name.len() + render_attribute_value(&value, &options).len() + 4
+                }
+                command => {
+                    let text = input.read_token_payload(token)?.len();
+                    text + match command {
+                        Command::Cdsect => "<![CDATA[]]>".len(),
+                        Command::Comment => "<!---->".len(),
+                        Command::ProcessingInstruction => "<??>".len(),
+                        Command::Docdecl => "<!DOCTYPE >".len(),
+                        Command::EntityRef => "&;".len(),
+                        _ => 0,
+                    }
+                }
+            };
+        }
+        Ok(size as u64)
+    }
+
+    /// Find the byte range of the first element at `name_path` (tag names from the root)
+    ///
+    /// The range covers the element's `START_TAG` through its matching
+    /// `END_TAG`, suitable for removing or replacing the whole subtree.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    ///
+    /// let input = File::open("0.xml").unwrap();
+    /// let path = ["user", "restrictions_user", "restrictions"];
+    /// let range = AbxToXmlConverter::subtree_byte_range(input, &path).unwrap();
+    /// ```
+    pub fn subtree_byte_range<R: Read + Seek>(
+        reader: R,
+        name_path: &[&str],
+    ) -> Result<Option<Range<u64>>> {
+        let mut deserializer = BinaryXmlDeserializer::new(reader, io::sink(), true)?;
+        deserializer.deserialize()?;
+        Ok(deserializer.subtree_byte_range(name_path))
     }
 
     /// Convert ABX file to XML file
@@ -31,13 +851,28 @@ impl AbxToXmlConverter {
     /// # Examples
     ///
     /// ```no_run
-    /// use abx2xml::AbxToXmlConverter;
+    /// use honeycomb::AbxToXmlConverter;
     ///
     /// AbxToXmlConverter::convert_file("input.abx", "output.xml").unwrap();
     /// ```
     pub fn convert_file(input_path: &str, output_path: &str) -> Result<()> {
-        if input_path == output_path {
-            return Self::convert_file_in_place(input_path);
+        Self::convert_file_with_options(
+            Path::new(input_path),
+            Path::new(output_path),
+            &DeserializerOptions::default(),
+        )
+        .map(|_| ())
+    }
+
+    fn convert_file_with_options(
+        input_path: &Path,
+        output_path: &Path,
+        options: &DeserializerOptions,
+    ) -> Result<ConversionReport> {
+        // Creating the output would truncate the input before it is read,
+        // however the two paths are spelled
+        if input_path == output_path || is_same_file(input_path, output_path) {
+            return Self::convert_file_in_place(input_path, options);
         }
 
         let input_file = File::open(input_path)?;
@@ -46,28 +881,362 @@ impl AbxToXmlConverter {
         let output_file = File::create(output_path)?;
         let writer = BufWriter::new(output_file);
 
-        Self::convert(reader, writer)
+        Self::convert_with_options(reader, writer, options)
+    }
+
+    /// Convert between paths, where `-` means stdin or stdout
+    ///
+    /// Equal input and output paths convert the file in place. This is what
+    /// the command line uses.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, DeserializerOptions};
+    ///
+    /// let options = DeserializerOptions::default();
+    /// AbxToXmlConverter::convert_paths("input.abx", "-", &options).unwrap();
+    /// ```
+    pub fn convert_paths(
+        input_path: &str,
+        output_path: &str,
+        options: &DeserializerOptions,
+    ) -> Result<ConversionReport> {
+        match (input_path, output_path) {
+            ("-", "-") => {
+                let stdout = io::stdout();
+                Self::convert_stdin_with_options(BufWriter::new(stdout.lock()), options)
+            }
+            ("-", output) => {
+                let output_file = File::create(output)?;
+                Self::convert_stdin_with_options(BufWriter::new(output_file), options)
+            }
+            (input, "-") => {
+                let input_file = File::open(input)?;
+                Self::convert_with_options(BufReader::new(input_file), io::stdout(), options)
+            }
+            (input, output) => {
+                Self::convert_file_with_options(Path::new(input), Path::new(output), options)
+            }
+        }
+    }
+
+    /// Convert many independent files, collecting each file's outcome
+    ///
+    /// Each job is an `(input, output)` pair; a failure in one file doesn't
+    /// stop the others. With the `parallel` feature the files are spread over
+    /// one worker thread per available core. Results are sorted by input path
+    /// either way, so the report doesn't depend on scheduling.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, DeserializerOptions};
+    /// use std::path::PathBuf;
+    ///
+    /// let jobs = vec![
+    ///     (PathBuf::from("a.abx"), PathBuf::from("a.xml")),
+    ///     (PathBuf::from("b.abx"), PathBuf::from("b.xml")),
+    /// ];
+    /// let report = AbxToXmlConverter::convert_batch(&jobs, &DeserializerOptions::default());
+    /// for (path, error) in report.failures() {
+    ///     eprintln!("{}: {}", path.display(), error);
+    /// }
+    /// ```
+    pub fn convert_batch(
+        jobs: &[(PathBuf, PathBuf)],
+        options: &DeserializerOptions,
+    ) -> BatchReport {
+        let convert = |(input, output): &(PathBuf, PathBuf)| BatchEntry {
+            input: input.clone(),
+            output: output.clone(),
+            result: Self::convert_file_with_options(input, output, options),
+        };
+
+        #[cfg(feature = "parallel")]
+        let mut results = {
+            use std::sync::Mutex;
+            use std::sync::atomic::AtomicUsize;
+
+            let next = AtomicUsize::new(0);
+            let results = Mutex::new(Vec::with_capacity(jobs.len()));
+            let workers = std::thread::available_parallelism()
+                .map_or(1, |n| n.get())
+                .min(jobs.len());
+            std::thread::scope(|scope| {
+                for _ in 0..workers {
+                    scope.spawn(|| {
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(job) = jobs.get(index) else { break };
+                            let result = convert(job);
+                            results.lock().unwrap().push((index, result));
+                        }
+                    });
+                }
+            });
+            results.into_inner().unwrap()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let mut results: Vec<_> = jobs.iter().map(convert).enumerate().collect();
+
+        // Job order breaks ties between duplicate inputs
+        results
+            .sort_by(|(a_index, a), (b_index, b)| a.input.cmp(&b.input).then(a_index.cmp(b_index)));
+        BatchReport {
+            results: results.into_iter().map(|(_, entry)| entry).collect(),
+        }
+    }
+
+    /// Convert every ABX file under a directory, recursively
+    ///
+    /// Files are sniffed for an accepted magic header; anything else is
+    /// skipped, and so are symlinks, which aren't followed. Each ABX file is
+    /// converted to the same relative path under `output_dir` using
+    /// [`convert_batch`](Self::convert_batch), so the results are keyed by
+    /// input path. Subdirectories that can't be read are reported as
+    /// failures. Fails up front if the output directory is the input
+    /// directory or either one contains the other, however the paths are
+    /// spelled, since the conversion would then overwrite its own input.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, DeserializerOptions};
+    ///
+    /// let options = DeserializerOptions::default();
+    /// let report = AbxToXmlConverter::convert_dir("system", "out", &options).unwrap();
+    /// report.write_json(std::fs::File::create("report.json").unwrap()).unwrap();
+    /// ```
+    pub fn convert_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+        input_dir: P,
+        output_dir: Q,
+        options: &DeserializerOptions,
+    ) -> Result<BatchReport> {
+        let (input_dir, output_dir) = (input_dir.as_ref(), output_dir.as_ref());
+        let resolved_input = fs::canonicalize(input_dir)?;
+        let resolved_output = resolve_path(output_dir)?;
+        if resolved_output.starts_with(&resolved_input)
+            || resolved_input.starts_with(&resolved_output)
+        {
+            return Err(crate::AbxError::ParseError(format!(
+                "output directory {} overlaps input directory {}",
+                output_dir.display(),
+                input_dir.display()
+            )));
+        }
+
+        let mut jobs = Vec::new();
+        let mut failed = Vec::new();
+        let mut pending = vec![input_dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let output_for =
+                |path: &Path| output_dir.join(path.strip_prefix(input_dir).unwrap_or(path));
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    failed.push(BatchEntry {
+                        output: output_for(&dir),
+                        input: dir,
+                        result: Err(e.into()),
+                    });
+                    continue;
+                }
+            };
+            for entry in entries {
+                // `file_type` doesn't follow symlinks, so a link cycle can't
+                // send the walk in circles
+                let (path, file_type) = match entry.and_then(|e| Ok((e.path(), e.file_type()?))) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        failed.push(BatchEntry {
+                            output: output_for(&dir),
+                            input: dir.clone(),
+                            result: Err(e.into()),
+                        });
+                        continue;
+                    }
+                };
+                if file_type.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                if !file_type.is_file() {
+                    continue;
+                }
+                let mut magic = [0u8; 4];
+                let is_abx = File::open(&path)
+                    .and_then(|mut file| file.read_exact(&mut magic))
+                    .is_ok()
+                    && options.accepted_magics.contains(&magic);
+                if !is_abx {
+                    continue;
+                }
+                let output = output_for(&path);
+                match output.parent().map_or(Ok(()), fs::create_dir_all) {
+                    Ok(()) => jobs.push((path, output)),
+                    Err(e) => failed.push(BatchEntry {
+                        input: path,
+                        output,
+                        result: Err(e.into()),
+                    }),
+                }
+            }
+        }
+
+        let mut report = Self::convert_batch(&jobs, options);
+        if !failed.is_empty() {
+            report.results.extend(failed);
+            report.results.sort_by(|a, b| a.input.cmp(&b.input));
+        }
+        Ok(report)
+    }
+
+    /// Convert every ABX file in a tar archive, optionally gzip-compressed
+    ///
+    /// A `.tar.gz` is recognized by its magic bytes, whatever its name, and
+    /// decompressed as it is read. Other compressions, such as xz, bzip2 and
+    /// zstd, are rejected up front and must be decompressed first. Entries
+    /// are sniffed for an accepted magic header; anything else is
+    /// skipped without being read into memory. Each ABX entry is converted to
+    /// the same relative path under `output_dir`, with the results keyed by
+    /// archive path. Entries whose path is absolute or contains `..`, and ABX
+    /// entries over 64 MiB, are reported as failures rather than converted.
+    /// A corrupt header ends the batch with a failure keyed by the archive's
+    /// own path, keeping the results before it, and so does corrupt
+    /// compressed data.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::{AbxToXmlConverter, DeserializerOptions};
+    ///
+    /// let options = DeserializerOptions::default();
+    /// let report = AbxToXmlConverter::convert_tar("dump.tar", "out", &options).unwrap();
+    /// println!("{} files converted", report.succeeded());
+    /// ```
+    #[cfg(feature = "tar")]
+    pub fn convert_tar<P: AsRef<Path>, Q: AsRef<Path>>(
+        archive_path: P,
+        output_dir: Q,
+        options: &DeserializerOptions,
+    ) -> Result<BatchReport> {
+        use std::io::BufRead;
+        use std::path::Component;
+
+        let archive_path = archive_path.as_ref();
+        let mut archive = BufReader::new(File::open(archive_path)?);
+        let prefix = archive.fill_buf()?;
+        if let Some(format) = crate::tar::unsupported_compression(prefix) {
+            return Err(crate::AbxError::ParseError(format!(
+                "{} is {}-compressed; only gzip-compressed archives are supported",
+                archive_path.display(),
+                format
+            )));
+        }
+        let compressed = prefix.starts_with(&crate::gzip::GZIP_MAGIC);
+        let mut archive: Box<dyn Read> = if compressed {
+            Box::new(crate::gzip::GzipReader::new(archive))
+        } else {
+            Box::new(archive)
+        };
+        let archive_failure = |e| BatchEntry {
+            input: archive_path.to_path_buf(),
+            output: output_dir.as_ref().to_path_buf(),
+            result: Err(e),
+        };
+        let mut results = Vec::new();
+        let mut header_failed = false;
+        for entry in crate::tar::TarReader::new(&mut archive, &options.accepted_magics) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    // Nothing after a bad header can be located, so it ends
+                    // the batch, recorded against the archive itself
+                    results.push(archive_failure(e));
+                    header_failed = true;
+                    break;
+                }
+            };
+
+            let relative = PathBuf::from(&entry.path);
+            let output_path = output_dir.as_ref().join(&relative);
+            let result = if relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                entry.data.and_then(|data| {
+                    if let Some(parent) = output_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let writer = BufWriter::new(File::create(&output_path)?);
+                    Self::convert_with_options(Cursor::new(data), writer, options)
+                })
+            } else {
+                Err(crate::AbxError::ParseError(format!(
+                    "refusing to write archive entry outside the output directory: {}",
+                    entry.path
+                )))
+            };
+            results.push(BatchEntry {
+                input: relative,
+                output: output_path,
+                result,
+            });
+        }
+        // The gzip trailer, and with it the check of everything decompressed,
+        // comes after the end of the tar data
+        if compressed
+            && !header_failed
+            && let Err(e) = io::copy(&mut archive, &mut io::sink())
+        {
+            results.push(archive_failure(e.into()));
+        }
+
+        results.sort_by(|a, b| a.input.cmp(&b.input));
+        Ok(BatchReport { results })
+    }
+
+    /// Convert ABX into a new temp file and return its handle
+    ///
+    /// The file is uniquely named in the system temp dir and deleted when the
+    /// handle is dropped, or on error. Handy for tools that only take paths.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    /// use std::process::Command;
+    ///
+    /// let xml = AbxToXmlConverter::convert_to_tempfile(File::open("input.abx").unwrap()).unwrap();
+    /// Command::new("xmllint").arg(xml.path()).status().unwrap();
+    /// ```
+    #[cfg(feature = "tempfile")]
+    pub fn convert_to_tempfile<R: Read + Seek>(reader: R) -> Result<crate::NamedTempFile> {
+        let temp = crate::NamedTempFile::new()?;
+        let mut writer = BufWriter::new(temp.as_file());
+        Self::convert(reader, &mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        Ok(temp)
     }
 
     /// Convert ABX from stdin to stdout (streaming with seek capability)
     ///
-    /// Uses a SeekableReader to provide seeking capability over stdin.
+    /// Stdin redirected from a regular file is read directly; pipes are read
+    /// with `convert_stream`, so nothing is buffered in memory.
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use abx2xml::AbxToXmlConverter;
+    /// use honeycomb::AbxToXmlConverter;
     ///
     /// // This would be called when processing: cat file.abx | abx2xml - -
     /// AbxToXmlConverter::convert_stdin_stdout().unwrap();
     /// ```
     pub fn convert_stdin_stdout() -> Result<()> {
-        let stdin = io::stdin();
-        let reader = SeekableReader::new(stdin.lock());
-        let stdout = io::stdout();
-        let writer = BufWriter::new(stdout.lock());
-
-        Self::convert(reader, writer)
+        Self::convert_paths("-", "-", &DeserializerOptions::default()).map(|_| ())
     }
 
     /// Convert ABX from stdin to file (streaming with seek capability)
@@ -75,18 +1244,56 @@ impl AbxToXmlConverter {
     /// # Examples
     ///
     /// ```no_run
-    /// use abx2xml::AbxToXmlConverter;
+    /// use honeycomb::AbxToXmlConverter;
     ///
     /// // This would be called when processing: cat file.abx | abx2xml - output.xml
     /// AbxToXmlConverter::convert_stdin_to_file("output.xml").unwrap();
     /// ```
     pub fn convert_stdin_to_file(output_path: &str) -> Result<()> {
-        let stdin = io::stdin();
-        let reader = SeekableReader::new(stdin.lock());
         let output_file = File::create(output_path)?;
         let writer = BufWriter::new(output_file);
 
-        Self::convert(reader, writer)
+        Self::convert_stdin_with_options(writer, &DeserializerOptions::default()).map(|_| ())
+    }
+
+    fn convert_stdin_with_options<W: Write>(
+        writer: W,
+        options: &DeserializerOptions,
+    ) -> Result<ConversionReport> {
+        match Self::seekable_stdin() {
+            Some(file) => Self::convert_with_options(BufReader::new(file), writer, options),
+            None => Self::convert_stream(io::stdin().lock(), writer, options),
+        }
+    }
+
+    /// Get stdin as a `File` when it is redirected from a regular file
+    ///
+    /// A redirected file (`honeycomb - < file.abx`) can be seeked directly, so
+    /// offsets are those of the file. Returns `None` for pipes, terminals,
+    /// and other non-seekable inputs.
+    fn seekable_stdin() -> Option<File> {
+        #[cfg(unix)]
+        let owned = {
+            use std::os::fd::AsFd;
+            io::stdin().as_fd().try_clone_to_owned().ok()?
+        };
+        #[cfg(windows)]
+        let owned = {
+            use std::os::windows::io::AsHandle;
+            io::stdin().as_handle().try_clone_to_owned().ok()?
+        };
+        #[cfg(not(any(unix, windows)))]
+        return None;
+
+        #[cfg(any(unix, windows))]
+        {
+            let mut file = File::from(owned);
+            if !file.metadata().ok()?.is_file() {
+                return None;
+            }
+            file.stream_position().ok()?;
+            Some(file)
+        }
     }
 
     /// Convert ABX file to stdout
@@ -94,7 +1301,7 @@ impl AbxToXmlConverter {
     /// # Examples
     ///
     /// ```no_run
-    /// use abx2xml::AbxToXmlConverter;
+    /// use honeycomb::AbxToXmlConverter;
     ///
     /// AbxToXmlConverter::convert_file_to_stdout("input.abx").unwrap();
     /// ```
@@ -108,18 +1315,23 @@ impl AbxToXmlConverter {
 
     /// Convert ABX file in place (overwrites the original file)
     ///
-    /// This method reads the entire file into memory, converts it,
-    /// and then writes the result back to the same file.
+    /// This method reads the entire file into memory and converts it. The
+    /// result is written to a temporary file in the same directory, which is
+    /// then renamed over the original, so the original is never left truncated
+    /// if the process dies mid-write.
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use abx2xml::AbxToXmlConverter;
+    /// use honeycomb::AbxToXmlConverter;
     ///
     /// // This is called internally when using the -i flag
     /// AbxToXmlConverter::convert_file("input.abx", "input.abx").unwrap();
     /// ```
-    fn convert_file_in_place(file_path: &str) -> Result<()> {
+    fn convert_file_in_place(
+        file_path: &Path,
+        options: &DeserializerOptions,
+    ) -> Result<ConversionReport> {
         // Read entire file into memory
         let input_file = File::open(file_path)?;
         let mut reader = BufReader::new(input_file);
@@ -129,18 +1341,48 @@ impl AbxToXmlConverter {
         // Convert from memory
         let cursor = Cursor::new(file_data);
         let mut output_data = Vec::new();
-        {
+        let report = {
             let writer = Cursor::new(&mut output_data);
-            Self::convert(cursor, writer)?;
-        }
+            Self::convert_with_options(cursor, writer, options)?
+        };
 
-        // Write back to file
-        let output_file = File::create(file_path)?;
-        let mut writer = BufWriter::new(output_file);
-        writer.write_all(&output_data)?;
-        writer.flush()?;
+        // Write to a sibling temp file, then atomically replace the original.
+        // `fs::rename` replaces an existing destination on both Unix and Windows.
+        // The counter keeps concurrent conversions of one file in this process
+        // (e.g. duplicate batch jobs) off each other's temp file.
+        static IN_PLACE_COUNTER: AtomicU32 = AtomicU32::new(0);
+        let path = file_path;
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let temp_path = path.with_file_name(format!(
+            ".{}.honeycomb-{}-{}.tmp",
+            file_name,
+            std::process::id(),
+            IN_PLACE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let permissions = fs::metadata(path)?.permissions();
 
-        Ok(())
+        let result = (|| -> Result<()> {
+            let temp_file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp_path)?;
+            // Keep the original's mode, e.g. 0600 on a user profile
+            temp_file.set_permissions(permissions)?;
+            let mut writer = BufWriter::new(temp_file);
+            writer.write_all(&output_data)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result.map(|_| report)
     }
 
     /// Convert ABX data from a byte slice to a String
@@ -150,23 +1392,92 @@ impl AbxToXmlConverter {
     /// # Examples
     ///
     /// ```no_run
-    /// use abx2xml::AbxToXmlConverter;
+    /// use honeycomb::AbxToXmlConverter;
     ///
     /// let abx_data = std::fs::read("input.abx").unwrap();
     /// let xml_string = AbxToXmlConverter::convert_bytes(&abx_data).unwrap();
     /// println!("{}", xml_string);
     /// ```
     pub fn convert_bytes(abx_data: &[u8]) -> Result<String> {
-        let cursor = Cursor::new(abx_data);
         let mut output_data = Vec::new();
-        {
-            let writer = Cursor::new(&mut output_data);
-            Self::convert(cursor, writer)?;
-        }
+        Self::convert_into(Cursor::new(abx_data), &mut output_data)?;
         String::from_utf8(output_data)
             .map_err(|_| crate::AbxError::ParseError("Invalid UTF-8 in output".to_string()))
     }
 
+    /// Convert just the first `max_elements` elements to a String
+    ///
+    /// Elements are counted at any depth, in document order. If the document
+    /// has more, the open elements are closed and `<!-- truncated -->` is
+    /// appended, so the preview is still well-formed; the rest of the input
+    /// isn't read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    ///
+    /// let preview = AbxToXmlConverter::convert_preview(File::open("0.xml").unwrap(), 50).unwrap();
+    /// println!("{}", preview);
+    /// ```
+    pub fn convert_preview<R: Read + Seek>(reader: R, max_elements: usize) -> Result<String> {
+        let mut output_data = Vec::new();
+        BinaryXmlDeserializer::new(reader, &mut output_data, false)?
+            .with_element_limit(max_elements)
+            .deserialize()?;
+        String::from_utf8(output_data)
+            .map_err(|_| crate::AbxError::ParseError("Invalid UTF-8 in output".to_string()))
+    }
+
+    /// Convert ABX data to a String, also collecting the offsets of every policy
+    ///
+    /// The same as `convert_bytes` plus the deserializer's `get_policies()`,
+    /// in one pass.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    ///
+    /// let abx_data = std::fs::read("0.xml").unwrap();
+    /// let (xml, policies) = AbxToXmlConverter::convert_bytes_with_policies(&abx_data).unwrap();
+    /// println!("{} bytes of XML, {} policies", xml.len(), policies.len());
+    /// ```
+    pub fn convert_bytes_with_policies(abx_data: &[u8]) -> Result<(String, Vec<Policy>)> {
+        let mut output_data = Vec::new();
+        let policies = {
+            let mut deserializer =
+                BinaryXmlDeserializer::new(Cursor::new(abx_data), &mut output_data, true)?;
+            deserializer.deserialize()?;
+            deserializer.get_policies().to_vec()
+        };
+        let xml = String::from_utf8(output_data)
+            .map_err(|_| crate::AbxError::ParseError("Invalid UTF-8 in output".to_string()))?;
+        Ok((xml, policies))
+    }
+
+    /// Convert ABX from a reader, appending the XML bytes to `out`
+    ///
+    /// Unlike `convert_bytes` the output isn't checked for UTF-8 or copied into
+    /// a `String`. Clear `out` first to reuse one buffer across conversions.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use honeycomb::AbxToXmlConverter;
+    /// use std::fs::File;
+    ///
+    /// let mut buffer = Vec::new();
+    /// for path in ["a.abx", "b.abx"] {
+    ///     buffer.clear();
+    ///     AbxToXmlConverter::convert_into(File::open(path).unwrap(), &mut buffer).unwrap();
+    /// }
+    /// ```
+    pub fn convert_into<R: Read + Seek>(reader: R, out: &mut Vec<u8>) -> Result<()> {
+        Self::convert(reader, out)
+    }
+
     /// Convert ABX data from a Vec<u8> to a String
     ///
     /// This takes ownership of the input data.
@@ -174,7 +1485,7 @@ impl AbxToXmlConverter {
     /// # Examples
     ///
     /// ```no_run
-    /// use abx2xml::AbxToXmlConverter;
+    /// use honeycomb::AbxToXmlConverter;
     ///
     /// let abx_data = std::fs::read("input.abx").unwrap();
     /// let xml_string = AbxToXmlConverter::convert_vec(abx_data).unwrap();
@@ -191,3 +1502,690 @@ impl AbxToXmlConverter {
             .map_err(|_| crate::AbxError::ParseError("Invalid UTF-8 in output".to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+    use quick_xml::events::Event;
+
+    #[test]
+    fn test_convert_file_in_place_replaces_original() {
+        let dir = std::env::temp_dir().join(format!("honeycomb-in-place-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("profile.xml");
+        let data = AbxFixture::new().start_tag("a").end_tag("a").build();
+        fs::write(&path, &data).unwrap();
+
+        let path_str = path.to_str().unwrap();
+        AbxToXmlConverter::convert_file(path_str, path_str).unwrap();
+
+        let xml = fs::read_to_string(&path).unwrap();
+        assert!(xml.ends_with("<a></a>"));
+        let leftovers: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(leftovers.len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_convert_file_in_place_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("honeycomb-in-place-mode-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("0.xml");
+        fs::write(&path, AbxFixture::new().start_tag("a").end_tag("a").build()).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let path_str = path.to_str().unwrap();
+        AbxToXmlConverter::convert_file(path_str, path_str).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_quick_xml_events_match_reparsed_output() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_string("x", "1 < 2")
+            .text("body")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+
+        let events: Vec<_> = AbxToXmlConverter::to_quick_xml_events(Cursor::new(&data))
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let xml = AbxToXmlConverter::convert_bytes(&data).unwrap();
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        let mut expected = Vec::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Eof => break,
+                event => expected.push(event.into_owned()),
+            }
+        }
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn test_quick_xml_events_are_pulled_lazily() {
+        let mut data = AbxFixture::new()
+            .start_tag("a")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+        // An unknown command after </b>
+        let end_a = data.len() - 4;
+        data.insert(end_a, 0x0B);
+
+        let options = DeserializerOptions::new().strict(true);
+        let mut events =
+            AbxToXmlConverter::to_quick_xml_events_with_options(Cursor::new(&data), &options);
+        assert!(matches!(events.next(), Some(Ok(Event::Decl(_)))));
+        assert!(matches!(events.next(), Some(Ok(Event::Start(_)))));
+        assert!(matches!(events.next(), Some(Ok(Event::Start(_)))));
+        assert!(matches!(events.next(), Some(Ok(Event::End(_)))));
+        assert!(matches!(events.next(), Some(Err(_))));
+        assert!(events.next().is_none());
+
+        let mut events = AbxToXmlConverter::to_quick_xml_events(Cursor::new(&data[..3]));
+        assert!(matches!(events.next(), Some(Err(_))));
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn test_convert_stream_matches_seekable_conversion() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_int("x", 1)
+            .attribute_string("y", "z")
+            .text("body")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+
+        let mut output = Vec::new();
+        let options = DeserializerOptions::new().strict(true);
+        // A byte slice is `Read` but not `Seek`
+        let report = AbxToXmlConverter::convert_stream(&data[..], &mut output, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            AbxToXmlConverter::convert_bytes(&data).unwrap()
+        );
+        assert_eq!(report.attribute_count, 2);
+    }
+
+    #[test]
+    fn test_preserve_empty_text_events() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .text("")
+            .end_tag("a")
+            .build();
+
+        let count_text = |options: &DeserializerOptions| {
+            AbxToXmlConverter::to_quick_xml_events_with_options(Cursor::new(&data), options)
+                .filter(|event| matches!(event, Ok(Event::Text(_))))
+                .count()
+        };
+        assert_eq!(count_text(&DeserializerOptions::new()), 0);
+        assert_eq!(
+            count_text(&DeserializerOptions::new().preserve_empty_text(true)),
+            1
+        );
+
+        let document = crate::AbxDocument::parse(Cursor::new(&data)).unwrap();
+        assert_eq!(document.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn test_convert_bytes_with_policies() {
+        let data = AbxFixture::new()
+            .start_tag("restrictions_user")
+            .attribute_int("id", 0)
+            .start_tag("restrictions")
+            .attribute_bool("x", true)
+            .attribute_bool("y", true)
+            .end_tag("restrictions")
+            .end_tag("restrictions_user")
+            .build();
+
+        let (xml, policies) = AbxToXmlConverter::convert_bytes_with_policies(&data).unwrap();
+        assert_eq!(xml, AbxToXmlConverter::convert_bytes(&data).unwrap());
+        let names: Vec<&str> = policies.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["x", "y"]);
+        assert_eq!(policies[0].end_offset, policies[1].start_offset);
+    }
+
+    #[test]
+    fn test_convert_into_appends() {
+        let data = AbxFixture::new().start_tag("a").end_tag("a").build();
+        let mut buffer = b"prefix".to_vec();
+        AbxToXmlConverter::convert_into(Cursor::new(&data), &mut buffer).unwrap();
+        let expected = AbxToXmlConverter::convert_bytes(&data).unwrap();
+        assert_eq!(buffer, [b"prefix", expected.as_bytes()].concat());
+    }
+
+    #[test]
+    fn test_extract_text() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_string("x", "not text")
+            .text("one ")
+            .raw(&[crate::ENTITY_REF | crate::TYPE_STRING, 0x00, 0x03])
+            .raw(b"amp")
+            .start_tag("b")
+            .text("two")
+            .end_tag("b")
+            .raw(&[crate::CDSECT | crate::TYPE_STRING, 0x00, 0x05])
+            .raw(b"three")
+            .end_tag("a")
+            .build();
+
+        let text = AbxToXmlConverter::extract_text(Cursor::new(&data), false).unwrap();
+        assert_eq!(text, "one &twothree");
+        let text = AbxToXmlConverter::extract_text(Cursor::new(&data), true).unwrap();
+        assert_eq!(text, "one &\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn test_type_histogram() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_bool("x", true)
+            .attribute_bool("y", true)
+            .attribute_int("z", 1)
+            .start_tag("b")
+            .raw(&[crate::ATTRIBUTE | 0xE0, 0xFF, 0xFF, 0x00, 0x01, b'q'])
+            .build();
+
+        let histogram = AbxToXmlConverter::type_histogram(&data).unwrap();
+        let expected: BTreeMap<u8, usize> = [
+            (crate::TYPE_INT, 1),
+            (crate::TYPE_BOOLEAN_TRUE, 2),
+            (0xE0, 1),
+        ]
+        .into();
+        assert_eq!(histogram, expected);
+    }
+
+    #[test]
+    fn test_convert_reporting() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_bool("x", true)
+            .start_tag("b")
+            .attribute_int("y", 3)
+            .end_tag("b")
+            .end_tag("a")
+            .build();
+
+        let mut output = Vec::new();
+        let report = AbxToXmlConverter::convert_reporting(Cursor::new(&data), &mut output).unwrap();
+        assert_eq!(report.output_bytes, output.len() as u64);
+        assert_eq!(report.input_bytes, data.len() as u64);
+        assert_eq!(
+            report.expansion_ratio(),
+            output.len() as f64 / data.len() as f64
+        );
+        assert_eq!(report.element_count, 2);
+        assert_eq!(report.attribute_count, 2);
+        assert!(report.warnings.is_empty());
+        assert_eq!(report.version, 0);
+    }
+
+    #[test]
+    fn test_convert_batch_sorts_results_by_path() {
+        let dir = std::env::temp_dir().join(format!("honeycomb-batch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data = AbxFixture::new().start_tag("a").end_tag("a").build();
+        for name in ["b.abx", "c.abx"] {
+            fs::write(dir.join(name), &data).unwrap();
+        }
+        fs::write(dir.join("a.abx"), b"not abx").unwrap();
+
+        let jobs: Vec<_> = ["c", "a", "b"]
+            .iter()
+            .map(|name| {
+                (
+                    dir.join(format!("{}.abx", name)),
+                    dir.join(format!("{}.xml", name)),
+                )
+            })
+            .collect();
+        let report = AbxToXmlConverter::convert_batch(&jobs, &DeserializerOptions::default());
+
+        let paths: Vec<_> = report.results.iter().map(|e| e.input.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![dir.join("a.abx"), dir.join("b.abx"), dir.join("c.abx")]
+        );
+        assert_eq!(report.succeeded(), 2);
+        let failures: Vec<_> = report.failures().map(|(p, _)| p.to_path_buf()).collect();
+        assert_eq!(failures, vec![dir.join("a.abx")]);
+        assert!(
+            fs::read_to_string(dir.join("c.xml"))
+                .unwrap()
+                .ends_with("<a></a>")
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_convert_ndjson() {
+        let data = AbxFixture::new()
+            .start_tag("settings")
+            .start_tag("setting")
+            .attribute_string("name", "a")
+            .text("1")
+            .end_tag("setting")
+            .start_tag("setting")
+            .attribute_string("name", "b\"")
+            .start_tag("empty")
+            .end_tag("empty")
+            .end_tag("setting")
+            .end_tag("settings")
+            .build();
+
+        let mut output = Vec::new();
+        AbxToXmlConverter::convert_ndjson(Cursor::new(&data), &mut output, 1).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"{"name":"setting","attributes":{"name":"a"},"children":["1"]}"#,
+                "\n",
+                r#"{"name":"setting","attributes":{"name":"b\""},"children":[{"name":"empty","attributes":{},"children":[]}]}"#,
+                "\n",
+            )
+        );
+
+        let mut output = Vec::new();
+        AbxToXmlConverter::convert_ndjson(Cursor::new(&data), &mut output, 0).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_estimate_output_size() {
+        let data = AbxFixture::new()
+            .start_tag("user")
+            .attribute_int("id", 10)
+            .attribute_bool("guest", false)
+            .start_tag("name")
+            .text("Owner")
+            .end_tag("name")
+            .end_tag("user")
+            .build();
+
+        let estimate = AbxToXmlConverter::estimate_output_size(Cursor::new(&data)).unwrap();
+        let xml = AbxToXmlConverter::convert_bytes(&data).unwrap();
+        assert_eq!(estimate, xml.len() as u64);
+    }
+
+    #[test]
+    fn test_convert_range() {
+        let abx = AbxFixture::new()
+            .start_tag("a")
+            .end_tag("a")
+            .build_unterminated();
+        let mut container = b"HEADER".to_vec();
+        container.extend_from_slice(&abx);
+        container.extend_from_slice(b"\x01trailer");
+
+        let mut output = Vec::new();
+        let end = 6 + abx.len() as u64;
+        AbxToXmlConverter::convert_range(Cursor::new(&container), 6, end, &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with("<a></a>"));
+
+        let result = AbxToXmlConverter::convert_range(Cursor::new(&container), 0, end, Vec::new());
+        assert!(matches!(
+            result,
+            Err(crate::AbxError::InvalidMagicHeader { .. })
+        ));
+    }
+
+    #[test]
+    fn test_convert_flat() {
+        let data = AbxFixture::new()
+            .start_tag("user")
+            .attribute_int("id", 10)
+            .start_tag("restrictions")
+            .attribute_bool("no_sms", true)
+            .attribute_string("note", "a\nb")
+            .end_tag("restrictions")
+            .end_tag("user")
+            .build();
+
+        let mut output = Vec::new();
+        AbxToXmlConverter::convert_flat(Cursor::new(&data), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "user@id=10\n\
+             user/restrictions@no_sms=true\n\
+             user/restrictions@note=a\\nb\n"
+        );
+    }
+
+    #[test]
+    fn test_outline() {
+        let data = AbxFixture::new()
+            .start_tag("user")
+            .attribute_int("id", 10)
+            .attribute_bool("guest", false)
+            .start_tag("restrictions")
+            .attribute_bool("no_sms", true)
+            .end_tag("restrictions")
+            .start_tag("name")
+            .text("Owner")
+            .end_tag("name")
+            .end_tag("user")
+            .build();
+
+        let outline = AbxToXmlConverter::outline(Cursor::new(&data)).unwrap();
+        let entry = |depth, name: &str, attribute_count| OutlineEntry {
+            depth,
+            name: name.to_string(),
+            attribute_count,
+        };
+        assert_eq!(
+            outline,
+            [
+                entry(0, "user", 2),
+                entry(1, "restrictions", 1),
+                entry(1, "name", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_root() {
+        let data = AbxFixture::new()
+            .start_tag("user")
+            .attribute_int("id", 10)
+            .attribute_string("name", "Owner")
+            .start_tag("restrictions")
+            .end_tag("restrictions")
+            .end_tag("user")
+            .build();
+
+        let root = AbxToXmlConverter::read_root(Cursor::new(&data)).unwrap();
+        assert_eq!(root.name, "user");
+        assert_eq!(
+            root.attributes,
+            [
+                Attribute {
+                    name: "id".to_string(),
+                    value: crate::AttributeValue::Int(10)
+                },
+                Attribute {
+                    name: "name".to_string(),
+                    value: crate::AttributeValue::String("Owner".to_string())
+                },
+            ]
+        );
+
+        let empty = AbxFixture::new().build();
+        assert!(AbxToXmlConverter::read_root(Cursor::new(&empty)).is_err());
+    }
+
+    #[test]
+    fn test_convert_preview() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .start_tag("b")
+            .text("x")
+            .end_tag("b")
+            .start_tag("c")
+            .end_tag("c")
+            .end_tag("a")
+            .build();
+
+        let preview = AbxToXmlConverter::convert_preview(Cursor::new(&data), 2).unwrap();
+        assert!(preview.ends_with("<a><b>x</b></a><!-- truncated -->"));
+
+        let full = AbxToXmlConverter::convert_preview(Cursor::new(&data), 3).unwrap();
+        assert_eq!(full, AbxToXmlConverter::convert_bytes(&data).unwrap());
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn test_convert_tar() {
+        let dir = std::env::temp_dir().join(format!("honeycomb-tar-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let abx = AbxFixture::new().start_tag("a").end_tag("a").build();
+        let archive = crate::tar::archive(&[
+            ("system/users/0.xml", &abx),
+            ("system/notes.txt", b"plain text"),
+            ("../escape.xml", &abx),
+        ]);
+        let archive_path = dir.join("dump.tar");
+        fs::write(&archive_path, archive).unwrap();
+
+        let output_dir = dir.join("out");
+        let report = AbxToXmlConverter::convert_tar(
+            &archive_path,
+            &output_dir,
+            &DeserializerOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.succeeded(), 1);
+        let xml = fs::read_to_string(output_dir.join("system/users/0.xml")).unwrap();
+        assert!(xml.ends_with("<a></a>"));
+        assert!(!output_dir.join("system/notes.txt").exists());
+        assert!(!dir.join("escape.xml").exists());
+
+        // A corrupt second header keeps the first result
+        let mut archive = crate::tar::archive(&[("0.xml", &abx), ("1.xml", &abx)]);
+        archive[1024] ^= 1;
+        fs::write(&archive_path, archive).unwrap();
+        let report = AbxToXmlConverter::convert_tar(
+            &archive_path,
+            &output_dir,
+            &DeserializerOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.succeeded(), 1);
+        assert_eq!(report.failures().next().unwrap().0, archive_path);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn test_convert_compressed_tar() {
+        let dir = std::env::temp_dir().join(format!("honeycomb-tar-gz-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let abx = AbxFixture::new().start_tag("a").end_tag("a").build();
+        let archive = crate::tar::archive(&[("system/users/0.xml", &abx)]);
+        let archive_path = dir.join("dump.tar.gz");
+        fs::write(&archive_path, crate::gzip::gzip_stored(&archive)).unwrap();
+
+        let output_dir = dir.join("out");
+        let options = DeserializerOptions::default();
+        let report = AbxToXmlConverter::convert_tar(&archive_path, &output_dir, &options).unwrap();
+        assert_eq!(report.succeeded(), 1);
+        let xml = fs::read_to_string(output_dir.join("system/users/0.xml")).unwrap();
+        assert!(xml.ends_with("<a></a>"));
+
+        // Damaged compressed data is a failure of the archive
+        let mut compressed = crate::gzip::gzip_stored(&archive);
+        let crc = compressed.len() - 8;
+        compressed[crc] ^= 1;
+        fs::write(&archive_path, compressed).unwrap();
+        let report = AbxToXmlConverter::convert_tar(&archive_path, &output_dir, &options).unwrap();
+        let (path, error) = report.failures().next().unwrap();
+        assert_eq!(path, archive_path);
+        assert!(error.to_string().contains("CRC mismatch"), "{}", error);
+
+        let xz_path = dir.join("dump.tar.xz");
+        fs::write(&xz_path, [0xFD, b'7', b'z', b'X', b'Z', 0, 0, 0]).unwrap();
+        let err = AbxToXmlConverter::convert_tar(&xz_path, &output_dir, &options).unwrap_err();
+        assert!(err.to_string().contains("xz-compressed"), "{}", err);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_convert_dir_writes_json_report() {
+        let dir = std::env::temp_dir().join(format!("honeycomb-dir-{}", std::process::id()));
+        let input = dir.join("system");
+        fs::create_dir_all(input.join("users")).unwrap();
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_int("x", 1)
+            .end_tag("a")
+            .build();
+        fs::write(input.join("users/0.xml"), &data).unwrap();
+        fs::write(input.join("broken.xml"), &data[..data.len() - 3]).unwrap();
+        fs::write(input.join("notes.txt"), b"not abx").unwrap();
+
+        let output = dir.join("out");
+        let options = DeserializerOptions::default().strict(true);
+        let report = AbxToXmlConverter::convert_dir(&input, &output, &options).unwrap();
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.succeeded(), 1);
+        assert!(output.join("users/0.xml").exists());
+        assert!(!output.join("notes.txt").exists());
+
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        let lines: Vec<_> = json.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].contains("\"success\":false,\"error\":"));
+        assert!(lines[2].ends_with(
+            "\"success\":true,\"version\":0,\"warnings\":0,\"elements\":1,\"attributes\":1}"
+        ));
+        assert!(lines[2].contains(&format!(
+            "\"output\":\"{}\"",
+            output.join("users/0.xml").display()
+        )));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_convert_dir_rejects_overlapping_output() {
+        let dir =
+            std::env::temp_dir().join(format!("honeycomb-dir-overlap-{}", std::process::id()));
+        let input = dir.join("system");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(
+            input.join("0.xml"),
+            AbxFixture::new().start_tag("a").end_tag("a").build(),
+        )
+        .unwrap();
+
+        let options = DeserializerOptions::default();
+        for output in [
+            input.clone(),
+            input.join("../system/out"),
+            input.join("out"),
+            dir.clone(),
+        ] {
+            let err = AbxToXmlConverter::convert_dir(&input, &output, &options).unwrap_err();
+            assert!(err.to_string().contains("overlaps"), "{}", output.display());
+        }
+        assert!(!input.join("out").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_convert_dir_skips_symlinks() {
+        let dir = std::env::temp_dir().join(format!("honeycomb-dir-links-{}", std::process::id()));
+        let input = dir.join("system");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(
+            input.join("0.xml"),
+            AbxFixture::new().start_tag("a").end_tag("a").build(),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(&input, input.join("loop")).unwrap();
+        std::os::unix::fs::symlink(input.join("0.xml"), input.join("1.xml")).unwrap();
+
+        let output = dir.join("out");
+        let options = DeserializerOptions::default();
+        let report = AbxToXmlConverter::convert_dir(&input, &output, &options).unwrap();
+        assert_eq!(report.results.len(), 1);
+        assert_eq!(report.results[0].input, input.join("0.xml"));
+        assert_eq!(report.results[0].output, output.join("0.xml"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_convert_file_to_same_file_by_another_path() {
+        let dir = std::env::temp_dir().join(format!("honeycomb-same-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("0.xml");
+        fs::write(&path, AbxFixture::new().start_tag("a").end_tag("a").build()).unwrap();
+
+        let other = dir.join(".").join("0.xml");
+        AbxToXmlConverter::convert_file(path.to_str().unwrap(), other.to_str().unwrap()).unwrap();
+
+        assert!(fs::read_to_string(&path).unwrap().ends_with("<a></a>"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "tempfile")]
+    #[test]
+    fn test_convert_to_tempfile() {
+        let data = AbxFixture::new().start_tag("a").end_tag("a").build();
+        let temp = AbxToXmlConverter::convert_to_tempfile(Cursor::new(&data)).unwrap();
+        let path = temp.path().to_path_buf();
+        assert!(fs::read_to_string(&path).unwrap().ends_with("<a></a>"));
+        drop(temp);
+        assert!(!path.exists());
+
+        let kept = AbxToXmlConverter::convert_to_tempfile(Cursor::new(&data))
+            .unwrap()
+            .keep();
+        assert!(kept.exists());
+        fs::remove_file(kept).unwrap();
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_conversion_metrics() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_bool("x", true)
+            .start_tag("a")
+            .end_tag("a")
+            .end_tag("a")
+            .build();
+
+        let mut output = Vec::new();
+        let report = AbxToXmlConverter::convert_reporting(Cursor::new(&data), &mut output).unwrap();
+        let metrics = report.metrics;
+        assert_eq!(metrics.peak_interned_strings, 2);
+        // Probing past the attributes peeks instead of re-reading
+        assert_eq!(metrics.bytes_read, data.len() as u64);
+        assert_eq!(metrics.bytes_written, output.len() as u64);
+        // Tokens are only read forward
+        assert_eq!(metrics.seeks, 0);
+
+        let report =
+            AbxToXmlConverter::convert_stream(&data[..], io::sink(), &DeserializerOptions::new())
+                .unwrap();
+        assert_eq!(report.metrics.seeks, 0);
+        assert_eq!(report.metrics.bytes_read, data.len() as u64);
+    }
+}
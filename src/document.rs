@@ -0,0 +1,621 @@
+//! In-memory ABX document tree that can be edited and encoded back to ABX
+
+use crate::Interner;
+use crate::PROTOCOL_MAGIC_VERSION_0;
+use crate::binary_xml::read_magic;
+use crate::{ATTRIBUTE, END_DOCUMENT, END_TAG, START_DOCUMENT, START_TAG};
+use crate::{AbxError, AttributeType, Command, FastDataInput, KNOWN_MAGICS, Result};
+use crate::{TYPE_NULL, TYPE_STRING, TYPE_STRING_INTERNED};
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+
+/// A typed attribute value, as stored in the ABX stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    InternedString(String),
+    BytesHex(Vec<u8>),
+    BytesBase64(Vec<u8>),
+    Int(i32),
+    IntHex(i32),
+    Long(i64),
+    LongHex(i64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+}
+
+impl AttributeValue {
+    /// The `AttributeType` this value is encoded as
+    pub fn attribute_type(&self) -> AttributeType {
+        match self {
+            AttributeValue::String(_) => AttributeType::String,
+            AttributeValue::InternedString(_) => AttributeType::StringInterned,
+            AttributeValue::BytesHex(_) => AttributeType::BytesHex,
+            AttributeValue::BytesBase64(_) => AttributeType::BytesBase64,
+            AttributeValue::Int(_) => AttributeType::Int,
+            AttributeValue::IntHex(_) => AttributeType::IntHex,
+            AttributeValue::Long(_) => AttributeType::Long,
+            AttributeValue::LongHex(_) => AttributeType::LongHex,
+            AttributeValue::Float(_) => AttributeType::Float,
+            AttributeValue::Double(_) => AttributeType::Double,
+            AttributeValue::Boolean(true) => AttributeType::BooleanTrue,
+            AttributeValue::Boolean(false) => AttributeType::BooleanFalse,
+        }
+    }
+
+    /// Whether a string value contains characters that XML output escapes
+    ///
+    /// ABX stores strings unescaped, so this tells a raw `&` or `<` in the
+    /// original bytes apart from values that convert unchanged. Text that
+    /// looks pre-escaped, like `&amp;`, counts too since its `&` is raw.
+    /// Always `false` for non-string values.
+    pub fn needs_escaping(&self) -> bool {
+        match self {
+            AttributeValue::String(s) | AttributeValue::InternedString(s) => {
+                s.contains(['&', '<', '>', '"', '\''])
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub value: AttributeValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    /// Create an element with no attributes or children
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// First attribute called `name`
+    pub fn attribute(&self, name: &str) -> Option<&AttributeValue> {
+        self.attributes
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| &a.value)
+    }
+}
+
+/// A node in the document tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+    Cdsect(String),
+    Comment(String),
+    ProcessingInstruction(String),
+    Docdecl(String),
+    EntityRef(String),
+    IgnorableWhitespace(String),
+}
+
+/// A parsed ABX document
+///
+/// Besides the tree itself, the document remembers the interned string table
+/// of the stream it was parsed from, so that
+/// [`write_preserving_interning`](Self::write_preserving_interning) can refuse
+/// edits that would renumber it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AbxDocument {
+    pub nodes: Vec<Node>,
+    interned: Vec<String>,
+}
+
+impl AbxDocument {
+    /// Create an empty document
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a complete ABX stream into a tree
+    ///
+    /// Unlike conversion to XML this is all-or-nothing: truncated or
+    /// malformed input is an error.
+    pub fn parse<R: Read + Seek>(reader: R) -> Result<Self> {
+        Self::parse_with(reader, 0, false)
+    }
+
+    /// Parse with interned references read as `index - reference_shift`
+    ///
+    /// With `close_at_eof`, a stream that ends without `END_DOCUMENT` is
+    /// accepted and any open elements are closed.
+    pub(crate) fn parse_with<R: Read + Seek>(
+        mut reader: R,
+        reference_shift: i32,
+        close_at_eof: bool,
+    ) -> Result<Self> {
+        read_magic(&mut reader, KNOWN_MAGICS)?;
+
+        let mut input = FastDataInput::new(reader).with_reference_shift(reference_shift);
+        let mut nodes = Vec::new();
+        let mut stack: Vec<Element> = Vec::new();
+        loop {
+            if input.is_eof() {
+                if !close_at_eof {
+                    return Err(AbxError::ParseError(
+                        "stream ended without END_DOCUMENT".to_string(),
+                    ));
+                }
+                while let Some(element) = stack.pop() {
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(Node::Element(element)),
+                        None => nodes.push(Node::Element(element)),
+                    }
+                }
+                break;
+            }
+            let offset = input.tell()?;
+            let token = input.read_byte()?;
+            let command = Command::try_from(token)?;
+            let node = match command {
+                Command::StartDocument => continue,
+                Command::EndDocument => break,
+                Command::StartTag => {
+                    stack.push(Element::new(&input.read_interned_utf()?));
+                    continue;
+                }
+                Command::Attribute => {
+                    let Some(element) = stack.last_mut() else {
+                        return Err(AbxError::ParseError(format!(
+                            "attribute outside of an element at offset {}",
+                            offset
+                        )));
+                    };
+                    let name = input.read_interned_utf()?;
+                    let value = decode_attribute_value(token, &mut input)?;
+                    element.attributes.push(Attribute { name, value });
+                    continue;
+                }
+                Command::EndTag => {
+                    let name = input.read_interned_utf()?;
+                    match stack.pop() {
+                        Some(element) if element.name == name => Node::Element(element),
+                        _ => {
+                            return Err(AbxError::ParseError(format!(
+                                "unexpected end tag </{}> at offset {}",
+                                name, offset
+                            )));
+                        }
+                    }
+                }
+                _ => {
+                    let text = input.read_token_payload(token)?;
+                    match command {
+                        Command::Text => Node::Text(text),
+                        Command::Cdsect => Node::Cdsect(text),
+                        Command::Comment => Node::Comment(text),
+                        Command::ProcessingInstruction => Node::ProcessingInstruction(text),
+                        Command::Docdecl => Node::Docdecl(text),
+                        Command::EntityRef => Node::EntityRef(text),
+                        _ => Node::IgnorableWhitespace(text),
+                    }
+                }
+            };
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => nodes.push(node),
+            }
+        }
+
+        if let Some(element) = stack.last() {
+            return Err(AbxError::ParseError(format!(
+                "END_DOCUMENT with <{}> still open",
+                element.name
+            )));
+        }
+
+        Ok(Self {
+            nodes,
+            interned: input.interned_strings().to_vec(),
+        })
+    }
+
+    /// Interned string table of the parsed stream, in definition order
+    ///
+    /// This is the table as parsed; edits to the tree don't change it. Empty
+    /// for documents built in memory.
+    pub fn interned_strings(&self) -> &[String] {
+        &self.interned
+    }
+
+    /// The first top-level element
+    pub fn root(&self) -> Option<&Element> {
+        self.nodes.iter().find_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// The first top-level element, mutably
+    pub fn root_mut(&mut self) -> Option<&mut Element> {
+        self.nodes.iter_mut().find_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// Encode the document as ABX
+    ///
+    /// Interned strings are defined at their first use in document order, the
+    /// same way Android's serializer does it. Any preservation of the
+    /// original table comes from that order alone: an unedited document
+    /// encodes byte-identical to its input, and edits keep the original
+    /// indices of every name first used before the edit. Names new to the
+    /// document get the next free index.
+    ///
+    /// The format has no way to define a string before its first use, so a
+    /// new name used before the last original first use, or removing the
+    /// first use of an original name, renumbers the names defined after it.
+    /// Use [`write_preserving_interning`](Self::write_preserving_interning)
+    /// to refuse such edits instead.
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        self.encode(AbxWriter::new(writer, &[]))
+    }
+
+    /// Encode the document as ABX, failing if any string of
+    /// [`interned_strings`](Self::interned_strings) would get a new index
+    ///
+    /// Encoding is the same as [`write_to`](Self::write_to). New names are
+    /// fine as long as they are first used after every original name is
+    /// defined. Nothing is written on failure.
+    pub fn write_preserving_interning<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut data = Vec::new();
+        self.encode(AbxWriter::new(&mut data, &self.interned))?;
+        writer.write_all(&data)?;
+        Ok(())
+    }
+
+    fn encode<W: Write>(&self, mut output: AbxWriter<'_, W>) -> Result<()> {
+        output.write_all(&PROTOCOL_MAGIC_VERSION_0)?;
+        output.write_byte(START_DOCUMENT | TYPE_NULL)?;
+        for node in &self.nodes {
+            output.write_node(node)?;
+        }
+        output.write_byte(END_DOCUMENT | TYPE_NULL)?;
+        Ok(())
+    }
+
+    /// Encode the document as ABX into a new buffer
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.write_to(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Decode the payload of an attribute whose type nibble is in `type_byte`
+///
+/// `input` must be positioned just after the attribute's name. Only the high
+/// nibble of `type_byte` is looked at, so the whole token byte can be passed.
+pub fn decode_attribute_value<R: Read, I: Interner>(
+    type_byte: u8,
+    input: &mut FastDataInput<R, I>,
+) -> Result<AttributeValue> {
+    Ok(match AttributeType::try_from(type_byte)? {
+        AttributeType::String => AttributeValue::String(input.read_utf()?),
+        AttributeType::StringInterned => AttributeValue::InternedString(input.read_interned_utf()?),
+        AttributeType::BytesHex => {
+            let length = input.read_unsigned_short()?;
+            AttributeValue::BytesHex(input.read_bytes(length)?)
+        }
+        AttributeType::BytesBase64 => {
+            let length = input.read_unsigned_short()?;
+            AttributeValue::BytesBase64(input.read_bytes(length)?)
+        }
+        AttributeType::Int => AttributeValue::Int(input.read_int()?),
+        AttributeType::IntHex => AttributeValue::IntHex(input.read_int()?),
+        AttributeType::Long => AttributeValue::Long(input.read_long()?),
+        AttributeType::LongHex => AttributeValue::LongHex(input.read_long()?),
+        AttributeType::Float => AttributeValue::Float(input.read_float()?),
+        AttributeType::Double => AttributeValue::Double(input.read_double()?),
+        AttributeType::BooleanTrue => AttributeValue::Boolean(true),
+        AttributeType::BooleanFalse => AttributeValue::Boolean(false),
+    })
+}
+
+/// Writes ABX primitives, mirroring `FastDataInput`
+struct AbxWriter<'a, W: Write> {
+    writer: W,
+    /// Index of each interned string defined so far
+    defined: HashMap<String, u16>,
+    /// Table that definitions must not renumber, if any
+    preserved: &'a [String],
+}
+
+impl<'a, W: Write> AbxWriter<'a, W> {
+    fn new(writer: W, preserved: &'a [String]) -> Self {
+        Self {
+            writer,
+            defined: HashMap::new(),
+            preserved,
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<()> {
+        self.write_all(&[byte])
+    }
+
+    fn write_short(&mut self, value: u16) -> Result<()> {
+        self.write_all(&value.to_be_bytes())
+    }
+
+    fn write_utf(&mut self, s: &str) -> Result<()> {
+        let length = u16::try_from(s.len())
+            .map_err(|_| AbxError::ParseError(format!("string too long: {} bytes", s.len())))?;
+        self.write_short(length)?;
+        self.write_all(s.as_bytes())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        let length = u16::try_from(bytes.len()).map_err(|_| {
+            AbxError::ParseError(format!("byte array too long: {} bytes", bytes.len()))
+        })?;
+        self.write_short(length)?;
+        self.write_all(bytes)
+    }
+
+    fn write_interned_utf(&mut self, s: &str) -> Result<()> {
+        if let Some(&index) = self.defined.get(s) {
+            return self.write_short(index);
+        }
+        // Once the table is full, strings are written inline every time
+        let index = self.defined.len();
+        if let Some(original) = self.preserved.get(index)
+            && original != s
+        {
+            return Err(AbxError::ParseError(format!(
+                "defining \"{}\" at interned index {} would renumber \"{}\"",
+                s, index, original
+            )));
+        }
+        if index < 0xFFFF {
+            self.defined.insert(s.to_string(), index as u16);
+        }
+        self.write_short(0xFFFF)?;
+        self.write_utf(s)
+    }
+
+    fn write_node(&mut self, node: &Node) -> Result<()> {
+        let (command, text) = match node {
+            Node::Element(element) => return self.write_element(element),
+            Node::Text(text) => (Command::Text, text),
+            Node::Cdsect(text) => (Command::Cdsect, text),
+            Node::Comment(text) => (Command::Comment, text),
+            Node::ProcessingInstruction(text) => (Command::ProcessingInstruction, text),
+            Node::Docdecl(text) => (Command::Docdecl, text),
+            Node::EntityRef(text) => (Command::EntityRef, text),
+            Node::IgnorableWhitespace(text) => (Command::IgnorableWhitespace, text),
+        };
+        self.write_byte(u8::from(command) | TYPE_STRING)?;
+        self.write_utf(text)
+    }
+
+    fn write_element(&mut self, element: &Element) -> Result<()> {
+        self.write_byte(START_TAG | TYPE_STRING_INTERNED)?;
+        self.write_interned_utf(&element.name)?;
+        for attribute in &element.attributes {
+            self.write_attribute(attribute)?;
+        }
+        for child in &element.children {
+            self.write_node(child)?;
+        }
+        self.write_byte(END_TAG | TYPE_STRING_INTERNED)?;
+        self.write_interned_utf(&element.name)
+    }
+
+    fn write_attribute(&mut self, attribute: &Attribute) -> Result<()> {
+        let value = &attribute.value;
+        self.write_byte(ATTRIBUTE | u8::from(value.attribute_type()))?;
+        self.write_interned_utf(&attribute.name)?;
+        match value {
+            AttributeValue::String(s) => self.write_utf(s),
+            AttributeValue::InternedString(s) => self.write_interned_utf(s),
+            AttributeValue::BytesHex(bytes) | AttributeValue::BytesBase64(bytes) => {
+                self.write_bytes(bytes)
+            }
+            AttributeValue::Int(v) | AttributeValue::IntHex(v) => self.write_all(&v.to_be_bytes()),
+            AttributeValue::Long(v) | AttributeValue::LongHex(v) => {
+                self.write_all(&v.to_be_bytes())
+            }
+            AttributeValue::Float(v) => self.write_all(&v.to_bits().to_be_bytes()),
+            AttributeValue::Double(v) => self.write_all(&v.to_bits().to_be_bytes()),
+            AttributeValue::Boolean(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+    use std::io::Cursor;
+
+    fn sample() -> Vec<u8> {
+        AbxFixture::new()
+            .start_tag("policies")
+            .start_tag("policy")
+            .attribute_string("name", "a")
+            .attribute_int("value", 1)
+            .end_tag("policy")
+            .start_tag("policy")
+            .attribute_bool("enabled", true)
+            .text("body")
+            .end_tag("policy")
+            .end_tag("policies")
+            .build()
+    }
+
+    #[test]
+    fn test_round_trip_is_byte_identical() {
+        let data = sample();
+        let document = AbxDocument::parse(Cursor::new(&data)).unwrap();
+        assert_eq!(
+            document.interned_strings(),
+            ["policies", "policy", "name", "value", "enabled"]
+        );
+        assert_eq!(document.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn test_edits_keep_interning_order() {
+        let data = sample();
+        let mut document = AbxDocument::parse(Cursor::new(&data)).unwrap();
+        let root = document.root_mut().unwrap();
+        let Node::Element(policy) = &mut root.children[0] else {
+            panic!("expected an element");
+        };
+        policy.attributes[1].value = AttributeValue::Int(2);
+        let mut added = Element::new("policy");
+        added.attributes.push(Attribute {
+            name: "extra".to_string(),
+            value: AttributeValue::Boolean(false),
+        });
+        root.children.push(Node::Element(added));
+
+        let encoded = document.to_bytes().unwrap();
+        // Everything up to the edited int is unchanged
+        let edit = data
+            .windows(4)
+            .position(|w| w == 1i32.to_be_bytes())
+            .unwrap();
+        assert_eq!(encoded[..edit], data[..edit]);
+
+        let reparsed = AbxDocument::parse(Cursor::new(&encoded)).unwrap();
+        assert_eq!(
+            reparsed.interned_strings(),
+            ["policies", "policy", "name", "value", "enabled", "extra"]
+        );
+        assert_eq!(
+            reparsed.root().unwrap().children[0],
+            document.root().unwrap().children[0]
+        );
+    }
+
+    #[test]
+    fn test_write_preserving_interning() {
+        let data = sample();
+        let mut document = AbxDocument::parse(Cursor::new(&data)).unwrap();
+        let mut output = Vec::new();
+        document.write_preserving_interning(&mut output).unwrap();
+        assert_eq!(output, data);
+
+        // A new name after every original definition is appended
+        let root = document.root_mut().unwrap();
+        root.children.push(Node::Element(Element::new("extra")));
+        let mut output = Vec::new();
+        document.write_preserving_interning(&mut output).unwrap();
+        assert_eq!(output, document.to_bytes().unwrap());
+
+        // One before "enabled" is first used would take its index
+        let root = document.root_mut().unwrap();
+        root.children
+            .insert(0, Node::Element(Element::new("early")));
+        let mut output = Vec::new();
+        let err = document
+            .write_preserving_interning(&mut output)
+            .unwrap_err();
+        assert!(err.to_string().contains("would renumber \"policy\""));
+        assert!(output.is_empty());
+        assert!(document.to_bytes().is_ok());
+    }
+
+    #[test]
+    fn test_needs_escaping() {
+        assert!(!AttributeValue::String("plain".to_string()).needs_escaping());
+        assert!(AttributeValue::String("a<b".to_string()).needs_escaping());
+        assert!(AttributeValue::InternedString("&amp;".to_string()).needs_escaping());
+        assert!(!AttributeValue::Int(0).needs_escaping());
+        assert_eq!(
+            AttributeValue::String("x'y".to_string()).needs_escaping(),
+            crate::encode_xml_entities("x'y") != "x'y"
+        );
+    }
+
+    fn decode(type_byte: u8, payload: &[u8]) -> AttributeValue {
+        let mut input = FastDataInput::new(Cursor::new(payload));
+        let value = decode_attribute_value(type_byte, &mut input).unwrap();
+        assert!(input.is_eof());
+        value
+    }
+
+    #[test]
+    fn test_decode_attribute_value() {
+        use crate::*;
+        let string = AttributeValue::String("hi".to_string());
+        assert_eq!(decode(TYPE_STRING, &[0, 2, b'h', b'i']), string);
+        assert_eq!(
+            decode(ATTRIBUTE | TYPE_STRING_INTERNED, &[0xFF, 0xFF, 0, 1, b'x']),
+            AttributeValue::InternedString("x".to_string())
+        );
+        assert_eq!(
+            decode(TYPE_BYTES_HEX, &[0, 2, 0xAB, 0xCD]),
+            AttributeValue::BytesHex(vec![0xAB, 0xCD])
+        );
+        assert_eq!(
+            decode(TYPE_BYTES_BASE64, &[0, 1, 7]),
+            AttributeValue::BytesBase64(vec![7])
+        );
+        assert_eq!(decode(TYPE_INT, &[0xFF; 4]), AttributeValue::Int(-1));
+        assert_eq!(
+            decode(TYPE_INT_HEX, &[0, 0, 1, 0]),
+            AttributeValue::IntHex(256)
+        );
+        assert_eq!(
+            decode(TYPE_LONG, &[0, 0, 0, 0, 0, 0, 0, 5]),
+            AttributeValue::Long(5)
+        );
+        assert_eq!(
+            decode(TYPE_LONG_HEX, &[0xFF; 8]),
+            AttributeValue::LongHex(-1)
+        );
+        assert_eq!(
+            decode(TYPE_FLOAT, &1.5f32.to_be_bytes()),
+            AttributeValue::Float(1.5)
+        );
+        assert_eq!(
+            decode(TYPE_DOUBLE, &0.25f64.to_be_bytes()),
+            AttributeValue::Double(0.25)
+        );
+        assert_eq!(
+            decode(TYPE_BOOLEAN_TRUE, &[]),
+            AttributeValue::Boolean(true)
+        );
+        assert_eq!(
+            decode(TYPE_BOOLEAN_FALSE, &[]),
+            AttributeValue::Boolean(false)
+        );
+
+        let mut input = FastDataInput::new(Cursor::new(&[][..]));
+        assert!(matches!(
+            decode_attribute_value(0xE0, &mut input),
+            Err(AbxError::UnknownAttributeType(0xE0))
+        ));
+        assert!(decode_attribute_value(TYPE_INT, &mut input).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_truncated_input() {
+        let data = AbxFixture::new().start_tag("a").build_unterminated();
+        assert!(AbxDocument::parse(Cursor::new(&data)).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Token-by-token listing of an ABX stream for reverse engineering
+
+use crate::binary_xml::read_magic;
+use crate::{Command, END_DOCUMENT, FastDataInput, KNOWN_MAGICS, Result};
+use crate::{TYPE_STRING_INTERNED, command_of, decode_attribute_value, describe_token, type_of};
+use std::io::{Read, Seek, Write};
+
+/// Write one line per token: input offset, token, and its payload
+///
+/// With `resolve_interned`, every interned string shows its table index, as
+/// `[idx=5 -> "name"]` for a reference and `[idx=5 new "name"]` for a
+/// definition; otherwise just the string is shown. The XML conversion itself
+/// is unaffected.
+///
+/// # Examples
+///
+/// ```no_run
+/// use honeycomb::dump_tokens;
+/// use std::fs::File;
+/// use std::io;
+///
+/// dump_tokens(File::open("input.abx").unwrap(), io::stdout(), true).unwrap();
+/// // 00000005  START_TAG  [idx=0 new "packages"]
+/// // 00000012  ATTRIBUTE|TYPE_INT  [idx=1 new "version"] = Int(3)
+/// ```
+pub fn dump_tokens<R: Read + Seek, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    resolve_interned: bool,
+) -> Result<()> {
+    read_magic(&mut reader, KNOWN_MAGICS)?;
+    let mut input = FastDataInput::new(reader);
+    while !input.is_eof() {
+        let offset = input.tell()?;
+        let (token, payload) = read_token(&mut input, resolve_interned)?;
+        write!(writer, "{:08X}  {}", offset, describe_token(token))?;
+        if !payload.is_empty() {
+            write!(writer, "  {}", payload)?;
+        }
+        writeln!(writer)?;
+        if command_of(token) == END_DOCUMENT {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The exact bytes of one token, from [`raw_token_log`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawToken {
+    /// Input offset of the token's first byte
+    pub offset: u64,
+    /// The token byte and its payload, as stored
+    pub bytes: Vec<u8>,
+    /// Token name as given by `describe_token`, or `MAGIC` for the header
+    pub description: String,
+}
+
+/// Split an ABX stream into the raw bytes of each token
+///
+/// The first entry is the 4-byte magic header. Attributes are entries of
+/// their own, and concatenating every entry's `bytes` gives back the stream
+/// up to `END_DOCUMENT`. Unlike [`dump_tokens`] this is meant for programs,
+/// e.g. to hash or patch specific regions.
+///
+/// # Examples
+///
+/// ```no_run
+/// use honeycomb::raw_token_log;
+/// use std::fs::File;
+///
+/// for token in raw_token_log(File::open("0.xml").unwrap()).unwrap() {
+///     println!("{:08X} {} {}", token.offset, token.description, hex::encode(&token.bytes));
+/// }
+/// ```
+pub fn raw_token_log<R: Read + Seek>(mut reader: R) -> Result<Vec<RawToken>> {
+    let magic = read_magic(&mut reader, KNOWN_MAGICS)?;
+    let mut tokens = vec![RawToken {
+        offset: 0,
+        bytes: magic.to_vec(),
+        description: "MAGIC".to_string(),
+    }];
+    let mut input = FastDataInput::new(reader);
+    while !input.is_eof() {
+        let offset = input.tell()?;
+        let (token, _) = read_token(&mut input, false)?;
+        let end = input.tell()?;
+        input.seek(offset)?;
+        // A token can be longer than one `read_bytes` call allows
+        let mut bytes = Vec::new();
+        let mut remaining = end - offset;
+        while remaining > 0 {
+            let chunk = remaining.min(u16::MAX as u64);
+            bytes.extend(input.read_bytes(chunk as u16)?);
+            remaining -= chunk;
+        }
+        tokens.push(RawToken {
+            offset,
+            bytes,
+            description: describe_token(token),
+        });
+        if command_of(token) == END_DOCUMENT {
+            break;
+        }
+    }
+    Ok(tokens)
+}
+
+/// Read one token, returning its byte and a rendering of its payload
+fn read_token<R: Read + Seek>(
+    input: &mut FastDataInput<R>,
+    resolve_interned: bool,
+) -> Result<(u8, String)> {
+    let token = input.read_byte()?;
+    let command = Command::try_from(token)?;
+    let payload = match command {
+        Command::StartDocument | Command::EndDocument => String::new(),
+        Command::StartTag | Command::EndTag => interned(input, resolve_interned)?,
+        Command::Attribute => {
+            let name = interned(input, resolve_interned)?;
+            let value = if type_of(token) == TYPE_STRING_INTERNED {
+                interned(input, resolve_interned)?
+            } else {
+                format!("{:?}", decode_attribute_value(token, input)?)
+            };
+            format!("{} = {}", name, value)
+        }
+        Command::Text | Command::Cdsect if type_of(token) == TYPE_STRING_INTERNED => {
+            interned(input, resolve_interned)?
+        }
+        _ => format!("{:?}", input.read_token_payload(token)?),
+    };
+    Ok((token, payload))
+}
+
+/// Read an interned string, rendered with its index when `resolve` is set
+fn interned<R: Read + Seek>(input: &mut FastDataInput<R>, resolve: bool) -> Result<String> {
+    let start = input.tell()?;
+    let index = input.read_interned_index()?;
+    input.seek(start)?;
+    let count = input.interned_count();
+    let string = input.read_interned_utf()?;
+    if !resolve {
+        return Ok(format!("{:?}", string));
+    }
+    Ok(match index {
+        Some(index) => format!("[idx={} -> {:?}]", index, string),
+        None if input.interned_count() > count => format!("[idx={} new {:?}]", count, string),
+        // The table is full, so the string isn't stored
+        None => format!("[inline {:?}]", string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_dump_tokens() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_int("x", 3)
+            .text("hi")
+            .end_tag("a")
+            .build();
+
+        let mut output = Vec::new();
+        dump_tokens(Cursor::new(&data), &mut output, true).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "00000004  START_DOCUMENT",
+                "00000005  START_TAG  [idx=0 new \"a\"]",
+                "0000000B  ATTRIBUTE|TYPE_INT  [idx=1 new \"x\"] = Int(3)",
+                "00000015  TEXT  \"hi\"",
+                "0000001A  END_TAG  [idx=0 -> \"a\"]",
+                "0000001D  END_DOCUMENT",
+            ]
+        );
+
+        let mut output = Vec::new();
+        dump_tokens(Cursor::new(&data), &mut output, false).unwrap();
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .contains("END_TAG  \"a\"")
+        );
+    }
+
+    #[test]
+    fn test_raw_token_log() {
+        let data = AbxFixture::new()
+            .start_tag("a")
+            .attribute_int("x", 3)
+            .end_tag("a")
+            .build();
+
+        let tokens = raw_token_log(Cursor::new(&data)).unwrap();
+        let descriptions: Vec<&str> = tokens.iter().map(|t| t.description.as_str()).collect();
+        assert_eq!(
+            descriptions,
+            [
+                "MAGIC",
+                "START_DOCUMENT",
+                "START_TAG",
+                "ATTRIBUTE|TYPE_INT",
+                "END_TAG",
+                "END_DOCUMENT"
+            ]
+        );
+        assert_eq!(tokens[3].offset, 0x0B);
+        assert_eq!(tokens[3].bytes.len(), 1 + 2 + 2 + 1 + 4);
+        assert_eq!(
+            tokens
+                .iter()
+                .flat_map(|t| t.bytes.clone())
+                .collect::<Vec<u8>>(),
+            data
+        );
+    }
+}
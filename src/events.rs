@@ -0,0 +1,181 @@
+//! Adapter from decoded ABX tokens to `quick_xml` events
+
+use crate::{BinaryXmlDeserializer, Result, TokenHandler, Warning};
+use quick_xml::events::{BytesCData, BytesDecl, BytesEnd, BytesPI, BytesRef, BytesStart};
+use quick_xml::events::{BytesText, Event};
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+/// Iterator over the `quick_xml` events of an ABX document
+///
+/// Returned by
+/// [`AbxToXmlConverter::to_quick_xml_events`](crate::AbxToXmlConverter::to_quick_xml_events).
+/// Tokens are decoded as events are pulled, so only the current element's
+/// start tag is held in memory. An error ends the iteration.
+pub struct QuickXmlEvents<R: Read> {
+    deserializer: Option<BinaryXmlDeserializer<R, io::Sink>>,
+    collector: EventCollector,
+    /// Error to yield next, ending the iteration
+    error: Option<crate::AbxError>,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: Read> QuickXmlEvents<R> {
+    pub(crate) fn new(
+        deserializer: Result<BinaryXmlDeserializer<R, io::Sink>>,
+        keep_empty_text: bool,
+    ) -> Self {
+        let (deserializer, error) = match deserializer {
+            Ok(deserializer) => (Some(deserializer), None),
+            Err(e) => (None, Some(e)),
+        };
+        Self {
+            deserializer,
+            collector: EventCollector::new(keep_empty_text),
+            error,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Problems recovered from so far, as with
+    /// [`BinaryXmlDeserializer::warnings`]
+    pub fn warnings(&self) -> &[Warning] {
+        self.deserializer
+            .as_ref()
+            .map_or(&[], |deserializer| deserializer.warnings())
+    }
+
+    /// Decode the next token into the collector
+    fn advance(&mut self) -> Result<()> {
+        let Some(deserializer) = &mut self.deserializer else {
+            self.finished = true;
+            return Ok(());
+        };
+        if !self.started {
+            self.started = true;
+            deserializer.begin()?;
+        }
+        if !deserializer.step(&mut self.collector)? {
+            self.collector.flush_pending();
+            self.finished = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for QuickXmlEvents<R> {
+    type Item = Result<Event<'static>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.error.take() {
+                self.finished = true;
+                self.collector.events.clear();
+                return Some(Err(e));
+            }
+            if let Some(event) = self.collector.events.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.finished {
+                return None;
+            }
+            if let Err(e) = self.advance() {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Collects tokens as the `quick_xml` events a reader of the converted XML
+/// would see
+///
+/// A start tag is held back until the next non-attribute token, since
+/// `Event::Start` carries all of the element's attributes.
+struct EventCollector {
+    events: VecDeque<Event<'static>>,
+    pending: Option<BytesStart<'static>>,
+    keep_empty_text: bool,
+}
+
+impl EventCollector {
+    /// With `keep_empty_text`, empty `TEXT` tokens become empty `Text`
+    /// events instead of being dropped like a reparse would
+    fn new(keep_empty_text: bool) -> Self {
+        Self {
+            events: VecDeque::from([Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None))]),
+            pending: None,
+            keep_empty_text,
+        }
+    }
+
+    fn push(&mut self, event: Event<'static>) {
+        self.flush_pending();
+        self.events.push_back(event);
+    }
+
+    /// Release a start tag still waiting for its attributes
+    fn flush_pending(&mut self) {
+        if let Some(start) = self.pending.take() {
+            self.events.push_back(Event::Start(start));
+        }
+    }
+}
+
+impl TokenHandler for EventCollector {
+    fn start_tag(&mut self, name: &str) -> Result<()> {
+        self.flush_pending();
+        self.pending = Some(BytesStart::new(name.to_string()));
+        Ok(())
+    }
+
+    fn attribute(&mut self, name: &str, value: &str) -> Result<()> {
+        if let Some(start) = &mut self.pending {
+            start.push_attribute((name, value));
+        }
+        Ok(())
+    }
+
+    fn end_tag(&mut self, name: &str) -> Result<()> {
+        self.push(Event::End(BytesEnd::new(name.to_string())));
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<()> {
+        if !text.is_empty() || self.keep_empty_text {
+            self.push(Event::Text(BytesText::new(text).into_owned()));
+        }
+        Ok(())
+    }
+
+    fn cdata(&mut self, text: &str) -> Result<()> {
+        self.push(Event::CData(BytesCData::new(text.to_string())));
+        Ok(())
+    }
+
+    fn comment(&mut self, text: &str) -> Result<()> {
+        self.push(Event::Comment(BytesText::from_escaped(text.to_string())));
+        Ok(())
+    }
+
+    fn processing_instruction(&mut self, text: &str) -> Result<()> {
+        self.push(Event::PI(BytesPI::new(text.to_string())));
+        Ok(())
+    }
+
+    fn docdecl(&mut self, text: &str) -> Result<()> {
+        self.push(Event::DocType(BytesText::from_escaped(text.to_string())));
+        Ok(())
+    }
+
+    fn entity_ref(&mut self, name: &str) -> Result<()> {
+        self.push(Event::GeneralRef(BytesRef::new(name.to_string())));
+        Ok(())
+    }
+
+    fn ignorable_whitespace(&mut self, text: &str) -> Result<()> {
+        self.push(Event::Text(BytesText::from_escaped(text.to_string())));
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! C ABI over the token parser, enabled with the `ffi` feature
+//!
+//! Strings are handed to callbacks as pointer + length pairs rather than
+//! NUL-terminated strings, since ABX strings may contain embedded NULs. The
+//! pointers are only valid for the duration of the callback.
+
+use crate::{AbxToXmlConverter, Result, TokenHandler};
+use std::ffi::c_void;
+use std::io::Cursor;
+
+/// Callback receiving a single string (tag name or text)
+pub type HoneycombStringCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, data: *const u8, len: usize)>;
+
+/// Callback receiving an attribute name and value
+pub type HoneycombAttributeCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        name: *const u8,
+        name_len: usize,
+        value: *const u8,
+        value_len: usize,
+    ),
+>;
+
+/// Set of callbacks mirroring [`TokenHandler`]; any callback may be null
+#[repr(C)]
+pub struct HoneycombCallbacks {
+    /// Opaque pointer passed back to every callback
+    pub user_data: *mut c_void,
+    pub start_tag: HoneycombStringCallback,
+    pub attribute: HoneycombAttributeCallback,
+    pub end_tag: HoneycombStringCallback,
+    pub text: HoneycombStringCallback,
+}
+
+/// Parsed successfully
+pub const HONEYCOMB_OK: i32 = 0;
+/// A null pointer was passed
+pub const HONEYCOMB_ERR_NULL: i32 = -1;
+/// The input could not be parsed
+pub const HONEYCOMB_ERR_PARSE: i32 = -2;
+
+struct CallbackHandler<'a> {
+    callbacks: &'a HoneycombCallbacks,
+}
+
+impl CallbackHandler<'_> {
+    fn emit(&self, callback: HoneycombStringCallback, s: &str) {
+        if let Some(callback) = callback {
+            // SAFETY: the caller of `honeycomb_parse` guarantees the callbacks are valid
+            unsafe { callback(self.callbacks.user_data, s.as_ptr(), s.len()) }
+        }
+    }
+}
+
+impl TokenHandler for CallbackHandler<'_> {
+    fn start_tag(&mut self, name: &str) -> Result<()> {
+        self.emit(self.callbacks.start_tag, name);
+        Ok(())
+    }
+
+    fn attribute(&mut self, name: &str, value: &str) -> Result<()> {
+        if let Some(callback) = self.callbacks.attribute {
+            // SAFETY: the caller of `honeycomb_parse` guarantees the callbacks are valid
+            unsafe {
+                callback(
+                    self.callbacks.user_data,
+                    name.as_ptr(),
+                    name.len(),
+                    value.as_ptr(),
+                    value.len(),
+                )
+            }
+        }
+        Ok(())
+    }
+
+    fn end_tag(&mut self, name: &str) -> Result<()> {
+        self.emit(self.callbacks.end_tag, name);
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<()> {
+        self.emit(self.callbacks.text, text);
+        Ok(())
+    }
+}
+
+/// Parse an in-memory ABX document, invoking `callbacks` for each token
+///
+/// Returns [`HONEYCOMB_OK`] on success or a negative error code.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `callbacks` must point to a
+/// valid `HoneycombCallbacks`, and every non-null callback must be safe to
+/// call with the provided `user_data`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn honeycomb_parse(
+    data: *const u8,
+    len: usize,
+    callbacks: *const HoneycombCallbacks,
+) -> i32 {
+    if data.is_null() || callbacks.is_null() {
+        return HONEYCOMB_ERR_NULL;
+    }
+
+    // SAFETY: checked for null above; validity is the caller's contract
+    let (bytes, callbacks) = unsafe { (std::slice::from_raw_parts(data, len), &*callbacks) };
+    let mut handler = CallbackHandler { callbacks };
+
+    match AbxToXmlConverter::parse_with_handler(Cursor::new(bytes), &mut handler) {
+        Ok(()) => HONEYCOMB_OK,
+        Err(_) => HONEYCOMB_ERR_PARSE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ATTRIBUTE, END_DOCUMENT, END_TAG, PROTOCOL_MAGIC_VERSION_0, START_DOCUMENT, START_TAG,
+        TYPE_STRING, TYPE_STRING_INTERNED,
+    };
+
+    unsafe extern "C" fn record(user_data: *mut c_void, data: *const u8, len: usize) {
+        let events = unsafe { &mut *(user_data as *mut Vec<Vec<u8>>) };
+        events.push(unsafe { std::slice::from_raw_parts(data, len) }.to_vec());
+    }
+
+    unsafe extern "C" fn record_attribute(
+        user_data: *mut c_void,
+        name: *const u8,
+        name_len: usize,
+        value: *const u8,
+        value_len: usize,
+    ) {
+        let events = unsafe { &mut *(user_data as *mut Vec<Vec<u8>>) };
+        let mut event = unsafe { std::slice::from_raw_parts(name, name_len) }.to_vec();
+        event.push(b'=');
+        event.extend_from_slice(unsafe { std::slice::from_raw_parts(value, value_len) });
+        events.push(event);
+    }
+
+    #[test]
+    fn test_parse_invokes_callbacks() {
+        let mut data = PROTOCOL_MAGIC_VERSION_0.to_vec();
+        data.push(START_DOCUMENT | TYPE_STRING);
+        data.push(START_TAG | TYPE_STRING_INTERNED);
+        data.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x01, b'r']);
+        data.push(ATTRIBUTE | TYPE_STRING);
+        data.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x01, b'k']);
+        data.extend_from_slice(&[0x00, 0x03, b'a', 0x00, b'b']);
+        data.push(END_TAG | TYPE_STRING_INTERNED);
+        data.extend_from_slice(&[0x00, 0x00]);
+        data.push(END_DOCUMENT | TYPE_STRING);
+
+        let mut events: Vec<Vec<u8>> = Vec::new();
+        let callbacks = HoneycombCallbacks {
+            user_data: &mut events as *mut Vec<Vec<u8>> as *mut c_void,
+            start_tag: Some(record),
+            attribute: Some(record_attribute),
+            end_tag: Some(record),
+            text: None,
+        };
+
+        let status = unsafe { honeycomb_parse(data.as_ptr(), data.len(), &callbacks) };
+        assert_eq!(status, HONEYCOMB_OK);
+        assert_eq!(
+            events,
+            vec![b"r".to_vec(), b"k=a\0b".to_vec(), b"r".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_null() {
+        let status = unsafe { honeycomb_parse(std::ptr::null(), 0, std::ptr::null()) };
+        assert_eq!(status, HONEYCOMB_ERR_NULL);
+    }
+}
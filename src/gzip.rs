@@ -0,0 +1,542 @@
+//! Minimal streaming gzip decoder, for `.tar.gz` dumps
+//!
+//! Implements DEFLATE (RFC 1951) inside a single gzip member (RFC 1952):
+//! stored, fixed and dynamic Huffman blocks, with the trailing CRC-32 and
+//! size checked at the end. Codes are decoded a bit at a time, which is slow
+//! next to zlib but plenty for profile-sized archives.
+
+use std::io::{self, Read};
+
+/// First two bytes of every gzip stream
+pub(crate) const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// How far back a DEFLATE distance can reach
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// Bytes decoded per refill before `read` hands them out
+const CHUNK_SIZE: usize = 16 * 1024;
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order the code length code lengths of a dynamic block are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("gzip: {}", message))
+}
+
+/// Canonical Huffman code, as symbol counts per code length and the symbols
+/// in code order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        // Reject codes with more codes of some length than the shorter
+        // lengths leave room for
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(invalid("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn fixed() -> (Self, Self) {
+        let mut lengths = [8u8; 288];
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        let literals = Self::new(&lengths).expect("the fixed code is complete");
+        let distances = Self::new(&[5u8; 30]).expect("the fixed code is complete");
+        (literals, distances)
+    }
+}
+
+/// Bits of the compressed stream, least significant first
+struct BitReader<R: Read> {
+    reader: R,
+    buffer: u32,
+    count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8];
+        match self.reader.read_exact(&mut byte) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(invalid("stream ends before the end of the compressed data"))
+            }
+            result => result.map(|()| byte[0]),
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            self.buffer |= (self.byte()? as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drop the rest of the current byte
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+
+    fn u16_le(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes([
+            self.byte()?,
+            self.byte()?,
+            self.byte()?,
+            self.byte()?,
+        ]))
+    }
+
+    fn decode(&mut self, code: &Huffman) -> io::Result<u16> {
+        // Canonical codes of each length are consecutive, so walk the
+        // lengths until the code read so far falls in that length's range
+        let (mut value, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS {
+            value |= self.bits(1)? as i32;
+            let count = code.counts[length] as i32;
+            if value - first < count {
+                return Ok(code.symbols[(index + value - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            value <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+enum State {
+    /// Expecting the gzip header
+    Header,
+    /// Expecting a block header; `last` once the final block has been read
+    Block {
+        last: bool,
+    },
+    Stored {
+        remaining: u16,
+        last: bool,
+    },
+    Compressed {
+        literals: Huffman,
+        distances: Huffman,
+        last: bool,
+    },
+    Done,
+}
+
+/// Decompresses a gzip stream as it is read
+pub(crate) struct GzipReader<R: Read> {
+    input: BitReader<R>,
+    state: State,
+    /// Decoded bytes, keeping at least the last `WINDOW_SIZE` for distances
+    window: Vec<u8>,
+    /// Start of the bytes in `window` not returned by `read` yet
+    unread: usize,
+    crc: u32,
+    size: u32,
+}
+
+impl<R: Read> GzipReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            input: BitReader {
+                reader,
+                buffer: 0,
+                count: 0,
+            },
+            state: State::Header,
+            window: Vec::new(),
+            unread: 0,
+            crc: !0,
+            size: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut fixed = [0u8; 10];
+        for byte in &mut fixed {
+            *byte = self.input.byte()?;
+        }
+        if fixed[..2] != GZIP_MAGIC {
+            return Err(invalid("not a gzip stream"));
+        }
+        if fixed[2] != 8 {
+            return Err(invalid(&format!("unknown compression method {}", fixed[2])));
+        }
+        let flags = fixed[3];
+        if flags & 0xE0 != 0 {
+            return Err(invalid("reserved header flags set"));
+        }
+        if flags & 0x04 != 0 {
+            let length = self.input.u16_le()?;
+            for _ in 0..length {
+                self.input.byte()?;
+            }
+        }
+        // File name, then comment, both zero-terminated
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                while self.input.byte()? != 0 {}
+            }
+        }
+        if flags & 0x02 != 0 {
+            self.input.u16_le()?;
+        }
+        Ok(())
+    }
+
+    fn read_block_header(&mut self) -> io::Result<State> {
+        let last = self.input.bits(1)? == 1;
+        match self.input.bits(2)? {
+            0 => {
+                self.input.align();
+                let length = self.input.u16_le()?;
+                if self.input.u16_le()? != !length {
+                    return Err(invalid("stored block length check failed"));
+                }
+                Ok(State::Stored {
+                    remaining: length,
+                    last,
+                })
+            }
+            1 => {
+                let (literals, distances) = Huffman::fixed();
+                Ok(State::Compressed {
+                    literals,
+                    distances,
+                    last,
+                })
+            }
+            2 => {
+                let (literals, distances) = self.read_dynamic_codes()?;
+                Ok(State::Compressed {
+                    literals,
+                    distances,
+                    last,
+                })
+            }
+            _ => Err(invalid("invalid block type")),
+        }
+    }
+
+    fn read_dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let literal_count = self.input.bits(5)? as usize + 257;
+        let distance_count = self.input.bits(5)? as usize + 1;
+        let length_count = self.input.bits(4)? as usize + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(invalid("too many length or distance codes"));
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..length_count] {
+            code_lengths[symbol] = self.input.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0u8; literal_count + distance_count];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = self.input.decode(&code_lengths)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    let Some(&previous) = i.checked_sub(1).map(|p| &lengths[p]) else {
+                        return Err(invalid("repeated length with no previous length"));
+                    };
+                    (previous, 3 + self.input.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.input.bits(3)? as usize),
+                _ => (0, 11 + self.input.bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(invalid("too many code lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(invalid("no end-of-block code"));
+        }
+        let (literals, distances) = lengths.split_at(literal_count);
+        Ok((Huffman::new(literals)?, Huffman::new(distances)?))
+    }
+
+    /// Decode until at least one byte is ready or the stream has ended
+    fn refill(&mut self) -> io::Result<()> {
+        // Keep the window from growing without bound
+        if self.window.len() > 2 * WINDOW_SIZE {
+            let drop = self.window.len() - WINDOW_SIZE;
+            self.window.drain(..drop);
+            self.unread -= drop;
+        }
+        let produced_from = self.window.len();
+        while self.window.len() == produced_from {
+            match std::mem::replace(&mut self.state, State::Done) {
+                State::Header => {
+                    self.read_header()?;
+                    self.state = State::Block { last: false };
+                }
+                State::Block { last: true } => {
+                    self.finish()?;
+                    return Ok(());
+                }
+                State::Block { last: false } => self.state = self.read_block_header()?,
+                State::Stored { remaining, last } => {
+                    let take = remaining.min(CHUNK_SIZE as u16);
+                    for _ in 0..take {
+                        let byte = self.input.byte()?;
+                        self.window.push(byte);
+                    }
+                    self.state = if remaining == take {
+                        State::Block { last }
+                    } else {
+                        State::Stored {
+                            remaining: remaining - take,
+                            last,
+                        }
+                    };
+                }
+                State::Compressed {
+                    literals,
+                    distances,
+                    last,
+                } => {
+                    let block_done = self.inflate_chunk(&literals, &distances)?;
+                    self.state = if block_done {
+                        State::Block { last }
+                    } else {
+                        State::Compressed {
+                            literals,
+                            distances,
+                            last,
+                        }
+                    };
+                }
+                State::Done => return Ok(()),
+            }
+        }
+        let produced = &self.window[produced_from..];
+        self.crc = crc32_update(self.crc, produced);
+        self.size = self.size.wrapping_add(produced.len() as u32);
+        Ok(())
+    }
+
+    /// Decode symbols of a Huffman block until a chunk is produced, returning
+    /// whether the end of the block was reached
+    fn inflate_chunk(&mut self, literals: &Huffman, distances: &Huffman) -> io::Result<bool> {
+        let target = self.window.len() + CHUNK_SIZE;
+        while self.window.len() < target {
+            let symbol = self.input.decode(literals)? as usize;
+            match symbol {
+                0..=255 => self.window.push(symbol as u8),
+                256 => return Ok(true),
+                _ => {
+                    let index = symbol - 257;
+                    if index >= LENGTH_BASE.len() {
+                        return Err(invalid("invalid length code"));
+                    }
+                    let length = LENGTH_BASE[index] as usize
+                        + self.input.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                    let index = self.input.decode(distances)? as usize;
+                    if index >= DISTANCE_BASE.len() {
+                        return Err(invalid("invalid distance code"));
+                    }
+                    let distance = DISTANCE_BASE[index] as usize
+                        + self.input.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                    if distance > self.window.len() {
+                        return Err(invalid("distance reaches before the start of the data"));
+                    }
+                    // Copied a byte at a time, since the source may overlap
+                    // the bytes being written
+                    let start = self.window.len() - distance;
+                    for i in 0..length {
+                        let byte = self.window[start + i];
+                        self.window.push(byte);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Check the trailer against what was decoded
+    fn finish(&mut self) -> io::Result<()> {
+        self.input.align();
+        let crc = self.input.u32_le()?;
+        let size = self.input.u32_le()?;
+        if crc != !self.crc {
+            return Err(invalid("CRC mismatch"));
+        }
+        if size != self.size {
+            return Err(invalid("length mismatch"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.unread == self.window.len() {
+            self.refill()?;
+        }
+        let available = &self.window[self.unread..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.unread += n;
+        Ok(n)
+    }
+}
+
+/// Continue a CRC-32 (IEEE) over `data`; start from `!0` and invert the result
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// Wrap `data` in a gzip stream of stored blocks, for tests
+#[cfg(test)]
+pub(crate) fn gzip_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF];
+    let mut chunks = data.chunks(0xFFFF).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(chunks.peek().is_none() as u8);
+        let length = chunk.len() as u16;
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&(!length).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&(!crc32_update(!0, data)).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        GzipReader::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_stored_blocks() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        assert_eq!(gunzip(&gzip_stored(&data)).unwrap(), data);
+        assert_eq!(gunzip(&gzip_stored(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_fixed_huffman() {
+        // gzip -9n of "hello hello hello\n"
+        let data = [
+            0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xCB, 0x48, 0xCD, 0xC9,
+            0xC9, 0x57, 0xC8, 0x40, 0x90, 0x5C, 0x00, 0x3B, 0x7C, 0x8A, 0xDF, 0x12, 0x00, 0x00,
+            0x00,
+        ];
+        assert_eq!(gunzip(&data).unwrap(), b"hello hello hello\n");
+    }
+
+    #[test]
+    fn test_dynamic_huffman() {
+        let expected: Vec<u8> = (0..20)
+            .flat_map(|i| {
+                format!("<policy name=\"p{}\" value=\"{}\"/>\n", i % 37, i % 5).into_bytes()
+            })
+            .collect();
+        // gzip -9n of `expected`
+        let data = [
+            0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x85, 0xD0, 0x3B, 0x0A,
+            0x80, 0x30, 0x14, 0x44, 0xD1, 0xDE, 0x55, 0x84, 0x6C, 0xC0, 0x4C, 0x12, 0x7F, 0xA0,
+            0xEE, 0x25, 0x88, 0x85, 0x10, 0x35, 0x8D, 0x82, 0xBB, 0xD7, 0xEA, 0x81, 0x12, 0xC6,
+            0x6E, 0xE0, 0x36, 0xC3, 0xE9, 0xD3, 0x1E, 0x97, 0xE9, 0x52, 0x5B, 0x58, 0xE7, 0x41,
+            0x27, 0xA3, 0xD5, 0x19, 0xE2, 0xF1, 0x4C, 0xA3, 0xCB, 0xB1, 0xE8, 0xDF, 0x15, 0x52,
+            0x91, 0xA9, 0x56, 0xAA, 0xCD, 0x54, 0x27, 0xD5, 0x65, 0xAA, 0x97, 0xEA, 0x33, 0xB5,
+            0xA2, 0xAF, 0x6A, 0xFA, 0xAA, 0xA1, 0xAF, 0x5A, 0xFA, 0xAA, 0xA3, 0xAF, 0xF0, 0x83,
+            0xC5, 0xB5, 0xC0, 0xB9, 0xC0, 0xBD, 0xC0, 0xC1, 0xC0, 0xC5, 0xC0, 0xC9, 0xC0, 0xCD,
+            0xC0, 0xD1, 0xF0, 0x51, 0xBB, 0x01, 0x5D, 0x03, 0xA2, 0x39, 0x62, 0x02, 0x00, 0x00,
+        ];
+        assert_eq!(gunzip(&data).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_corrupt_streams() {
+        let mut data = gzip_stored(b"hello");
+        let crc = data.len() - 8;
+        data[crc] ^= 1;
+        assert!(
+            gunzip(&data)
+                .unwrap_err()
+                .to_string()
+                .contains("CRC mismatch")
+        );
+
+        let data = gzip_stored(b"hello");
+        let err = gunzip(&data[..data.len() - 3]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut data = gzip_stored(b"hello");
+        data[2] = 9;
+        assert!(gunzip(&data).is_err());
+    }
+}
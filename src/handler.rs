@@ -0,0 +1,62 @@
+use crate::Result;
+
+/// Receives decoded ABX tokens as the deserializer walks the stream
+///
+/// Every method has a no-op default, so implementors only override the
+/// events they care about. Strings are passed decoded, without XML escaping.
+pub trait TokenHandler {
+    /// Called when an element starts, before any of its attributes
+    fn start_tag(&mut self, _name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once per attribute of the most recently started element
+    fn attribute(&mut self, _name: &str, _value: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when an element ends
+    fn end_tag(&mut self, _name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for character data
+    fn text(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for CDATA sections
+    fn cdata(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for comments
+    fn comment(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for processing instructions
+    fn processing_instruction(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for document type declarations
+    fn docdecl(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for entity references, with the entity name
+    fn entity_ref(&mut self, _name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for ignorable whitespace
+    fn ignorable_whitespace(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A handler that ignores every token
+pub struct NoopHandler;
+
+impl TokenHandler for NoopHandler {}
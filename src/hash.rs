@@ -0,0 +1,249 @@
+//! Structural hashing of ABX documents
+
+use crate::{AbxDocument, AttributeValue, Element, Node, Result};
+use std::io::Cursor;
+
+/// SHA-256 of the document's semantic content
+///
+/// Differences that don't change meaning are ignored: interning order,
+/// attribute order, plain vs interned strings, decimal vs hex integers, base64
+/// vs hex byte arrays, comments and ignorable whitespace. Element names,
+/// attribute names and values, text and nesting all contribute.
+pub fn structural_hash(abx_data: &[u8]) -> Result<[u8; 32]> {
+    let document = AbxDocument::parse(Cursor::new(abx_data))?;
+    Ok(document_hash(&document))
+}
+
+/// SHA-256 of an already parsed document, see [`structural_hash`]
+pub fn document_hash(document: &AbxDocument) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for node in &document.nodes {
+        hash_node(&mut hasher, node);
+    }
+    hasher.finish()
+}
+
+fn hash_node(hasher: &mut Sha256, node: &Node) {
+    match node {
+        Node::Element(element) => hash_element(hasher, element),
+        Node::Text(text) | Node::Cdsect(text) => hash_field(hasher, b'T', text.as_bytes()),
+        Node::EntityRef(name) => hash_field(hasher, b'R', name.as_bytes()),
+        Node::ProcessingInstruction(text) => hash_field(hasher, b'P', text.as_bytes()),
+        Node::Docdecl(text) => hash_field(hasher, b'D', text.as_bytes()),
+        Node::Comment(_) | Node::IgnorableWhitespace(_) => {}
+    }
+}
+
+fn hash_element(hasher: &mut Sha256, element: &Element) {
+    hash_field(hasher, b'<', element.name.as_bytes());
+
+    let mut attributes: Vec<(&str, String)> = element
+        .attributes
+        .iter()
+        .map(|a| (a.name.as_str(), canonical_value(&a.value)))
+        .collect();
+    attributes.sort();
+    for (name, value) in attributes {
+        hash_field(hasher, b'@', name.as_bytes());
+        hash_field(hasher, b'=', value.as_bytes());
+    }
+
+    for child in &element.children {
+        hash_node(hasher, child);
+    }
+    hash_field(hasher, b'>', b"");
+}
+
+/// Tag and length-prefix each field so distinct trees can't produce the same
+/// byte sequence
+fn hash_field(hasher: &mut Sha256, tag: u8, bytes: &[u8]) {
+    hasher.update(&[tag]);
+    hasher.update(&(bytes.len() as u64).to_be_bytes());
+    hasher.update(bytes);
+}
+
+/// Value rendering that is the same however the value was encoded
+pub(crate) fn canonical_value(value: &AttributeValue) -> String {
+    match value {
+        AttributeValue::String(s) | AttributeValue::InternedString(s) => format!("s:{}", s),
+        AttributeValue::BytesHex(bytes) | AttributeValue::BytesBase64(bytes) => {
+            format!("x:{}", hex::encode(bytes))
+        }
+        AttributeValue::Int(v) | AttributeValue::IntHex(v) => format!("i:{}", v),
+        AttributeValue::Long(v) | AttributeValue::LongHex(v) => format!("l:{}", v),
+        AttributeValue::Float(v) => format!("f:{:08x}", v.to_bits()),
+        AttributeValue::Double(v) => format!("d:{:016x}", v.to_bits()),
+        AttributeValue::Boolean(v) => format!("b:{}", v),
+    }
+}
+
+/// SHA-256 of an attribute value's canonical form
+pub(crate) fn value_hash(value: &AttributeValue) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(canonical_value(value).as_bytes());
+    hasher.finish()
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Minimal streaming SHA-256 (FIPS 180-4)
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+
+    fn sha256(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hex::encode(hasher.finish())
+    }
+
+    #[test]
+    fn test_sha256_vectors() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn test_structural_hash_ignores_insignificant_differences() {
+        let a = AbxFixture::new()
+            .start_tag("root")
+            .attribute_int("x", 1)
+            .attribute_string("y", "v")
+            .start_tag("child")
+            .end_tag("child")
+            .end_tag("root")
+            .build();
+        // Different attribute order, so a different interning order too
+        let b = AbxFixture::new()
+            .start_tag("root")
+            .attribute_string("y", "v")
+            .attribute_int("x", 1)
+            .start_tag("child")
+            .end_tag("child")
+            .end_tag("root")
+            .build();
+        let c = AbxFixture::new()
+            .start_tag("root")
+            .attribute_string("y", "v")
+            .attribute_int("x", 2)
+            .start_tag("child")
+            .end_tag("child")
+            .end_tag("root")
+            .build();
+
+        assert_ne!(a, b);
+        assert_eq!(structural_hash(&a).unwrap(), structural_hash(&b).unwrap());
+        assert_ne!(structural_hash(&a).unwrap(), structural_hash(&c).unwrap());
+    }
+}
@@ -0,0 +1,122 @@
+/// Identifier handed out by an [`Interner`] for a stored string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InternId(pub u32);
+
+/// Storage for the strings an ABX stream interns
+///
+/// `FastDataInput` maps each interned index in the file to the `InternId`
+/// returned by `intern`, so implementations are free to deduplicate strings,
+/// share a pool across files, or store them however they like.
+pub trait Interner {
+    /// Store a newly defined string and return its identifier
+    fn intern(&mut self, s: String) -> InternId;
+
+    /// Look up a previously interned string
+    fn resolve(&self, id: InternId) -> Option<&str>;
+}
+
+impl<T: Interner + ?Sized> Interner for &mut T {
+    fn intern(&mut self, s: String) -> InternId {
+        (**self).intern(s)
+    }
+
+    fn resolve(&self, id: InternId) -> Option<&str> {
+        (**self).resolve(id)
+    }
+}
+
+/// Default interner: every string is appended to a `Vec` in definition order
+#[derive(Debug, Default, Clone)]
+pub struct VecInterner {
+    strings: Vec<String>,
+}
+
+impl VecInterner {
+    /// Create an empty interner
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All strings in definition order
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
+}
+
+impl Interner for VecInterner {
+    fn intern(&mut self, s: String) -> InternId {
+        self.strings.push(s);
+        InternId(self.strings.len() as u32 - 1)
+    }
+
+    fn resolve(&self, id: InternId) -> Option<&str> {
+        self.strings.get(id.0 as usize).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+    use crate::{BinaryXmlDeserializer, DeserializerOptions};
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    /// Stores each distinct string once
+    #[derive(Default)]
+    struct DedupInterner {
+        strings: Vec<String>,
+        ids: HashMap<String, InternId>,
+    }
+
+    impl Interner for DedupInterner {
+        fn intern(&mut self, s: String) -> InternId {
+            if let Some(id) = self.ids.get(&s) {
+                return *id;
+            }
+            let id = InternId(self.strings.len() as u32);
+            self.strings.push(s.clone());
+            self.ids.insert(s, id);
+            id
+        }
+
+        fn resolve(&self, id: InternId) -> Option<&str> {
+            self.strings.get(id.0 as usize).map(String::as_str)
+        }
+    }
+
+    #[test]
+    fn test_shared_dedup_interner_across_files() {
+        let first = AbxFixture::new()
+            .start_tag("a")
+            .attribute_bool("x", true)
+            .end_tag("a")
+            .build();
+        let second = AbxFixture::new()
+            .start_tag("x")
+            .attribute_bool("a", false)
+            .end_tag("x")
+            .build();
+
+        let mut pool = DedupInterner::default();
+        let mut outputs = Vec::new();
+        for data in [&first, &second] {
+            let mut output = Vec::new();
+            let mut deserializer = BinaryXmlDeserializer::with_interner(
+                Cursor::new(data),
+                &mut output,
+                false,
+                DeserializerOptions::new(),
+                &mut pool,
+            )
+            .unwrap();
+            deserializer.deserialize().unwrap();
+            drop(deserializer);
+            outputs.push(String::from_utf8(output).unwrap());
+        }
+
+        assert!(outputs[0].ends_with("<a x=\"true\"></a>"));
+        assert!(outputs[1].ends_with("<x a=\"false\"></x>"));
+        assert_eq!(pool.strings, vec!["a", "x"]);
+    }
+}
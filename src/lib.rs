@@ -6,7 +6,7 @@
 //! # Examples
 //!
 //! ```no_run
-//! use abx2xml::AbxToXmlConverter;
+//! use honeycomb::AbxToXmlConverter;
 //! use std::fs::File;
 //!
 //! // Convert a file
@@ -22,13 +22,60 @@ use std::io;
 use thiserror::Error;
 
 mod binary_xml;
+mod builder;
 pub mod cli;
 mod converter;
+mod document;
+mod dump;
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "tar")]
+mod gzip;
+mod handler;
+mod hash;
+mod interner;
+mod ndjson;
+mod options;
+mod policy;
+pub mod protocol;
+mod repair;
 mod seekable_reader;
+#[cfg(feature = "tar")]
+mod tar;
+#[cfg(feature = "tempfile")]
+mod temp_file;
+#[cfg(test)]
+mod test_support;
+mod token;
+mod transform;
 
 pub use binary_xml::{BinaryXmlDeserializer, FastDataInput, encode_xml_entities};
-pub use converter::AbxToXmlConverter;
-pub use seekable_reader::SeekableReader;
+pub use binary_xml::{is_xml_name, sanitize_xml_name};
+pub use builder::AbxBuilder;
+pub use converter::{AbxToXmlConverter, BatchEntry, BatchReport, ConversionReport, OutlineEntry, RootInfo};
+#[cfg(feature = "metrics")]
+pub use converter::Metrics;
+pub use document::{AbxDocument, Attribute, AttributeValue, Element, Node, decode_attribute_value};
+pub use dump::{RawToken, dump_tokens, raw_token_log};
+pub use events::QuickXmlEvents;
+pub use handler::{NoopHandler, TokenHandler};
+pub use hash::{document_hash, structural_hash};
+pub use interner::{InternId, Interner, VecInterner};
+pub use options::{AttributeWrap, BooleanStyle, DEFAULT_MAX_DEPTH, DeserializerOptions};
+pub use options::DEFAULT_MAX_INTERNED_STRINGS;
+pub use options::{DuplicateAttributePolicy, FloatFormat, FlushPolicy, NamespaceMode};
+pub use options::{Endianness, LineEnding, QuoteStyle, StringEncoding, WhitespacePolicy};
+pub use options::{Base64Alphabet, Base64Config, CoalesceText, WarningPolicy};
+pub use options::{ExpectedType, NumericStyle, REDACTED_PLACEHOLDER};
+pub use policy::{for_each_policy, get_policy_list, get_readable_xml, insert_restriction};
+pub use policy::{PolicyEditor, RestrictionSet, remove_restriction, validate_policies};
+pub use repair::repair;
+#[cfg(feature = "tempfile")]
+pub use temp_file::NamedTempFile;
+pub use seekable_reader::{DEFAULT_CHUNK_SIZE, SeekableReader};
+pub use token::{AttributeType, Command, command_of, describe_token, type_of};
+pub use transform::{ElementInfo, KeepOrDrop, filter_elements};
 
 /// Error types for ABX parsing and conversion
 #[derive(Error, Debug)]
@@ -36,15 +83,21 @@ pub enum AbxError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
     #[error(
-        "Invalid ABX file format - magic header mismatch. Expected: {expected:02X?}, got: {actual:02X?}"
+        "Invalid ABX file format - magic header mismatch. Expected one of: {expected:02X?}, got: {actual:02X?}"
     )]
-    InvalidMagicHeader { expected: [u8; 4], actual: [u8; 4] },
+    InvalidMagicHeader { expected: Vec<[u8; 4]>, actual: [u8; 4] },
     #[error("Failed to read {0} from stream")]
     ReadError(String),
     #[error("Invalid interned string index: {0}")]
     InvalidInternedStringIndex(u16),
-    #[error("Unknown attribute type: {0}")]
+    #[error(
+        "Interned string index {index} at offset {offset} is past the end of the table ({defined} defined so far)"
+    )]
+    InternedIndexOutOfRange { index: u16, offset: u64, defined: usize },
+    #[error("Unknown attribute type: 0x{0:02X}")]
     UnknownAttributeType(u8),
+    #[error("Unknown command: 0x{0:02X}")]
+    UnknownCommand(u8),
     #[error("Parse error: {0}")]
     ParseError(String),
 }
@@ -55,6 +108,9 @@ pub type Result<T> = std::result::Result<T, AbxError>;
 // Protocol constants - exposed for advanced users
 pub const PROTOCOL_MAGIC_VERSION_0: [u8; 4] = [0x41, 0x42, 0x58, 0x00];
 
+/// Magic headers accepted by default
+pub const KNOWN_MAGICS: &[[u8; 4]] = &[PROTOCOL_MAGIC_VERSION_0];
+
 // Command tokens
 pub const START_DOCUMENT: u8 = 0;
 pub const END_DOCUMENT: u8 = 1;
@@ -70,6 +126,7 @@ pub const DOCDECL: u8 = 10;
 pub const ATTRIBUTE: u8 = 15;
 
 // Type tokens
+pub const TYPE_NULL: u8 = 1 << 4;
 pub const TYPE_STRING: u8 = 2 << 4;
 pub const TYPE_STRING_INTERNED: u8 = 3 << 4;
 pub const TYPE_BYTES_HEX: u8 = 4 << 4;
@@ -88,4 +145,45 @@ pub struct Policy {
     pub name: String,
     pub start_offset: u32,
     pub end_offset: u32
+}
+
+/// Kind of a non-element token collected with `collect_metadata`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataKind {
+    Comment,
+    ProcessingInstruction,
+    Docdecl,
+}
+
+/// Where an interned string reference appears, see `InternRefSite`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InternRefContext {
+    StartTag,
+    EndTag,
+    AttributeName,
+    AttributeValue,
+    Text,
+}
+
+/// A reference to an earlier interned string, collected with `record_intern_refs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InternRefSite {
+    /// Offset of the two-byte index
+    pub offset: u64,
+    pub index: u16,
+    pub context: InternRefContext,
+}
+
+/// A non-fatal problem encountered while converting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// Input byte offset the warning refers to
+    pub offset: u64,
+    pub message: String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at offset {})", self.message, self.offset)
+    }
 }
\ No newline at end of file
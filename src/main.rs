@@ -1,8 +1,7 @@
-use std::{fs::File, io::{BufReader, Read, Write}, path::PathBuf, string};
+use std::{fs::File, io::BufReader};
 
 use clap::Parser;
-use honeycomb::{BinaryXmlDeserializer, Policy, SeekableReader};
-use quick_xml::{events::Event, Reader};
+use honeycomb::{AbxError, BinaryXmlDeserializer, PolicyEditor, SeekableReader, get_policy_list, get_readable_xml};
 
 /// Android device policy editor
 #[derive(Parser, Debug)]
@@ -40,13 +39,12 @@ fn main() {
     let args = Args::parse();
     let user_profile_path = args.profile_path;
     if args.list_policies {
-        let policies = get_policy_list(&user_profile_path);
-        for i in 0..policies.len() {
-            println!("{}", policies[i]);
+        let policies = get_policy_list(&user_profile_path).unwrap_or_else(|e| exit_with_error(e));
+        for policy in &policies {
+            println!("{}", policy);
         }
-        return;
     } else {
-        // For adding a policy, call get_restriction_node_offset to get the restriction offset
+        // For adding a policy, insert_restriction re-encodes the profile with it
         // For removing a policy, use the cleaned policy list struct
         let policy_name = args.policy_name.unwrap();
         let file = File::open(&user_profile_path).unwrap();
@@ -55,15 +53,12 @@ fn main() {
         let mut output = Vec::new();
         let mut deserializer = BinaryXmlDeserializer::new(&mut seekable_reader, &mut output, true).unwrap();
         let _ = deserializer.deserialize();
+        for warning in deserializer.warnings() {
+            eprintln!("Warning: {}", warning);
+        }
         
-        // I named this function terribly. It gets all attributes in the ABX/XML, NOT all policies. So we have to clean it
-        let uncleaned_policy_list = deserializer.get_policies().to_vec();
-        let policy_names = get_policy_list(&user_profile_path);
-        
-        let cleaned_policy_list: Vec<Policy> = uncleaned_policy_list
-            .into_iter()
-            .filter(|policy| policy_names.contains(&policy.name))
-            .collect();
+        // Only the attributes of <restrictions> inside <restrictions_user> are collected
+        let cleaned_policy_list = deserializer.get_restrictions();
 
         let mut should_create_policy = true;
 
@@ -80,21 +75,13 @@ fn main() {
                     policy.name, policy.start_offset, policy.end_offset
                 );
 
-                let mut buffer = Vec::new();
-                let mut file2 = File::open(&user_profile_path).unwrap();
-                file2.read_to_end(&mut buffer).unwrap();
-
-                buffer.drain(policy.start_offset as usize..policy.end_offset as usize);
+                // Draining the policy's bytes would renumber every string interned after
+                // its name, so the profile is re-encoded without it instead
+                let mut editor = PolicyEditor::open(&user_profile_path).unwrap_or_else(|e| exit_with_error(e));
+                editor.disable(&policy.name).unwrap_or_else(|e| exit_with_error(e));
 
-                // Decrement the fifth last byte by one. I have no idea what this represents!
-                // But when we remove a policy, this must go down too.
-                let len = buffer.len();
-                if len >= 5 {
-                    buffer[len - 5] = buffer[len - 5].wrapping_sub(1);
-                }
-
-                let mut new_file = File::create(args.out.clone().unwrap()).unwrap();
-                let _ = new_file.write_all(&buffer);
+                let new_file = File::create(args.out.clone().unwrap()).unwrap();
+                editor.write_to(new_file).unwrap_or_else(|e| exit_with_error(e));
 
                 println!("Successfully disabled the {} policy", policy.name);
                 println!("Wrote XML without policy to {}!", args.out.clone().unwrap());
@@ -103,25 +90,11 @@ fn main() {
 
         if should_create_policy {
             println!("CREATING the {} policy", policy_name);
-            let offset = deserializer.get_restriction_node_offset();
-            let policy_bytes = policy_to_bytes(&policy_name);
-            let mut buffer = Vec::new();
-            let mut file2 = File::open(user_profile_path).unwrap();
-            file2.read_to_end(&mut buffer).unwrap();
-
-            buffer.splice(
-                *offset as usize..*offset as usize,
-                policy_bytes,
-            );
-
-            // Increment the fifth last byte by one.
-            let len = buffer.len();
-            if len >= 5 {
-                buffer[len - 5] = buffer[len - 5].wrapping_add(1);
-            }
+            let mut editor = PolicyEditor::open(&user_profile_path).unwrap_or_else(|e| exit_with_error(e));
+            editor.enable(&policy_name).unwrap_or_else(|e| exit_with_error(e));
 
-            let mut new_file = File::create(args.out.clone().unwrap()).unwrap();
-            let _ = new_file.write_all(&buffer);
+            let new_file = File::create(args.out.clone().unwrap()).unwrap();
+            editor.write_to(new_file).unwrap_or_else(|e| exit_with_error(e));
 
             println!("Successfully added the {} policy", policy_name);
             println!();
@@ -130,86 +103,11 @@ fn main() {
         println!();
         println!("You may want to double check that this XML matches your expectations.");
         println!("Watch out for any syntax errors that the ABX -> XML conversion caused.");
-        println!("{}", get_readable_xml(args.out.clone().unwrap()));
+        println!("{}", get_readable_xml(args.out.clone().unwrap()).unwrap_or_else(|e| exit_with_error(e)));
     }
 }
 
-fn policy_to_bytes(policy_name: &str) -> Vec<u8> {
-    let mut serialized_policy_node = Vec::new();
-    const POLICY_NODE_BYTES: [u8; 3] = [0xCF, 0xFF, 0xFF];
-
-    serialized_policy_node.extend_from_slice(&POLICY_NODE_BYTES);
-    let name_len = policy_name.len() as u16;
-    serialized_policy_node.extend_from_slice(&name_len.to_be_bytes());
-    serialized_policy_node.extend_from_slice(policy_name.as_bytes());
-
-    // for byte in &serialized_policy_node {
-    //     print!("{:02X} ", byte);
-    // }
-    return serialized_policy_node;
+fn exit_with_error(error: AbxError) -> ! {
+    eprintln!("Error: {}", error);
+    std::process::exit(1);
 }
-
-fn get_readable_xml(path: String) -> String {
-    let file = File::open(path).unwrap();
-    let buf_reader = BufReader::new(file);
-    let mut seekable_reader = SeekableReader::new(buf_reader);
-    let mut output = Vec::new();
-    let mut deserializer = BinaryXmlDeserializer::new(&mut seekable_reader, &mut output, false).unwrap();
-    let _ = deserializer.deserialize();
-
-    // human readable form of the ABX file
-    let xml_str = String::from_utf8(output).unwrap();
-
-    return xml_str;
-}
-
-fn get_policy_list(abx_path: &str) -> Vec<String> {
-    let mut list_output: Vec<String> = Vec::new();
-    let file = File::open(abx_path).unwrap();
-    let buf_reader = BufReader::new(file);
-    let mut seekable_reader = SeekableReader::new(buf_reader);
-    let mut output = Vec::new();
-    let mut deserializer = BinaryXmlDeserializer::new(&mut seekable_reader, &mut output, false).unwrap();
-    let _ = deserializer.deserialize();
-
-    // human readable form of the ABX file
-    let xml_str = String::from_utf8(output).unwrap();
-
-    let mut reader = Reader::from_str(&xml_str);
-
-    let mut buf = Vec::new();
-
-    let mut is_correct_policy_node = false;
-
-    loop {
-        let event = reader.read_event_into(&mut buf).unwrap();
-        match event {
-            Event::Eof => break,
-            Event::Start(e) => {
-                // Check if this is the <restrictions> inside device_policy_local_restrictions
-                let event_name = e.name();
-
-                match event_name.as_ref() {
-                    b"restrictions" => {
-                        if is_correct_policy_node {
-                            for attr in e.attributes().flatten() {
-                                let key = String::from_utf8_lossy(attr.key.as_ref()).to_string();
-                                list_output.push(key);
-                            }
-                        }
-                    },
-                    b"restrictions_user" => {
-                        // We do this to ensure that these are the restrictions inside of <restrictions_user />
-                        is_correct_policy_node = true
-                    },
-                    _ => {}
-                }
-            }
-            _ => {}
-        }
-        buf.clear();
-    }
-
-    return list_output;
-
-}
\ No newline at end of file
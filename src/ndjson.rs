@@ -0,0 +1,156 @@
+//! Newline-delimited JSON output, one record per element at a chosen depth
+
+use crate::{Result, TokenHandler};
+use std::io::Write;
+
+/// An element being collected into a record
+#[derive(Default)]
+struct Record {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Child>,
+}
+
+enum Child {
+    Element(Record),
+    Text(String),
+}
+
+/// Handler that writes every element at `depth` as a line of JSON
+pub(crate) struct NdjsonWriter<W: Write> {
+    writer: W,
+    depth: usize,
+    /// Number of open elements, including those above `depth`
+    open: usize,
+    /// Open elements at or below `depth`, outermost first
+    stack: Vec<Record>,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub(crate) fn new(writer: W, depth: usize) -> Self {
+        Self {
+            writer,
+            depth,
+            open: 0,
+            stack: Vec::new(),
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let Some(record) = self.stack.last_mut() else {
+            return;
+        };
+        match record.children.last_mut() {
+            Some(Child::Text(existing)) => existing.push_str(text),
+            _ => record.children.push(Child::Text(text.to_string())),
+        }
+    }
+}
+
+impl<W: Write> TokenHandler for NdjsonWriter<W> {
+    fn start_tag(&mut self, name: &str) -> Result<()> {
+        if self.open >= self.depth {
+            self.stack.push(Record {
+                name: name.to_string(),
+                ..Record::default()
+            });
+        }
+        self.open += 1;
+        Ok(())
+    }
+
+    fn attribute(&mut self, name: &str, value: &str) -> Result<()> {
+        if let Some(record) = self.stack.last_mut() {
+            record
+                .attributes
+                .push((name.to_string(), value.to_string()));
+        }
+        Ok(())
+    }
+
+    fn end_tag(&mut self, _name: &str) -> Result<()> {
+        self.open = self.open.saturating_sub(1);
+        if self.open < self.depth {
+            return Ok(());
+        }
+        let Some(record) = self.stack.pop() else {
+            return Ok(());
+        };
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(Child::Element(record)),
+            None => {
+                let mut line = String::new();
+                write_record(&mut line, &record);
+                line.push('\n');
+                self.writer.write_all(line.as_bytes())?;
+                self.writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<()> {
+        self.push_text(text);
+        Ok(())
+    }
+
+    fn cdata(&mut self, text: &str) -> Result<()> {
+        self.push_text(text);
+        Ok(())
+    }
+}
+
+/// Append `{"name":..,"attributes":{..},"children":[..]}` to `out`
+fn write_record(out: &mut String, record: &Record) {
+    out.push_str("{\"name\":");
+    write_string(out, &record.name);
+    out.push_str(",\"attributes\":{");
+    for (i, (name, value)) in record.attributes.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_string(out, name);
+        out.push(':');
+        write_string(out, value);
+    }
+    out.push_str("},\"children\":[");
+    for (i, child) in record.children.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        match child {
+            Child::Element(element) => write_record(out, element),
+            Child::Text(text) => write_string(out, text),
+        }
+    }
+    out.push_str("]}");
+}
+
+/// Append `s` as a JSON string literal
+pub(crate) fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_string_escapes() {
+        let mut out = String::new();
+        write_string(&mut out, "a\"b\\c\n\u{1}");
+        assert_eq!(out, r#""a\"b\\c\n\u0001""#);
+    }
+}
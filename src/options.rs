@@ -0,0 +1,686 @@
+use crate::{AttributeType, KNOWN_MAGICS, Warning};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// How to handle an element that carries the same attribute name more than once
+///
+/// ABX doesn't forbid duplicate attribute names, but most XML parsers reject
+/// them, so converted output may need to be adjusted to stay parseable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateAttributePolicy {
+    /// Write every attribute as stored, duplicates included
+    #[default]
+    Keep,
+    /// Rename later occurrences by appending `_2`, `_3`, ..., skipping any
+    /// suffixed name the element already has
+    RenameSuffix,
+    /// Keep only the first occurrence of each name
+    First,
+    /// Keep only the last occurrence's value, at the first occurrence's
+    /// position
+    Last,
+}
+
+/// How `TYPE_FLOAT` and `TYPE_DOUBLE` attribute values are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FloatFormat {
+    /// Rust's `Display` formatting (`1`, `0.001`, `10000000`)
+    #[default]
+    Rust,
+    /// Java's `Float.toString`/`Double.toString` (`1.0`, `0.001`, `1.0E7`),
+    /// matching the XML Android itself produces
+    JavaCompatible,
+}
+
+/// How `TYPE_INT` and `TYPE_LONG` attribute values are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumericStyle {
+    /// Plain numbers for both (`5`, `0x1F`)
+    #[default]
+    Bare,
+    /// Java literal conventions: ints stay plain, longs get an `L` suffix
+    /// (`5L`, `0x1FL`), so the two can be told apart in the output
+    JavaLiteral,
+}
+
+/// How boolean attributes are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BooleanStyle {
+    /// `name="true"` and `name="false"`
+    #[default]
+    Text,
+    /// HTML-style: `name=""` for true, and false attributes are left out
+    ///
+    /// The omitted attributes are recorded by the deserializer.
+    Presence,
+}
+
+/// Which quote character delimits attribute values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuoteStyle {
+    /// `name="value"`; both quote characters in values are escaped
+    #[default]
+    Double,
+    /// `name='value'`; only `'` in values is escaped
+    Single,
+}
+
+/// Alphabet used for `TYPE_BYTES_BASE64` attribute values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Base64Alphabet {
+    /// RFC 4648 standard alphabet, with `+` and `/`
+    #[default]
+    Standard,
+    /// RFC 4648 URL-safe alphabet, with `-` and `_`
+    UrlSafe,
+}
+
+/// How `TYPE_BYTES_BASE64` attribute values are encoded
+///
+/// The default is the standard alphabet with `=` padding on one line, as
+/// Android writes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Base64Config {
+    pub alphabet: Base64Alphabet,
+    /// Pad the output to a multiple of four characters with `=`
+    pub padding: bool,
+    /// Break lines after this many characters, e.g. `Some(76)` for MIME
+    ///
+    /// Lines are separated with the configured [`LineEnding`]. XML parsers
+    /// normalize line breaks in attribute values to spaces, so consumers
+    /// need to strip whitespace before decoding.
+    pub wrap: Option<usize>,
+}
+
+impl Default for Base64Config {
+    fn default() -> Self {
+        Self {
+            alphabet: Base64Alphabet::Standard,
+            padding: true,
+            wrap: None,
+        }
+    }
+}
+
+/// Kind of value an attribute is expected to hold, for `schema`
+///
+/// Coarser than [`AttributeType`]: the interned and hex variants of a type
+/// match the same expectation, as do both boolean types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedType {
+    String,
+    Bytes,
+    Int,
+    Long,
+    Float,
+    Double,
+    Boolean,
+}
+
+impl ExpectedType {
+    /// Whether a value stored as `stored` meets this expectation
+    pub fn matches(self, stored: AttributeType) -> bool {
+        use AttributeType as T;
+        match self {
+            ExpectedType::String => matches!(stored, T::String | T::StringInterned),
+            ExpectedType::Bytes => matches!(stored, T::BytesHex | T::BytesBase64),
+            ExpectedType::Int => matches!(stored, T::Int | T::IntHex),
+            ExpectedType::Long => matches!(stored, T::Long | T::LongHex),
+            ExpectedType::Float => stored == T::Float,
+            ExpectedType::Double => stored == T::Double,
+            ExpectedType::Boolean => matches!(stored, T::BooleanTrue | T::BooleanFalse),
+        }
+    }
+}
+
+/// When to put each attribute of a start tag on its own line
+///
+/// Wrapped attributes are aligned under the tag name. Elements with a single
+/// attribute are never wrapped by width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttributeWrap {
+    /// Keep all attributes on the tag's line
+    #[default]
+    Never,
+    /// Wrap elements with more than this many attributes
+    MoreThan(usize),
+    /// Wrap when the start tag would be wider than this many columns
+    Width(usize),
+}
+
+/// Newline sequence used for line breaks the deserializer inserts itself
+///
+/// Such breaks are those between attributes split by [`AttributeWrap`],
+/// those in base64 values wrapped by [`Base64Config::wrap`], and those before
+/// tags indented with [`DeserializerOptions::indent`]. This doesn't add any
+/// newline by itself. Text content and `IGNORABLE_WHITESPACE` are written as
+/// stored, see [`WhitespacePolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+/// How whitespace stored in the document is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhitespacePolicy {
+    /// Write `IGNORABLE_WHITESPACE` and whitespace-only text as stored
+    #[default]
+    Preserve,
+    /// Drop `IGNORABLE_WHITESPACE`, and whitespace-only text between two
+    /// tags, giving the same minimal output however the source was indented
+    ///
+    /// Adjacent text tokens are judged together, so the result doesn't
+    /// depend on how the text was split. Whitespace that is an element's
+    /// only content, like `<name> </name>`, or that touches other text, CDATA
+    /// or a comment is kept.
+    Compact,
+}
+
+/// Whether consecutive text tokens are written as one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoalesceText {
+    /// Write every `TEXT` and `CDSECT` token as it comes
+    #[default]
+    Off,
+    /// Merge adjacent `TEXT` tokens into one text node, and adjacent `CDSECT`
+    /// tokens into one CDATA section, split only where the content has `]]>`
+    Adjacent,
+}
+
+/// What happens when the deserializer records a warning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WarningPolicy {
+    /// Keep converting and collect the warning
+    #[default]
+    Collect,
+    /// Stop at the first warning and return it as an error
+    ///
+    /// Unlike `strict`, problems are still recovered from as usual until the
+    /// token that caused them has been written; the conversion then fails
+    /// instead of going on.
+    FailFast,
+}
+
+/// When the deserializer flushes its output writer
+///
+/// Output is always written as it is produced; flushing additionally pushes it
+/// through any buffering in the writer, e.g. to stream a chunked response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Never flush; the writer decides (current behavior)
+    #[default]
+    Never,
+    /// Flush after every N tokens, and at the end
+    EveryTokens(usize),
+    /// Flush after each child of the root element closes, and at the end
+    TopLevelElement,
+}
+
+/// How string payloads are encoded in the ABX stream
+///
+/// Android's `FastDataOutput` writes a `u16` byte length followed by modified
+/// UTF-8. Some older or vendor-customized serializers instead write a `u16`
+/// count of UTF-16 code units followed by big-endian UTF-16. The two can't be
+/// told apart reliably from the bytes alone, so the encoding is chosen
+/// explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StringEncoding {
+    #[default]
+    Utf8,
+    Utf16,
+}
+
+/// Byte order of the multi-byte integers in the input
+///
+/// Standard Android ABX is always big-endian. Little-endian is for dumps
+/// from tools that byte-swapped every short, int and long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+/// How prefixed tag and attribute names are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NamespaceMode {
+    /// Write names as stored, e.g. `android:label`
+    #[default]
+    Preserve,
+    /// Resolve prefixes against the in-scope `xmlns` declarations and write
+    /// Clark notation, e.g. `{http://schemas.android.com/apk/res/android}label`
+    ///
+    /// The declarations themselves are kept. Clark names aren't valid XML
+    /// names, so this is meant for canonicalization and tooling rather than
+    /// for output that is parsed as XML again.
+    Clark,
+}
+
+/// Callback set with [`DeserializerOptions::on_warning`]
+#[derive(Clone)]
+pub(crate) struct WarningCallback(pub(crate) Arc<dyn Fn(&Warning) + Send + Sync>);
+
+impl fmt::Debug for WarningCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WarningCallback(..)")
+    }
+}
+
+/// Value written in place of redacted attribute values
+pub const REDACTED_PLACEHOLDER: &str = "[REDACTED]";
+
+/// Default limit on element nesting depth
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
+/// Default limit on the number of interned strings a document may define
+///
+/// Real `users/0.xml` files define a few hundred.
+pub const DEFAULT_MAX_INTERNED_STRINGS: usize = 32_768;
+
+/// Options controlling how the deserializer renders XML
+#[derive(Debug, Clone)]
+pub struct DeserializerOptions {
+    pub(crate) duplicate_attributes: DuplicateAttributePolicy,
+    pub(crate) float_format: FloatFormat,
+    pub(crate) numeric_style: NumericStyle,
+    pub(crate) base64: Base64Config,
+    pub(crate) attribute_wrap: AttributeWrap,
+    pub(crate) emit_offset_comments: bool,
+    pub(crate) max_depth: usize,
+    pub(crate) max_interned_strings: usize,
+    pub(crate) flush: FlushPolicy,
+    pub(crate) strict: bool,
+    pub(crate) string_encoding: StringEncoding,
+    pub(crate) endianness: Endianness,
+    pub(crate) accepted_magics: Vec<[u8; 4]>,
+    pub(crate) namespaces: NamespaceMode,
+    pub(crate) collect_metadata: bool,
+    pub(crate) line_ending: LineEnding,
+    pub(crate) boolean_style: BooleanStyle,
+    pub(crate) hash_attributes: bool,
+    pub(crate) whitespace: WhitespacePolicy,
+    pub(crate) indent: Option<usize>,
+    pub(crate) max_indent_depth: Option<usize>,
+    pub(crate) coalesce_text: CoalesceText,
+    pub(crate) quote_style: QuoteStyle,
+    pub(crate) preserve_empty_text: bool,
+    pub(crate) warning_policy: WarningPolicy,
+    pub(crate) schema: HashMap<String, ExpectedType>,
+    pub(crate) redact: HashSet<String>,
+    pub(crate) rename: HashMap<String, String>,
+    pub(crate) entity_map: Option<HashMap<String, String>>,
+    pub(crate) max_expansion_ratio: Option<f64>,
+    pub(crate) inline_warning_comments: bool,
+    pub(crate) record_interned_definitions: bool,
+    pub(crate) capture_trailer: bool,
+    pub(crate) record_intern_refs: bool,
+    pub(crate) validate_interned_indices: bool,
+    pub(crate) on_warning: Option<WarningCallback>,
+}
+
+impl Default for DeserializerOptions {
+    fn default() -> Self {
+        Self {
+            duplicate_attributes: DuplicateAttributePolicy::default(),
+            float_format: FloatFormat::default(),
+            numeric_style: NumericStyle::default(),
+            base64: Base64Config::default(),
+            attribute_wrap: AttributeWrap::default(),
+            emit_offset_comments: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_interned_strings: DEFAULT_MAX_INTERNED_STRINGS,
+            flush: FlushPolicy::default(),
+            strict: false,
+            string_encoding: StringEncoding::default(),
+            endianness: Endianness::default(),
+            accepted_magics: KNOWN_MAGICS.to_vec(),
+            namespaces: NamespaceMode::default(),
+            collect_metadata: false,
+            line_ending: LineEnding::default(),
+            boolean_style: BooleanStyle::default(),
+            hash_attributes: false,
+            whitespace: WhitespacePolicy::default(),
+            indent: None,
+            max_indent_depth: None,
+            coalesce_text: CoalesceText::default(),
+            quote_style: QuoteStyle::default(),
+            preserve_empty_text: false,
+            warning_policy: WarningPolicy::default(),
+            schema: HashMap::new(),
+            redact: HashSet::new(),
+            rename: HashMap::new(),
+            entity_map: None,
+            max_expansion_ratio: None,
+            inline_warning_comments: false,
+            record_interned_definitions: false,
+            capture_trailer: false,
+            record_intern_refs: false,
+            validate_interned_indices: false,
+            on_warning: None,
+        }
+    }
+}
+
+impl DeserializerOptions {
+    /// Create options with the default (current) behavior
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how duplicate attribute names within one element are handled
+    pub fn duplicate_attributes(mut self, policy: DuplicateAttributePolicy) -> Self {
+        self.duplicate_attributes = policy;
+        self
+    }
+
+    /// Set how float and double attribute values are rendered
+    pub fn float_format(mut self, format: FloatFormat) -> Self {
+        self.float_format = format;
+        self
+    }
+
+    /// Set how int and long attribute values are rendered
+    pub fn numeric_style(mut self, style: NumericStyle) -> Self {
+        self.numeric_style = style;
+        self
+    }
+
+    /// Set how base64 attribute values are encoded
+    pub fn base64(mut self, config: Base64Config) -> Self {
+        self.base64 = config;
+        self
+    }
+
+    /// Set how boolean attributes are written
+    pub fn boolean_style(mut self, style: BooleanStyle) -> Self {
+        self.boolean_style = style;
+        self
+    }
+
+    /// Set the quote character around attribute values
+    pub fn quote_style(mut self, style: QuoteStyle) -> Self {
+        self.quote_style = style;
+        self
+    }
+
+    /// Set when start tag attributes are split onto separate lines
+    pub fn attribute_wrap(mut self, wrap: AttributeWrap) -> Self {
+        self.attribute_wrap = wrap;
+        self
+    }
+
+    /// Set the newline written between wrapped attributes, in wrapped base64
+    /// values and before indented tags
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Set whether stored whitespace between elements is written
+    pub fn whitespace(mut self, policy: WhitespacePolicy) -> Self {
+        self.whitespace = policy;
+        self
+    }
+
+    /// Put each element on its own line, indented `width` spaces per level
+    ///
+    /// Stored whitespace between tags is dropped in favor of the indentation,
+    /// as with [`WhitespacePolicy::Compact`]. Elements that have text are
+    /// left as they are inside, since added whitespace would change the
+    /// text; so is an element whose first child comes before its text.
+    pub fn indent(mut self, width: usize) -> Self {
+        self.indent = Some(width);
+        self
+    }
+
+    /// Only indent elements up to `depth` levels below the root
+    ///
+    /// The root is at depth 0. Deeper elements are written compactly on
+    /// their parent's line, keeping large repetitive subtrees small while
+    /// the top-level structure stays readable. Only has an effect together
+    /// with [`indent`](Self::indent).
+    pub fn max_indent_depth(mut self, depth: usize) -> Self {
+        self.max_indent_depth = Some(depth);
+        self
+    }
+
+    /// Set whether adjacent text and CDATA tokens are merged in the output
+    ///
+    /// Merged text is held back until the next other token. Token handlers
+    /// still see every token.
+    pub fn coalesce_text(mut self, coalesce: CoalesceText) -> Self {
+        self.coalesce_text = coalesce;
+        self
+    }
+
+    /// Report empty `TEXT` tokens as empty text events instead of dropping them
+    ///
+    /// The XML output is the same either way. This is for consumers of
+    /// `to_quick_xml_events_with_options` that re-encode the events and need
+    /// every token back; [`AbxDocument`](crate::AbxDocument) always keeps
+    /// empty text nodes.
+    pub fn preserve_empty_text(mut self, enabled: bool) -> Self {
+        self.preserve_empty_text = enabled;
+        self
+    }
+
+    /// Annotate each element with a `<!-- @0x... -->` comment holding the input
+    /// offset of its `START_TAG` token
+    ///
+    /// The comment is written as the element's first child, so the output
+    /// remains well-formed XML.
+    pub fn emit_offset_comments(mut self, enabled: bool) -> Self {
+        self.emit_offset_comments = enabled;
+        self
+    }
+
+    /// Set the maximum element nesting depth before conversion fails
+    ///
+    /// Guards against maliciously deep files. Defaults to [`DEFAULT_MAX_DEPTH`].
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Set the maximum number of interned strings before conversion fails
+    ///
+    /// Bounds memory on untrusted input. Defaults to
+    /// [`DEFAULT_MAX_INTERNED_STRINGS`].
+    pub fn max_interned_strings(mut self, limit: usize) -> Self {
+        self.max_interned_strings = limit;
+        self
+    }
+
+    /// Record where each interned string is defined in the input
+    ///
+    /// Off by default to save the extra copy of every string; see
+    /// [`BinaryXmlDeserializer::interned_string_definitions`](crate::BinaryXmlDeserializer::interned_string_definitions).
+    pub fn record_interned_definitions(mut self, enabled: bool) -> Self {
+        self.record_interned_definitions = enabled;
+        self
+    }
+
+    /// Fail on interned references to strings not defined yet
+    ///
+    /// ABX has no forward references, so an index at or past the number of
+    /// strings defined so far means the file is corrupt. With this set such
+    /// a reference stops the conversion, even in lenient mode, with
+    /// [`AbxError::InternedIndexOutOfRange`](crate::AbxError::InternedIndexOutOfRange)
+    /// giving its offset and index.
+    pub fn validate_interned_indices(mut self, enabled: bool) -> Self {
+        self.validate_interned_indices = enabled;
+        self
+    }
+
+    /// Record the offset of every reference to an interned string
+    ///
+    /// See [`BinaryXmlDeserializer::intern_ref_sites`](crate::BinaryXmlDeserializer::intern_ref_sites).
+    /// Off by default, costing nothing then.
+    pub fn record_intern_refs(mut self, enabled: bool) -> Self {
+        self.record_intern_refs = enabled;
+        self
+    }
+
+    /// Keep the bytes that follow `END_DOCUMENT`
+    ///
+    /// By default conversion stops reading at `END_DOCUMENT`. With this set
+    /// the rest of the input is read too, and is available from
+    /// [`BinaryXmlDeserializer::trailer`](crate::BinaryXmlDeserializer::trailer).
+    pub fn capture_trailer(mut self, enabled: bool) -> Self {
+        self.capture_trailer = enabled;
+        self
+    }
+
+    /// Set when the output writer is flushed during conversion
+    pub fn flush(mut self, flush: FlushPolicy) -> Self {
+        self.flush = flush;
+        self
+    }
+
+    /// Fail the conversion on any token that can't be decoded
+    ///
+    /// By default (lenient) a bad token is recorded as a warning and the
+    /// output converted so far is kept. Strict mode also requires the body to
+    /// open with `START_DOCUMENT`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Set how string payloads in the input are encoded
+    pub fn string_encoding(mut self, encoding: StringEncoding) -> Self {
+        self.string_encoding = encoding;
+        self
+    }
+
+    /// Set the byte order of lengths, numbers and interned references
+    ///
+    /// Android always writes big-endian (the default). When a big-endian read
+    /// fails early in the document, the error suggests trying little-endian.
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    /// Set the expected type of attributes by name
+    ///
+    /// An attribute stored with a type its entry doesn't match is converted
+    /// as usual and a warning is recorded. Attributes not in the map aren't
+    /// checked.
+    pub fn schema(mut self, schema: HashMap<String, ExpectedType>) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Warn when the XML is more than `ratio` times the size of the input
+    ///
+    /// Base64 and hex values and deep nesting make output larger than the
+    /// ABX it came from, but not by orders of magnitude; a huge ratio points
+    /// at crafted input. Unset by default.
+    pub fn max_expansion_ratio(mut self, ratio: f64) -> Self {
+        self.max_expansion_ratio = Some(ratio);
+        self
+    }
+
+    /// Also write each warning into the output, as a comment like
+    /// `<!-- honeycomb: Unknown token: unknown command 0x0B (input offset 0x11) -->`
+    ///
+    /// Comments go right after the token that caused the warning, or after
+    /// its start tag for problems with attributes.
+    pub fn inline_warning_comments(mut self, enabled: bool) -> Self {
+        self.inline_warning_comments = enabled;
+        self
+    }
+
+    /// Set whether a warning stops the conversion
+    pub fn warning_policy(mut self, policy: WarningPolicy) -> Self {
+        self.warning_policy = policy;
+        self
+    }
+
+    /// Call `callback` with each warning as it is recorded
+    ///
+    /// For routing conversion problems into an application's own logging,
+    /// e.g. `log::warn!(target: "honeycomb::binary_xml", "{}", warning)`;
+    /// the warning carries its input offset. Warnings are still collected as
+    /// usual. The callback runs on whichever thread converts, so with the
+    /// `parallel` feature it may be called from several threads at once.
+    pub fn on_warning<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Warning) + Send + Sync + 'static,
+    {
+        self.on_warning = Some(WarningCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Set which 4-byte magic headers are accepted
+    ///
+    /// Defaults to [`KNOWN_MAGICS`]. Lets new format versions be tried out
+    /// before this crate knows about them; `version()` reports the last byte
+    /// of whichever magic matched.
+    pub fn accepted_magics(mut self, magics: Vec<[u8; 4]>) -> Self {
+        self.accepted_magics = magics;
+        self
+    }
+
+    /// Set whether namespace prefixes are written as stored or expanded
+    pub fn namespaces(mut self, mode: NamespaceMode) -> Self {
+        self.namespaces = mode;
+        self
+    }
+
+    /// Collect comments, processing instructions and doctypes separately
+    /// instead of writing them into the XML
+    ///
+    /// The collected entries are available from the deserializer's
+    /// `metadata()` and in the conversion report.
+    pub fn collect_metadata(mut self, enabled: bool) -> Self {
+        self.collect_metadata = enabled;
+        self
+    }
+
+    /// Record a SHA-256 of every attribute value, keyed by element path and
+    /// attribute name, to tell which settings differ between two files
+    pub fn hash_attributes(mut self, enabled: bool) -> Self {
+        self.hash_attributes = enabled;
+        self
+    }
+
+    /// Set attributes whose values are replaced with [`REDACTED_PLACEHOLDER`]
+    ///
+    /// Entries are attribute names, matching in any element, or paths like
+    /// `accounts/account@token` matching one element path. The attribute
+    /// itself is kept, and its stored value is still read, so the rest of
+    /// the document converts unchanged.
+    pub fn redact(mut self, attributes: HashSet<String>) -> Self {
+        self.redact = attributes;
+        self
+    }
+
+    /// Write tag and attribute names found in `names` as the name they map to
+    ///
+    /// E.g. `restrictions2` → `restrictions` normalizes a vendor's element
+    /// name. Start and end tags are renamed alike. Only the output changes:
+    /// other options that take names, such as `redact` and `schema`, and
+    /// token handlers still see the names as stored. Replacement names are
+    /// written as given.
+    pub fn rename(mut self, names: HashMap<String, String>) -> Self {
+        self.rename = names;
+        self
+    }
+
+    /// Expand entity references using `entities`, name to replacement text
+    ///
+    /// Without a map `ENTITY_REF` tokens are written as `&name;`, which only
+    /// reparses if the DTD declaring them is at hand. With one, listed
+    /// entities are written as their (escaped) replacement text; others stay
+    /// references, with a warning unless they are one of XML's predefined
+    /// entities.
+    pub fn entity_map(mut self, entities: HashMap<String, String>) -> Self {
+        self.entity_map = Some(entities);
+        self
+    }
+}
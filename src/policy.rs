@@ -0,0 +1,661 @@
+//! Helpers for reading device policy restrictions out of a user profile
+
+use crate::binary_xml::read_magic;
+use crate::decode_attribute_value;
+use crate::{AbxDocument, AbxError, Attribute, AttributeValue};
+use crate::{BinaryXmlDeserializer, Element, Node, Result, SeekableReader};
+use crate::{Command, FastDataInput, KNOWN_MAGICS, Policy};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+/// Convert an ABX file to XML text for display
+pub fn get_readable_xml<P: AsRef<Path>>(path: P) -> Result<String> {
+    let file = File::open(path)?;
+    let seekable_reader = SeekableReader::new(BufReader::new(file));
+    let mut output = Vec::new();
+    let mut deserializer = BinaryXmlDeserializer::new(seekable_reader, &mut output, false)?;
+    deserializer.deserialize()?;
+
+    String::from_utf8(output)
+        .map_err(|_| AbxError::ParseError("Invalid UTF-8 in output".to_string()))
+}
+
+/// Names of the policies set in a user profile
+///
+/// These are the attribute names of the `<restrictions>` element directly
+/// inside `<restrictions_user>`, as with [`for_each_policy`].
+pub fn get_policy_list<P: AsRef<Path>>(abx_path: P) -> Result<Vec<String>> {
+    let file = File::open(abx_path)?;
+    policy_names(SeekableReader::new(BufReader::new(file)))
+}
+
+/// Policies in a user profile that aren't in `known`, in document order
+///
+/// Catches typos and restrictions newer than the caller's tooling.
+///
+/// # Examples
+///
+/// ```no_run
+/// use honeycomb::validate_policies;
+/// use std::collections::HashSet;
+/// use std::fs::File;
+///
+/// let known: HashSet<String> = ["no_sms", "no_usb"].map(String::from).into();
+/// let unknown = validate_policies(File::open("0.xml").unwrap(), &known).unwrap();
+/// for name in unknown {
+///     eprintln!("unrecognized restriction: {}", name);
+/// }
+/// ```
+pub fn validate_policies<R: Read + Seek>(
+    reader: R,
+    known: &HashSet<String>,
+) -> Result<Vec<String>> {
+    let mut names = policy_names(reader)?;
+    names.retain(|name| !known.contains(name));
+    Ok(names)
+}
+
+fn policy_names<R: Read + Seek>(reader: R) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut seen = HashSet::new();
+    for_each_policy(reader, |policy| {
+        // A repeated attribute is listed once, where it first appears
+        if seen.insert(policy.name.clone()) {
+            names.push(policy.name.clone());
+        }
+        ControlFlow::Continue(())
+    })?;
+    Ok(names)
+}
+
+/// Call `f` with each policy as it is read, until it returns `Break`
+///
+/// Policies are the attributes of a `<restrictions>` element directly inside
+/// `<restrictions_user>`. Offsets are the same as the deserializer's
+/// `get_policies()`. Nothing after the policy that breaks is read, so
+/// looking for a single policy stops as soon as it is found.
+///
+/// # Examples
+///
+/// ```no_run
+/// use honeycomb::for_each_policy;
+/// use std::fs::File;
+/// use std::ops::ControlFlow;
+///
+/// let mut found = None;
+/// for_each_policy(File::open("0.xml").unwrap(), |policy| {
+///     if policy.name == "no_sms" {
+///         found = Some(policy.clone());
+///         return ControlFlow::Break(());
+///     }
+///     ControlFlow::Continue(())
+/// })
+/// .unwrap();
+/// ```
+pub fn for_each_policy<R, F>(mut reader: R, mut f: F) -> Result<()>
+where
+    R: Read + Seek,
+    F: FnMut(&Policy) -> ControlFlow<()>,
+{
+    read_magic(&mut reader, KNOWN_MAGICS)?;
+    let mut input = FastDataInput::new(reader);
+    let mut stack: Vec<String> = Vec::new();
+    while !input.is_eof() {
+        let token = input.read_byte()?;
+        match Command::try_from(token)? {
+            Command::StartDocument => {}
+            Command::EndDocument => break,
+            Command::StartTag => stack.push(input.read_interned_utf()?),
+            Command::EndTag => {
+                input.read_interned_utf()?;
+                stack.pop();
+            }
+            Command::Attribute => {
+                let start_offset = input.tell()? as u32 - 1;
+                let name = input.read_interned_utf()?;
+                decode_attribute_value(token, &mut input)?;
+                let end_offset = input.tell()? as u32;
+                let in_user_restrictions = matches!(
+                    stack.as_slice(),
+                    [.., parent, element] if parent == "restrictions_user" && element == "restrictions"
+                );
+                if in_user_restrictions {
+                    let policy = Policy {
+                        name,
+                        start_offset,
+                        end_offset,
+                    };
+                    if f(&policy).is_break() {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                input.read_token_payload(token)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Set the policy `name` in an in-memory user profile
+///
+/// The policy is added as the last attribute of the `<restrictions>` element
+/// inside `<restrictions_user>`, so bytes up to the end of the existing
+/// policies are unchanged. The profile is re-encoded rather than patched in
+/// place, because a newly interned name still renumbers every string first
+/// used after `<restrictions>`. A policy that is already present is set to
+/// `true` where it is.
+pub fn insert_restriction(buffer: &mut Vec<u8>, name: &str) -> Result<()> {
+    let mut restrictions = RestrictionSet::from_bytes(buffer)?;
+    restrictions.enable(name);
+    *buffer = restrictions.serialize()?;
+    Ok(())
+}
+
+/// Remove the policy `name` from an in-memory user profile
+///
+/// Returns whether the policy was present. Like `insert_restriction` the
+/// profile is re-encoded: cutting a policy's `start_offset..end_offset` out
+/// of the bytes is only safe when its name was interned earlier, or when
+/// nothing after it refers to a string interned later, since removing the
+/// definition renumbers every later string.
+pub fn remove_restriction(buffer: &mut Vec<u8>, name: &str) -> Result<bool> {
+    let mut restrictions = RestrictionSet::from_bytes(buffer)?;
+    if !restrictions.disable(name) {
+        return Ok(false);
+    }
+    *buffer = restrictions.serialize()?;
+    Ok(true)
+}
+
+/// The policies of a user profile, for querying and editing
+///
+/// Works on the `<restrictions>` element inside `<restrictions_user>`, where
+/// each set policy is a boolean attribute. Edits are made on the parsed
+/// document and `serialize` encodes it again, so interned string indices are
+/// always consistent; nothing is patched by offset.
+///
+/// # Examples
+///
+/// ```no_run
+/// use honeycomb::RestrictionSet;
+///
+/// let profile = std::fs::read("/data/system/users/0.xml").unwrap();
+/// let mut restrictions = RestrictionSet::from_bytes(&profile).unwrap();
+/// if !restrictions.is_enabled("no_camera") {
+///     restrictions.enable("no_camera");
+/// }
+/// std::fs::write("0.xml", restrictions.serialize().unwrap()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RestrictionSet {
+    document: AbxDocument,
+    /// Child indices leading from the top-level nodes to `<restrictions>`
+    path: Vec<usize>,
+}
+
+impl RestrictionSet {
+    /// Parse a user profile
+    ///
+    /// Fails if the profile has no `<restrictions>` inside `<restrictions_user>`.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let document = AbxDocument::parse(Cursor::new(data))?;
+        let path = user_restrictions_path(&document.nodes).ok_or_else(|| {
+            AbxError::ParseError("No <restrictions> inside <restrictions_user>".to_string())
+        })?;
+        Ok(Self { document, path })
+    }
+
+    /// Whether policy `name` is set to `true`
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.restrictions().attribute(name) == Some(&AttributeValue::Boolean(true))
+    }
+
+    /// Names of the policies set to `true`, in document order
+    pub fn enabled(&self) -> impl Iterator<Item = &str> {
+        self.restrictions()
+            .attributes
+            .iter()
+            .filter(|a| a.value == AttributeValue::Boolean(true))
+            .map(|a| a.name.as_str())
+    }
+
+    /// Set policy `name` to `true`
+    ///
+    /// A new policy goes last, so its name is interned after every existing
+    /// policy and their indices stay the same; one that is already present is
+    /// set where it is.
+    pub fn enable(&mut self, name: &str) {
+        let restrictions = self.restrictions_mut();
+        let value = AttributeValue::Boolean(true);
+        match restrictions.attributes.iter_mut().find(|a| a.name == name) {
+            Some(existing) => existing.value = value,
+            None => restrictions.attributes.push(Attribute {
+                name: name.to_string(),
+                value,
+            }),
+        }
+    }
+
+    /// Remove policy `name`, returning whether it was present
+    pub fn disable(&mut self, name: &str) -> bool {
+        let restrictions = self.restrictions_mut();
+        let before = restrictions.attributes.len();
+        restrictions.attributes.retain(|a| a.name != name);
+        restrictions.attributes.len() != before
+    }
+
+    /// Encode the edited profile as ABX
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        self.document.to_bytes()
+    }
+
+    fn restrictions(&self) -> &Element {
+        let (first, rest) = self.path.split_first().expect("path is never empty");
+        let mut element = as_element(&self.document.nodes[*first]);
+        for &i in rest {
+            element = as_element(&element.children[i]);
+        }
+        element
+    }
+
+    fn restrictions_mut(&mut self) -> &mut Element {
+        let (first, rest) = self.path.split_first().expect("path is never empty");
+        let mut element = as_element_mut(&mut self.document.nodes[*first]);
+        for &i in rest {
+            element = as_element_mut(&mut element.children[i]);
+        }
+        element
+    }
+}
+
+fn as_element(node: &Node) -> &Element {
+    match node {
+        Node::Element(element) => element,
+        _ => unreachable!("the path only leads through elements"),
+    }
+}
+
+fn as_element_mut(node: &mut Node) -> &mut Element {
+    match node {
+        Node::Element(element) => element,
+        _ => unreachable!("the path only leads through elements"),
+    }
+}
+
+/// Child indices of the first `<restrictions>` directly inside a `<restrictions_user>`
+fn user_restrictions_path(nodes: &[Node]) -> Option<Vec<usize>> {
+    for (i, node) in nodes.iter().enumerate() {
+        let Node::Element(element) = node else {
+            continue;
+        };
+        if element.name == "restrictions_user" {
+            let position = element
+                .children
+                .iter()
+                .position(|child| matches!(child, Node::Element(e) if e.name == "restrictions"));
+            if let Some(j) = position {
+                return Some(vec![i, j]);
+            }
+        }
+        if let Some(mut path) = user_restrictions_path(&element.children) {
+            path.insert(0, i);
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Edits the policies of a user profile held in memory
+///
+/// Wraps `insert_restriction` and `remove_restriction` so several edits can
+/// be made before the result is written anywhere.
+///
+/// # Examples
+///
+/// ```no_run
+/// use honeycomb::PolicyEditor;
+/// use std::fs::File;
+///
+/// let mut editor = PolicyEditor::open("/data/system/users/0.xml").unwrap();
+/// editor.enable("no_install_apps").unwrap();
+/// editor.disable("no_usb_file_transfer").unwrap();
+/// editor.write_to(File::create("0.xml").unwrap()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct PolicyEditor {
+    buffer: Vec<u8>,
+}
+
+impl PolicyEditor {
+    /// Edit the profile in `buffer`
+    pub fn new(buffer: Vec<u8>) -> Self {
+        Self { buffer }
+    }
+
+    /// Read the profile at `path` for editing
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(std::fs::read(path)?))
+    }
+
+    /// Set the policy `name`, see `insert_restriction`
+    pub fn enable(&mut self, name: &str) -> Result<()> {
+        insert_restriction(&mut self.buffer, name)
+    }
+
+    /// Remove the policy `name`, returning whether it was set, see `remove_restriction`
+    pub fn disable(&mut self, name: &str) -> Result<bool> {
+        remove_restriction(&mut self.buffer, name)
+    }
+
+    /// The edited profile
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Write the edited profile to `writer` and flush it
+    pub fn write_to<W: Write>(self, mut writer: W) -> Result<()> {
+        writer.write_all(&self.buffer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AbxToXmlConverter;
+    use crate::test_support::AbxFixture;
+    use std::fs;
+
+    fn profile() -> Vec<u8> {
+        AbxFixture::new()
+            .start_tag("user")
+            .start_tag("restrictions")
+            .attribute_bool("not_a_policy", true)
+            .end_tag("restrictions")
+            .start_tag("restrictions_user")
+            .start_tag("restrictions")
+            .attribute_bool("no_usb", true)
+            .attribute_bool("no_sms", true)
+            .end_tag("restrictions")
+            .end_tag("restrictions_user")
+            .end_tag("user")
+            .build()
+    }
+
+    #[test]
+    fn test_restriction_node_offset() {
+        let data = profile();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), std::io::sink(), false).unwrap();
+        deserializer.deserialize().unwrap();
+        let offset = deserializer.get_restriction_node_offset().unwrap();
+        // The second <restrictions> is a back reference to the interned name
+        assert_eq!(
+            data[offset as usize - 3],
+            crate::START_TAG | crate::TYPE_STRING_INTERNED
+        );
+        assert_eq!(
+            data[offset as usize],
+            crate::ATTRIBUTE | crate::TYPE_BOOLEAN_TRUE
+        );
+
+        let data = AbxFixture::new()
+            .start_tag("restrictions")
+            .end_tag("restrictions")
+            .build();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), std::io::sink(), false).unwrap();
+        deserializer.deserialize().unwrap();
+        assert_eq!(deserializer.get_restriction_node_offset(), None);
+    }
+
+    #[test]
+    fn test_insert_restriction() {
+        let original = profile();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&original), std::io::sink(), false).unwrap();
+        deserializer.deserialize().unwrap();
+        let offset = deserializer.get_restriction_node_offset().unwrap() as usize;
+
+        let mut data = original.clone();
+        insert_restriction(&mut data, "no_camera").unwrap();
+        assert_eq!(data[..offset], original[..offset]);
+        let xml = AbxToXmlConverter::convert_bytes(&data).unwrap();
+        assert!(xml.contains(
+            "<restrictions_user><restrictions no_usb=\"true\" no_sms=\"true\" no_camera=\"true\">"
+        ));
+
+        // Already present: nothing is added
+        insert_restriction(&mut data, "no_usb").unwrap();
+        assert_eq!(AbxToXmlConverter::convert_bytes(&data).unwrap(), xml);
+
+        let mut data = AbxFixture::new().start_tag("user").end_tag("user").build();
+        assert!(insert_restriction(&mut data, "no_camera").is_err());
+    }
+
+    /// `profile()` followed by an element interned after the policies, as
+    /// real profiles have
+    fn profile_with_trailer() -> Vec<u8> {
+        AbxFixture::new()
+            .start_tag("user")
+            .start_tag("restrictions_user")
+            .start_tag("restrictions")
+            .attribute_bool("no_usb", true)
+            .attribute_bool("no_sms", true)
+            .end_tag("restrictions")
+            .end_tag("restrictions_user")
+            .start_tag("device_policy_local_restrictions")
+            .end_tag("device_policy_local_restrictions")
+            .end_tag("user")
+            .build()
+    }
+
+    #[test]
+    fn test_draining_boolean_policy() {
+        // A boolean policy is just its token and name; draining it leaves
+        // the stream aligned
+        let data = profile();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), std::io::sink(), true).unwrap();
+        deserializer.deserialize().unwrap();
+        let policy = deserializer.get_policies()[0].clone();
+        assert_eq!(policy.name, "no_usb");
+        assert_eq!(
+            data[policy.start_offset as usize],
+            crate::ATTRIBUTE | crate::TYPE_BOOLEAN_TRUE
+        );
+        // Token, 0xFFFF and the length-prefixed name
+        assert_eq!(policy.end_offset - policy.start_offset, 1 + 2 + 2 + 6);
+
+        let mut drained = data.clone();
+        drained.drain(policy.start_offset as usize..policy.end_offset as usize);
+        let xml = AbxToXmlConverter::convert_bytes(&drained).unwrap();
+        assert!(xml.contains("<restrictions_user><restrictions no_sms=\"true\"></restrictions>"));
+
+        // With a string interned after the policy, every later reference to
+        // it is now one too high
+        let data = profile_with_trailer();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), std::io::sink(), true).unwrap();
+        deserializer.deserialize().unwrap();
+        let policy = deserializer.get_policies()[0].clone();
+        let mut drained = data.clone();
+        drained.drain(policy.start_offset as usize..policy.end_offset as usize);
+        assert!(AbxDocument::parse(Cursor::new(&drained)).is_err());
+    }
+
+    #[test]
+    fn test_remove_restriction() {
+        let mut data = profile_with_trailer();
+        assert!(remove_restriction(&mut data, "no_usb").unwrap());
+        let document = AbxDocument::parse(Cursor::new(&data)).unwrap();
+        let xml = AbxToXmlConverter::convert_bytes(&data).unwrap();
+        assert!(xml.ends_with(
+            "<user><restrictions_user><restrictions no_sms=\"true\"></restrictions></restrictions_user>\
+             <device_policy_local_restrictions></device_policy_local_restrictions></user>"
+        ));
+        assert_eq!(document.to_bytes().unwrap(), data);
+
+        assert!(!remove_restriction(&mut data, "no_usb").unwrap());
+        let mut data = AbxFixture::new().start_tag("user").end_tag("user").build();
+        assert!(remove_restriction(&mut data, "no_usb").is_err());
+    }
+
+    #[test]
+    fn test_policy_editor_write_to() {
+        let mut editor = PolicyEditor::new(profile_with_trailer());
+        editor.enable("no_camera").unwrap();
+        assert!(editor.disable("no_usb").unwrap());
+
+        let mut output = Vec::new();
+        editor.write_to(&mut output).unwrap();
+        let xml = AbxToXmlConverter::convert_bytes(&output).unwrap();
+        assert!(xml.contains("<restrictions no_sms=\"true\" no_camera=\"true\"></restrictions>"));
+    }
+
+    #[test]
+    fn test_restriction_set() {
+        let data = profile();
+        let mut restrictions = RestrictionSet::from_bytes(&data).unwrap();
+        // Only the policies of <restrictions_user>
+        assert!(!restrictions.is_enabled("not_a_policy"));
+        assert!(restrictions.is_enabled("no_usb"));
+        assert!(!restrictions.is_enabled("no_camera"));
+
+        restrictions.enable("no_camera");
+        assert!(restrictions.disable("no_usb"));
+        assert!(!restrictions.disable("no_usb"));
+        assert_eq!(
+            restrictions.enabled().collect::<Vec<_>>(),
+            ["no_sms", "no_camera"]
+        );
+
+        let edited = restrictions.serialize().unwrap();
+        let reparsed = RestrictionSet::from_bytes(&edited).unwrap();
+        assert!(reparsed.is_enabled("no_camera"));
+        assert!(!reparsed.is_enabled("no_usb"));
+        let xml = AbxToXmlConverter::convert_bytes(&edited).unwrap();
+        assert!(xml.contains(
+            "<restrictions_user><restrictions no_sms=\"true\" no_camera=\"true\"></restrictions>"
+        ));
+
+        let data = AbxFixture::new().start_tag("user").end_tag("user").build();
+        assert!(RestrictionSet::from_bytes(&data).is_err());
+    }
+
+    #[test]
+    fn test_for_each_policy() {
+        let data = profile();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), std::io::sink(), true).unwrap();
+        deserializer.deserialize().unwrap();
+        let all = deserializer.get_policies();
+
+        let mut seen = Vec::new();
+        for_each_policy(Cursor::new(&data), |policy| {
+            seen.push(policy.clone());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0].name, "no_usb");
+        let expected = all.iter().find(|p| p.name == "no_sms").unwrap();
+        assert_eq!(
+            (seen[1].start_offset, seen[1].end_offset),
+            (expected.start_offset, expected.end_offset)
+        );
+
+        let mut calls = 0;
+        for_each_policy(Cursor::new(&data), |_| {
+            calls += 1;
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_get_restrictions() {
+        let data = AbxFixture::new()
+            .start_tag("user")
+            .attribute_int("id", 0)
+            .start_tag("restrictions_user")
+            .start_tag("restrictions")
+            .attribute_bool("no_usb", true)
+            .attribute_bool("no_camera", true)
+            .attribute_bool("no_usb", false)
+            .end_tag("restrictions")
+            .end_tag("restrictions_user")
+            .start_tag("restrictions")
+            .attribute_bool("no_sms", true)
+            .end_tag("restrictions")
+            .end_tag("user")
+            .build();
+        let mut deserializer =
+            BinaryXmlDeserializer::new(Cursor::new(&data), std::io::sink(), true).unwrap();
+        deserializer.deserialize().unwrap();
+
+        let restrictions = deserializer.get_restrictions();
+        let names: Vec<&str> = restrictions.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["no_camera", "no_usb"]);
+        let first_usb = deserializer
+            .get_policies()
+            .iter()
+            .find(|p| p.name == "no_usb")
+            .unwrap();
+        assert_eq!(
+            (restrictions[1].start_offset, restrictions[1].end_offset),
+            (first_usb.start_offset, first_usb.end_offset)
+        );
+    }
+
+    #[test]
+    fn test_validate_policies() {
+        let known: HashSet<String> = ["no_sms".to_string(), "no_camera".to_string()].into();
+        let unknown = validate_policies(Cursor::new(profile()), &known).unwrap();
+        assert_eq!(unknown, ["no_usb"]);
+
+        // Restrictions outside <restrictions_user> aren't user policies
+        let data = AbxFixture::new()
+            .start_tag("user")
+            .start_tag("restrictions_user")
+            .start_tag("restrictions")
+            .attribute_bool("no_usb", true)
+            .end_tag("restrictions")
+            .end_tag("restrictions_user")
+            .start_tag("device_policy_local_restrictions")
+            .start_tag("restrictions")
+            .attribute_bool("no_debugging", true)
+            .end_tag("restrictions")
+            .end_tag("device_policy_local_restrictions")
+            .end_tag("user")
+            .build();
+        let unknown = validate_policies(Cursor::new(data), &known).unwrap();
+        assert_eq!(unknown, ["no_usb"]);
+    }
+
+    #[test]
+    fn test_policy_list_and_readable_xml() {
+        let path = std::env::temp_dir().join(format!("honeycomb-policy-{}", std::process::id()));
+        fs::write(&path, profile()).unwrap();
+
+        assert_eq!(get_policy_list(&path).unwrap(), ["no_usb", "no_sms"]);
+        assert!(
+            get_readable_xml(&path)
+                .unwrap()
+                .contains("<restrictions no_usb=\"true\"")
+        );
+
+        fs::write(&path, b"not abx").unwrap();
+        assert!(get_policy_list(&path).is_err());
+        assert!(get_readable_xml(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
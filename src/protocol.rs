@@ -0,0 +1,29 @@
+//! The ABX wire format in one place, for tooling built on top of this crate
+//!
+//! Re-exports the magic headers, the token (`START_TAG`, ...) and type
+//! (`TYPE_*`) constants, and the typed [`Command`] and [`AttributeType`]
+//! enums, all of which are also available at the crate root.
+//!
+//! A token byte holds its command in the low nibble and its type in the high
+//! nibble; [`command_of`] and [`type_of`] split it.
+//!
+//! # Examples
+//!
+//! ```
+//! use honeycomb::protocol::*;
+//!
+//! let token = ATTRIBUTE | TYPE_INT;
+//! assert_eq!(command_of(token), ATTRIBUTE);
+//! assert_eq!(Command::try_from(token).unwrap(), Command::Attribute);
+//! assert_eq!(type_of(token), TYPE_INT);
+//! ```
+
+pub use crate::token::{AttributeType, Command, command_of, describe_token, type_of};
+pub use crate::{KNOWN_MAGICS, PROTOCOL_MAGIC_VERSION_0};
+
+pub use crate::{ATTRIBUTE, CDSECT, COMMENT, DOCDECL, END_DOCUMENT, END_TAG, ENTITY_REF};
+pub use crate::{IGNORABLE_WHITESPACE, PROCESSING_INSTRUCTION, START_DOCUMENT, START_TAG, TEXT};
+
+pub use crate::{TYPE_BOOLEAN_FALSE, TYPE_BOOLEAN_TRUE, TYPE_BYTES_BASE64, TYPE_BYTES_HEX};
+pub use crate::{TYPE_DOUBLE, TYPE_FLOAT, TYPE_INT, TYPE_INT_HEX, TYPE_LONG, TYPE_LONG_HEX};
+pub use crate::{TYPE_NULL, TYPE_STRING, TYPE_STRING_INTERNED};
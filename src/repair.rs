@@ -0,0 +1,109 @@
+//! Best-effort repair of ABX files with broken interned references
+
+use crate::{AbxDocument, AbxError, Result};
+use std::io::{Cursor, Read};
+
+/// Reference shifts tried by [`repair`], in order
+const REFERENCE_SHIFTS: [i32; 5] = [0, 1, -1, 2, -2];
+
+/// Rewrite a damaged ABX stream as valid ABX (experimental)
+///
+/// The input is parsed with interned references read as written, then as if
+/// every reference were off by one or two in either direction. The first
+/// reading under which every reference resolves and every end tag matches
+/// its start tag wins, and the tree is re-encoded with a clean interned
+/// table. A stream that ends early has its open elements closed.
+///
+/// What this can fix:
+/// - all references shifted by the same amount, up to two places
+/// - a missing `END_DOCUMENT` or missing end tags at the end of the stream
+///
+/// What it can't:
+/// - corrupt string definitions, since the table is rebuilt from them
+/// - shifts that differ from one reference to the next
+/// - corruption that happens to resolve and balance under a smaller shift
+///   than the real one, since the first shift that works is taken
+/// - damage outside the interned references, such as corrupt lengths or
+///   unknown token types
+///
+/// Check the result before relying on it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use honeycomb::repair;
+/// use std::fs::File;
+///
+/// let repaired = repair(File::open("broken.xml").unwrap()).unwrap();
+/// std::fs::write("repaired.xml", repaired).unwrap();
+/// ```
+pub fn repair<R: Read>(mut reader: R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let mut first_error = None;
+    for shift in REFERENCE_SHIFTS {
+        match AbxDocument::parse_with(Cursor::new(&data), shift, true) {
+            Ok(document) => return document.to_bytes(),
+            Err(error @ AbxError::InvalidMagicHeader { .. }) => return Err(error),
+            Err(error) => {
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    Err(AbxError::ParseError(format!(
+        "could not repair stream: {}",
+        first_error.map(|e| e.to_string()).unwrap_or_default()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+    use crate::{END_TAG, TYPE_STRING_INTERNED};
+
+    fn nested() -> Vec<u8> {
+        AbxFixture::new()
+            .start_tag("a")
+            .start_tag("b")
+            .end_tag("b")
+            .end_tag("a")
+            .build()
+    }
+
+    #[test]
+    fn test_repair_shifted_references() {
+        // End tags referencing one past the right string
+        let broken = AbxFixture::new()
+            .start_tag("a")
+            .start_tag("b")
+            .raw(&[END_TAG | TYPE_STRING_INTERNED, 0x00, 0x02])
+            .raw(&[END_TAG | TYPE_STRING_INTERNED, 0x00, 0x01])
+            .build();
+        assert!(AbxDocument::parse(Cursor::new(&broken)).is_err());
+
+        assert_eq!(repair(&broken[..]).unwrap(), nested());
+    }
+
+    #[test]
+    fn test_repair_closes_truncated_stream() {
+        let truncated = AbxFixture::new()
+            .start_tag("a")
+            .start_tag("b")
+            .build_unterminated();
+        assert_eq!(repair(&truncated[..]).unwrap(), nested());
+    }
+
+    #[test]
+    fn test_repair_gives_up() {
+        let broken = AbxFixture::new()
+            .start_tag("a")
+            .raw(&[END_TAG | TYPE_STRING_INTERNED, 0x00, 0x07])
+            .build();
+        match repair(&broken[..]) {
+            Err(AbxError::ParseError(msg)) => assert!(msg.starts_with("could not repair")),
+            other => panic!("Expected ParseError, got {:?}", other),
+        }
+    }
+}
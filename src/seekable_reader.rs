@@ -1,5 +1,8 @@
 use std::io::{self, Read, Seek, SeekFrom};
 
+/// Default number of bytes requested from the inner reader at a time
+pub const DEFAULT_CHUNK_SIZE: usize = 8192;
+
 /// A wrapper that provides limited seeking capability by buffering data
 ///
 /// This is useful for converting streams (like stdin) that don't naturally
@@ -9,19 +12,34 @@ pub struct SeekableReader<R: Read> {
     buffer: Vec<u8>,
     position: usize,
     end_reached: bool,
+    chunk_size: usize,
 }
 
 impl<R: Read> SeekableReader<R> {
     /// Create a new seekable reader wrapping the given reader
     pub fn new(inner: R) -> Self {
+        Self::with_chunk_size(inner, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Create a new seekable reader that reads `chunk_size` bytes at a time
+    ///
+    /// Larger chunks mean fewer reads on slow IO; smaller ones hand data over
+    /// sooner on interactive streams. A size of 0 is treated as 1.
+    pub fn with_chunk_size(inner: R, chunk_size: usize) -> Self {
         Self {
             inner,
             buffer: Vec::new(),
             position: 0,
             end_reached: false,
+            chunk_size: chunk_size.max(1),
         }
     }
 
+    /// Number of bytes requested from the inner reader at a time
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
     /// Get the current position in the buffer
     pub fn position(&self) -> usize {
         self.position
@@ -36,24 +54,30 @@ impl<R: Read> SeekableReader<R> {
     pub fn buffer_len(&self) -> usize {
         self.buffer.len()
     }
+
+    /// Append one chunk from the inner reader to the buffer
+    ///
+    /// Reads straight into the buffer's tail rather than through a temporary.
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let filled = self.buffer.len();
+        self.buffer.resize(filled + self.chunk_size, 0);
+        let result = self.inner.read(&mut self.buffer[filled..]);
+        let read = *result.as_ref().unwrap_or(&0);
+        self.buffer.truncate(filled + read);
+        if result? == 0 {
+            self.end_reached = true;
+        }
+        Ok(())
+    }
 }
 
 impl<R: Read> Read for SeekableReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        // If we need to read beyond what's currently buffered, read more data
-        while self.position + buf.len() > self.buffer.len() && !self.end_reached {
-            let mut temp_buf = vec![0u8; 8192]; // Read in 8KB chunks
-            match self.inner.read(&mut temp_buf) {
-                Ok(0) => {
-                    self.end_reached = true;
-                    break;
-                }
-                Ok(n) => {
-                    temp_buf.truncate(n);
-                    self.buffer.extend_from_slice(&temp_buf);
-                }
-                Err(e) => return Err(e),
-            }
+        // Only fetch more when nothing is left to hand out. Returning what is
+        // buffered is a valid short read, and `read_exact` asks again for the
+        // rest, so a large read buffers a chunk at a time instead of all at once.
+        while self.position >= self.buffer.len() && !self.end_reached {
+            self.read_chunk()?;
         }
 
         // Copy data from buffer to output buffer
@@ -77,46 +101,29 @@ impl<R: Read> Seek for SeekableReader<R> {
 
                 // If seeking beyond current buffer, read more data
                 while pos > self.buffer.len() && !self.end_reached {
-                    let mut temp_buf = vec![0u8; 8192];
-                    match self.inner.read(&mut temp_buf) {
-                        Ok(0) => {
-                            self.end_reached = true;
-                            break;
-                        }
-                        Ok(n) => {
-                            temp_buf.truncate(n);
-                            self.buffer.extend_from_slice(&temp_buf);
-                        }
-                        Err(e) => return Err(e),
-                    }
+                    self.read_chunk()?;
                 }
 
                 self.position = pos.min(self.buffer.len());
                 Ok(self.position as u64)
             }
             SeekFrom::Current(offset) => {
-                let new_pos = if offset >= 0 {
-                    self.position.saturating_add(offset as usize)
-                } else {
-                    self.position.saturating_sub((-offset) as usize)
-                };
-                self.seek(SeekFrom::Start(new_pos as u64))
+                // Like `std::io::Cursor`, seeking before the start is an error
+                // rather than silently clamping to 0
+                let new_pos = (self.position as u64)
+                    .checked_add_signed(offset)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "invalid seek to a negative or overflowing position",
+                        )
+                    })?;
+                self.seek(SeekFrom::Start(new_pos))
             }
             SeekFrom::End(_) => {
                 // Read all remaining data to find the end
                 while !self.end_reached {
-                    let mut temp_buf = vec![0u8; 8192];
-                    match self.inner.read(&mut temp_buf) {
-                        Ok(0) => {
-                            self.end_reached = true;
-                            break;
-                        }
-                        Ok(n) => {
-                            temp_buf.truncate(n);
-                            self.buffer.extend_from_slice(&temp_buf);
-                        }
-                        Err(e) => return Err(e),
-                    }
+                    self.read_chunk()?;
                 }
                 self.position = self.buffer.len();
                 Ok(self.position as u64)
@@ -128,3 +135,142 @@ impl<R: Read> Seek for SeekableReader<R> {
         Ok(self.position as u64)
     }
 }
+
+/// A window of `[start, end)` of a seekable reader, as a stream of its own
+///
+/// Positions are relative to `start`, and reads stop at `end`.
+pub(crate) struct RangeReader<R: Read + Seek> {
+    inner: R,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<R: Read + Seek> RangeReader<R> {
+    pub(crate) fn new(mut inner: R, start: u64, end: u64) -> io::Result<Self> {
+        if end < start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("range end {} is before its start {}", end, start),
+            ));
+        }
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            inner,
+            start,
+            len: end - start,
+            position: 0,
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for RangeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.position);
+        let limit = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..limit])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for RangeReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(target) => Some(target),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start of range"))?;
+        self.inner.seek(SeekFrom::Start(self.start + target))?;
+        self.position = target;
+        Ok(target)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::AbxFixture;
+    use crate::{ATTRIBUTE, AbxToXmlConverter, TYPE_BYTES_HEX};
+
+    /// Reader that hands out at most `max` bytes per call, like a pipe
+    struct Trickle<'a> {
+        data: &'a [u8],
+        max: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.max).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_large_read_over_short_reads() {
+        let value = vec![0xAB; 60 * 1024];
+        let data = AbxFixture::new()
+            .start_tag("r")
+            .raw(&[ATTRIBUTE | TYPE_BYTES_HEX, 0xFF, 0xFF, 0x00, 0x01])
+            .raw(b"v")
+            .raw(&(value.len() as u16).to_be_bytes())
+            .raw(&value)
+            .end_tag("r")
+            .build();
+
+        let mut reader = SeekableReader::new(Trickle {
+            data: &data,
+            max: 1000,
+        });
+        let mut buf = vec![0u8; 5000];
+        // A single read hands back what one chunk delivered
+        assert_eq!(reader.read(&mut buf).unwrap(), 1000);
+        assert_eq!(reader.buffer_len(), 1000);
+
+        let reader = SeekableReader::new(Trickle {
+            data: &data,
+            max: 1000,
+        });
+        let mut output = Vec::new();
+        AbxToXmlConverter::convert(reader, &mut output).unwrap();
+        let xml = String::from_utf8(output).unwrap();
+        assert!(xml.contains(&format!("<r v=\"{}\"></r>", "AB".repeat(value.len()))));
+    }
+
+    #[test]
+    fn test_seek_current_before_start_errors() {
+        let mut reader = SeekableReader::new(&b"abcdef"[..]);
+        reader.seek(SeekFrom::Start(2)).unwrap();
+
+        let err = reader.seek(SeekFrom::Current(-3)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(reader.position(), 2);
+
+        assert_eq!(reader.seek(SeekFrom::Current(-2)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        let mut reader = SeekableReader::with_chunk_size(&b"abcdefgh"[..], 3);
+        assert_eq!(reader.chunk_size(), 3);
+
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.buffer_len(), 3);
+
+        assert_eq!(reader.seek(SeekFrom::Start(4)).unwrap(), 4);
+        assert_eq!(reader.buffer_len(), 6);
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"efgh");
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 8);
+    }
+}
@@ -0,0 +1,333 @@
+//! Minimal reader for ustar archives
+//!
+//! Only what's needed to pull regular files out of a device dump: ustar and
+//! old-style headers, GNU long names (`L`) and the `path` record of PAX
+//! headers (`x`). Other entry types are skipped. Gzip-compressed archives
+//! are decompressed by [`crate::gzip`] before they get here.
+
+use crate::{AbxError, Result};
+use std::io::{self, Read};
+
+const BLOCK_SIZE: u64 = 512;
+
+/// Largest entry read into memory; ABX files are far smaller, and a corrupt
+/// size field mustn't turn into a multi-gigabyte allocation
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Largest GNU long name or PAX header read
+const MAX_HEADER_DATA: u64 = 1024 * 1024;
+
+/// An ABX file read from an archive
+pub(crate) struct TarEntry {
+    /// Path of the entry as stored in the archive
+    pub(crate) path: String,
+    /// Contents of the entry, or why they weren't read
+    pub(crate) data: Result<Vec<u8>>,
+}
+
+/// Iterator over the regular files of a tar stream that start with one of
+/// the accepted magic headers
+///
+/// Other files are skipped without being buffered. A bad header ends the
+/// iteration with an error that gives its offset, since the archive can't be
+/// followed past it.
+pub(crate) struct TarReader<'a, R: Read> {
+    reader: R,
+    magics: &'a [[u8; 4]],
+    /// Entries larger than this are reported instead of read
+    max_entry_size: u64,
+    /// Bytes of the archive consumed so far
+    position: u64,
+    finished: bool,
+}
+
+impl<'a, R: Read> TarReader<'a, R> {
+    pub(crate) fn new(reader: R, magics: &'a [[u8; 4]]) -> Self {
+        Self {
+            reader,
+            magics,
+            max_entry_size: MAX_ENTRY_SIZE,
+            position: 0,
+            finished: false,
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<TarEntry>> {
+        let mut long_path = None;
+        loop {
+            let mut header = [0u8; BLOCK_SIZE as usize];
+            if !read_block(&mut self.reader, &mut header)? || header.iter().all(|&b| b == 0) {
+                // A zero block (or a missing end marker) ends the archive
+                return Ok(None);
+            }
+            self.position += BLOCK_SIZE;
+            verify_checksum(&header)?;
+
+            let size = parse_octal(&header[124..136])?;
+            let padded = size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+            match header[156] {
+                b'0' | 0 => {
+                    let path = long_path.take().unwrap_or_else(|| header_path(&header));
+                    let mut magic = [0u8; 4];
+                    let sniffed = size.min(4);
+                    self.read_exact(&mut magic[..sniffed as usize])?;
+                    if sniffed < 4 || !self.magics.contains(&magic) {
+                        self.skip(padded - sniffed)?;
+                        continue;
+                    }
+                    if size > self.max_entry_size {
+                        self.skip(padded - sniffed)?;
+                        let data = Err(AbxError::ParseError(format!(
+                            "tar entry is {} bytes, over the {} byte limit",
+                            size, self.max_entry_size
+                        )));
+                        return Ok(Some(TarEntry { path, data }));
+                    }
+                    let mut data = vec![0u8; size as usize];
+                    data[..4].copy_from_slice(&magic);
+                    self.read_exact(&mut data[4..])?;
+                    self.skip(padded - size)?;
+                    return Ok(Some(TarEntry {
+                        path,
+                        data: Ok(data),
+                    }));
+                }
+                b'L' | b'x' => {
+                    if size > MAX_HEADER_DATA {
+                        return Err(AbxError::ParseError(format!(
+                            "tar extended header is {} bytes, over the {} byte limit",
+                            size, MAX_HEADER_DATA
+                        )));
+                    }
+                    let mut data = vec![0u8; size as usize];
+                    self.read_exact(&mut data)?;
+                    self.skip(padded - size)?;
+                    if header[156] == b'L' {
+                        long_path = Some(c_string(&data));
+                    } else if let Some(path) = pax_path(&data) {
+                        long_path = Some(path);
+                    }
+                }
+                _ => {
+                    self.skip(padded)?;
+                    long_path = None;
+                }
+            }
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reader.read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Discard `length` bytes without buffering them
+    fn skip(&mut self, length: u64) -> Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(length), &mut io::sink())?;
+        self.position += skipped;
+        if skipped < length {
+            return Err(AbxError::ReadError("tar entry data".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for TarReader<'_, R> {
+    type Item = Result<TarEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let header_offset = self.position;
+        let entry = self
+            .next_entry()
+            .map_err(|e| {
+                AbxError::ParseError(format!(
+                    "tar archive unreadable after offset {}: {}",
+                    header_offset, e
+                ))
+            })
+            .transpose();
+        if !matches!(entry, Some(Ok(_))) {
+            self.finished = true;
+        }
+        entry
+    }
+}
+
+/// Name of the compression an archive starting with `prefix` uses, for the
+/// formats that can't be read
+pub(crate) fn unsupported_compression(prefix: &[u8]) -> Option<&'static str> {
+    const FORMATS: [(&[u8], &str); 3] = [
+        (&[0xFD, b'7', b'z', b'X', b'Z', 0], "xz"),
+        (b"BZh", "bzip2"),
+        (&[0x28, 0xB5, 0x2F, 0xFD], "zstd"),
+    ];
+    FORMATS
+        .iter()
+        .find(|(magic, _)| prefix.starts_with(magic))
+        .map(|&(_, name)| name)
+}
+
+/// Fill `block`, returning `false` at a clean end of stream
+fn read_block<R: Read>(reader: &mut R, block: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match reader.read(&mut block[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(AbxError::ReadError("tar header".to_string())),
+            n => filled += n,
+        }
+    }
+    Ok(true)
+}
+
+fn verify_checksum(header: &[u8; BLOCK_SIZE as usize]) -> Result<()> {
+    let expected = parse_octal(&header[148..156])?;
+    // The checksum field itself counts as spaces
+    let actual: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+        .sum();
+    if actual != expected {
+        return Err(AbxError::ParseError(
+            "tar header checksum mismatch".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64> {
+    let digits = field
+        .iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|&&b| (b'0'..=b'7').contains(&b));
+    let mut value = 0u64;
+    for &digit in digits {
+        value = value
+            .checked_mul(8)
+            .and_then(|v| v.checked_add((digit - b'0') as u64))
+            .ok_or_else(|| AbxError::ParseError("tar numeric field overflow".to_string()))?;
+    }
+    Ok(value)
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Name of a header, joined with the ustar prefix field when present
+fn header_path(header: &[u8; BLOCK_SIZE as usize]) -> String {
+    let name = c_string(&header[0..100]);
+    if &header[257..262] == b"ustar" {
+        let prefix = c_string(&header[345..500]);
+        if !prefix.is_empty() {
+            return format!("{}/{}", prefix, name);
+        }
+    }
+    name
+}
+
+/// The `path` record of a PAX extended header, if any
+///
+/// Records look like `"<len> <key>=<value>\n"`.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.split_inclusive('\n').find_map(|record| {
+        let (_, rest) = record.split_once(' ')?;
+        let value = rest.strip_prefix("path=")?;
+        Some(value.trim_end_matches('\n').to_string())
+    })
+}
+
+/// Build tar archives in tests
+#[cfg(test)]
+pub(crate) fn archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (path, contents) in entries {
+        let mut header = [0u8; BLOCK_SIZE as usize];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+        data.extend_from_slice(&header);
+        data.extend_from_slice(contents);
+        let block = BLOCK_SIZE as usize;
+        data.resize(data.len().div_ceil(block) * block, 0);
+    }
+    data.extend_from_slice(&[0u8; 2 * BLOCK_SIZE as usize]);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KNOWN_MAGICS;
+
+    fn read_all(data: &[u8]) -> Vec<Result<TarEntry>> {
+        TarReader::new(data, KNOWN_MAGICS).collect()
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let mut large = b"ABX\0".to_vec();
+        large.resize(600, 0);
+        let data = archive(&[
+            ("a.abx", b"ABX\0hello"),
+            ("notes.txt", b"hello"),
+            ("dir/b.abx", &large),
+        ]);
+        let entries: Vec<TarEntry> = read_all(&data).into_iter().collect::<Result<_>>().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "a.abx");
+        assert_eq!(entries[0].data.as_ref().unwrap(), b"ABX\0hello");
+        assert_eq!(entries[1].path, "dir/b.abx");
+        assert_eq!(entries[1].data.as_ref().unwrap().len(), 600);
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut data = archive(&[("a.abx", b"ABX\0")]);
+        data[0] = b'b';
+        let err = read_all(&data).remove(0).err().unwrap();
+        assert!(err.to_string().contains("after offset 0"), "{}", err);
+    }
+
+    #[test]
+    fn test_oversized_entry_is_reported() {
+        let data = archive(&[("a.abx", b"ABX\0 too large"), ("b.abx", b"ABX\0")]);
+        let mut reader = TarReader::new(&data[..], KNOWN_MAGICS);
+        reader.max_entry_size = 8;
+        let entries: Vec<TarEntry> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(entries.len(), 2);
+        let err = entries[0].data.as_ref().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error: tar entry is 14 bytes, over the 8 byte limit"
+        );
+        assert_eq!(entries[1].data.as_ref().unwrap(), b"ABX\0");
+    }
+
+    #[test]
+    fn test_size_past_end_of_archive() {
+        // Claim 8 GiB for an entry holding only a magic header
+        let mut data = archive(&[("a.abx", b"ABX\0")]);
+        data[124..136].copy_from_slice(b"77777777777\0");
+        data[148..156].copy_from_slice(b"        ");
+        let checksum: u64 = data[..512].iter().map(|&b| b as u64).sum();
+        data[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+        let entries = read_all(&data);
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! Self-deleting temporary files for handing XML to external tools
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Attempts at a fresh name before giving up
+const MAX_ATTEMPTS: u32 = 64;
+
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// A uniquely named file in the system temp dir, deleted when dropped
+///
+/// Call [`keep`](Self::keep) to hold on to the file past the handle.
+#[derive(Debug)]
+pub struct NamedTempFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl NamedTempFile {
+    /// Create an empty file named `honeycomb-<unique>.xml` in the temp dir
+    pub(crate) fn new() -> io::Result<Self> {
+        let dir = std::env::temp_dir();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        for _ in 0..MAX_ATTEMPTS {
+            let name = format!(
+                "honeycomb-{}-{:08x}-{}.xml",
+                std::process::id(),
+                nanos,
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = dir.join(name);
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => {
+                    return Ok(Self {
+                        path,
+                        file: Some(file),
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "could not find an unused temp file name",
+        ))
+    }
+
+    /// Path of the file, valid until the handle is dropped
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The open file, positioned after the last write
+    pub fn as_file(&self) -> &File {
+        self.file.as_ref().expect("file is only taken by keep")
+    }
+
+    /// Close the file without deleting it and return its path
+    pub fn keep(mut self) -> PathBuf {
+        self.file = None;
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Drop for NamedTempFile {
+    fn drop(&mut self) {
+        // Close before removing so this also works on Windows
+        self.file = None;
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Helpers for building ABX fixtures in unit tests
+
+#![allow(dead_code)]
+
+use crate::{
+    ATTRIBUTE, END_DOCUMENT, END_TAG, PROTOCOL_MAGIC_VERSION_0, START_DOCUMENT, START_TAG,
+};
+use crate::{TEXT, TYPE_BOOLEAN_FALSE, TYPE_BOOLEAN_TRUE, TYPE_INT, TYPE_STRING};
+use crate::{TYPE_NULL, TYPE_STRING_INTERNED};
+
+/// Incrementally writes an ABX byte stream
+pub(crate) struct AbxFixture {
+    data: Vec<u8>,
+    interned: Vec<String>,
+}
+
+impl AbxFixture {
+    /// Start a document: magic header followed by `START_DOCUMENT`
+    pub(crate) fn new() -> Self {
+        let mut data = PROTOCOL_MAGIC_VERSION_0.to_vec();
+        data.push(START_DOCUMENT | TYPE_NULL);
+        Self {
+            data,
+            interned: Vec::new(),
+        }
+    }
+
+    /// Current length of the stream, i.e. the offset of the next token
+    pub(crate) fn offset(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    pub(crate) fn start_tag(&mut self, name: &str) -> &mut Self {
+        self.data.push(START_TAG | TYPE_STRING_INTERNED);
+        self.interned(name)
+    }
+
+    pub(crate) fn end_tag(&mut self, name: &str) -> &mut Self {
+        self.data.push(END_TAG | TYPE_STRING_INTERNED);
+        self.interned(name)
+    }
+
+    pub(crate) fn attribute_bool(&mut self, name: &str, value: bool) -> &mut Self {
+        let type_info = if value {
+            TYPE_BOOLEAN_TRUE
+        } else {
+            TYPE_BOOLEAN_FALSE
+        };
+        self.data.push(ATTRIBUTE | type_info);
+        self.interned(name)
+    }
+
+    pub(crate) fn attribute_string(&mut self, name: &str, value: &str) -> &mut Self {
+        self.data.push(ATTRIBUTE | TYPE_STRING);
+        self.interned(name);
+        self.utf(value)
+    }
+
+    pub(crate) fn attribute_int(&mut self, name: &str, value: i32) -> &mut Self {
+        self.data.push(ATTRIBUTE | TYPE_INT);
+        self.interned(name);
+        self.raw(&value.to_be_bytes())
+    }
+
+    pub(crate) fn text(&mut self, text: &str) -> &mut Self {
+        self.data.push(TEXT | TYPE_STRING);
+        self.utf(text)
+    }
+
+    /// Text stored as an interned string, as some serializers write it
+    pub(crate) fn text_interned(&mut self, text: &str) -> &mut Self {
+        self.data.push(TEXT | TYPE_STRING_INTERNED);
+        self.interned(text)
+    }
+
+    /// Append bytes verbatim
+    pub(crate) fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    /// Finish with `END_DOCUMENT` and return the bytes
+    pub(crate) fn build(&mut self) -> Vec<u8> {
+        self.data.push(END_DOCUMENT | TYPE_NULL);
+        self.data.clone()
+    }
+
+    /// Return the bytes written so far, without `END_DOCUMENT`
+    pub(crate) fn build_unterminated(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    fn utf(&mut self, s: &str) -> &mut Self {
+        self.data.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        self.data.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    fn interned(&mut self, s: &str) -> &mut Self {
+        match self.interned.iter().position(|i| i == s) {
+            Some(index) => self.data.extend_from_slice(&(index as u16).to_be_bytes()),
+            None => {
+                self.data.extend_from_slice(&0xFFFFu16.to_be_bytes());
+                self.interned.push(s.to_string());
+                self.utf(s);
+            }
+        }
+        self
+    }
+}
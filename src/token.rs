@@ -0,0 +1,240 @@
+use crate::{ATTRIBUTE, CDSECT, COMMENT, DOCDECL, END_DOCUMENT, END_TAG, ENTITY_REF};
+use crate::{AbxError, IGNORABLE_WHITESPACE, PROCESSING_INSTRUCTION, START_DOCUMENT, START_TAG};
+use crate::{TEXT, TYPE_BOOLEAN_FALSE, TYPE_BOOLEAN_TRUE, TYPE_BYTES_BASE64, TYPE_BYTES_HEX};
+use crate::{TYPE_DOUBLE, TYPE_FLOAT, TYPE_INT, TYPE_INT_HEX, TYPE_LONG, TYPE_LONG_HEX};
+use crate::{TYPE_STRING, TYPE_STRING_INTERNED};
+
+/// Extract the command (low nibble) from a token byte
+pub fn command_of(token: u8) -> u8 {
+    token & 0x0F
+}
+
+/// Extract the type (high nibble) from a token byte
+pub fn type_of(token: u8) -> u8 {
+    token & 0xF0
+}
+
+/// Describe a token byte for humans, e.g. `START_TAG` or `ATTRIBUTE|TYPE_INT`
+///
+/// Attribute tokens include their type; for other commands the type nibble is
+/// left out. Unknown nibbles are shown in hex, e.g. `unknown command 0x0B`.
+pub fn describe_token(token: u8) -> String {
+    match Command::try_from(token) {
+        Ok(Command::Attribute) => match AttributeType::try_from(token) {
+            Ok(attribute_type) => format!("ATTRIBUTE|{}", attribute_type.name()),
+            Err(_) => format!("ATTRIBUTE|unknown attribute type 0x{:02X}", type_of(token)),
+        },
+        Ok(command) => command.name().to_string(),
+        Err(_) => format!("unknown command 0x{:02X}", command_of(token)),
+    }
+}
+
+/// Typed view of the command nibble of a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    StartDocument,
+    EndDocument,
+    StartTag,
+    EndTag,
+    Text,
+    Cdsect,
+    EntityRef,
+    IgnorableWhitespace,
+    ProcessingInstruction,
+    Comment,
+    Docdecl,
+    Attribute,
+}
+
+impl Command {
+    /// Name of the command's constant, e.g. `START_TAG`
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::StartDocument => "START_DOCUMENT",
+            Command::EndDocument => "END_DOCUMENT",
+            Command::StartTag => "START_TAG",
+            Command::EndTag => "END_TAG",
+            Command::Text => "TEXT",
+            Command::Cdsect => "CDSECT",
+            Command::EntityRef => "ENTITY_REF",
+            Command::IgnorableWhitespace => "IGNORABLE_WHITESPACE",
+            Command::ProcessingInstruction => "PROCESSING_INSTRUCTION",
+            Command::Comment => "COMMENT",
+            Command::Docdecl => "DOCDECL",
+            Command::Attribute => "ATTRIBUTE",
+        }
+    }
+}
+
+impl TryFrom<u8> for Command {
+    type Error = AbxError;
+
+    /// Decode a token byte; only its command nibble is considered
+    fn try_from(token: u8) -> Result<Self, Self::Error> {
+        match command_of(token) {
+            START_DOCUMENT => Ok(Command::StartDocument),
+            END_DOCUMENT => Ok(Command::EndDocument),
+            START_TAG => Ok(Command::StartTag),
+            END_TAG => Ok(Command::EndTag),
+            TEXT => Ok(Command::Text),
+            CDSECT => Ok(Command::Cdsect),
+            ENTITY_REF => Ok(Command::EntityRef),
+            IGNORABLE_WHITESPACE => Ok(Command::IgnorableWhitespace),
+            PROCESSING_INSTRUCTION => Ok(Command::ProcessingInstruction),
+            COMMENT => Ok(Command::Comment),
+            DOCDECL => Ok(Command::Docdecl),
+            ATTRIBUTE => Ok(Command::Attribute),
+            command => Err(AbxError::UnknownCommand(command)),
+        }
+    }
+}
+
+impl From<Command> for u8 {
+    fn from(command: Command) -> u8 {
+        match command {
+            Command::StartDocument => START_DOCUMENT,
+            Command::EndDocument => END_DOCUMENT,
+            Command::StartTag => START_TAG,
+            Command::EndTag => END_TAG,
+            Command::Text => TEXT,
+            Command::Cdsect => CDSECT,
+            Command::EntityRef => ENTITY_REF,
+            Command::IgnorableWhitespace => IGNORABLE_WHITESPACE,
+            Command::ProcessingInstruction => PROCESSING_INSTRUCTION,
+            Command::Comment => COMMENT,
+            Command::Docdecl => DOCDECL,
+            Command::Attribute => ATTRIBUTE,
+        }
+    }
+}
+
+/// Typed view of the type nibble of a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttributeType {
+    String,
+    StringInterned,
+    BytesHex,
+    BytesBase64,
+    Int,
+    IntHex,
+    Long,
+    LongHex,
+    Float,
+    Double,
+    BooleanTrue,
+    BooleanFalse,
+}
+
+impl AttributeType {
+    /// Name of the type's constant, e.g. `TYPE_INT`
+    pub fn name(self) -> &'static str {
+        match self {
+            AttributeType::String => "TYPE_STRING",
+            AttributeType::StringInterned => "TYPE_STRING_INTERNED",
+            AttributeType::BytesHex => "TYPE_BYTES_HEX",
+            AttributeType::BytesBase64 => "TYPE_BYTES_BASE64",
+            AttributeType::Int => "TYPE_INT",
+            AttributeType::IntHex => "TYPE_INT_HEX",
+            AttributeType::Long => "TYPE_LONG",
+            AttributeType::LongHex => "TYPE_LONG_HEX",
+            AttributeType::Float => "TYPE_FLOAT",
+            AttributeType::Double => "TYPE_DOUBLE",
+            AttributeType::BooleanTrue => "TYPE_BOOLEAN_TRUE",
+            AttributeType::BooleanFalse => "TYPE_BOOLEAN_FALSE",
+        }
+    }
+}
+
+impl TryFrom<u8> for AttributeType {
+    type Error = AbxError;
+
+    /// Decode a token byte; only its type nibble is considered
+    fn try_from(token: u8) -> Result<Self, Self::Error> {
+        match type_of(token) {
+            TYPE_STRING => Ok(AttributeType::String),
+            TYPE_STRING_INTERNED => Ok(AttributeType::StringInterned),
+            TYPE_BYTES_HEX => Ok(AttributeType::BytesHex),
+            TYPE_BYTES_BASE64 => Ok(AttributeType::BytesBase64),
+            TYPE_INT => Ok(AttributeType::Int),
+            TYPE_INT_HEX => Ok(AttributeType::IntHex),
+            TYPE_LONG => Ok(AttributeType::Long),
+            TYPE_LONG_HEX => Ok(AttributeType::LongHex),
+            TYPE_FLOAT => Ok(AttributeType::Float),
+            TYPE_DOUBLE => Ok(AttributeType::Double),
+            TYPE_BOOLEAN_TRUE => Ok(AttributeType::BooleanTrue),
+            TYPE_BOOLEAN_FALSE => Ok(AttributeType::BooleanFalse),
+            type_info => Err(AbxError::UnknownAttributeType(type_info)),
+        }
+    }
+}
+
+impl From<AttributeType> for u8 {
+    fn from(attribute_type: AttributeType) -> u8 {
+        match attribute_type {
+            AttributeType::String => TYPE_STRING,
+            AttributeType::StringInterned => TYPE_STRING_INTERNED,
+            AttributeType::BytesHex => TYPE_BYTES_HEX,
+            AttributeType::BytesBase64 => TYPE_BYTES_BASE64,
+            AttributeType::Int => TYPE_INT,
+            AttributeType::IntHex => TYPE_INT_HEX,
+            AttributeType::Long => TYPE_LONG,
+            AttributeType::LongHex => TYPE_LONG_HEX,
+            AttributeType::Float => TYPE_FLOAT,
+            AttributeType::Double => TYPE_DOUBLE,
+            AttributeType::BooleanTrue => TYPE_BOOLEAN_TRUE,
+            AttributeType::BooleanFalse => TYPE_BOOLEAN_FALSE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nibbles() {
+        let token = ATTRIBUTE | TYPE_INT_HEX;
+        assert_eq!(command_of(token), ATTRIBUTE);
+        assert_eq!(type_of(token), TYPE_INT_HEX);
+        assert_eq!(Command::try_from(token).unwrap(), Command::Attribute);
+        assert_eq!(
+            AttributeType::try_from(token).unwrap(),
+            AttributeType::IntHex
+        );
+    }
+
+    #[test]
+    fn test_round_trip_and_unknown() {
+        for byte in 0..=0x0Fu8 {
+            if let Ok(command) = Command::try_from(byte) {
+                assert_eq!(u8::from(command), byte);
+            }
+        }
+        assert!(matches!(
+            Command::try_from(0x0B),
+            Err(AbxError::UnknownCommand(0x0B))
+        ));
+        assert!(matches!(
+            AttributeType::try_from(0xE0),
+            Err(AbxError::UnknownAttributeType(0xE0))
+        ));
+    }
+
+    #[test]
+    fn test_describe_token() {
+        assert_eq!(
+            describe_token(START_TAG | TYPE_STRING_INTERNED),
+            "START_TAG"
+        );
+        assert_eq!(describe_token(ATTRIBUTE | TYPE_INT), "ATTRIBUTE|TYPE_INT");
+        assert_eq!(describe_token(0x0B), "unknown command 0x0B");
+        assert_eq!(
+            describe_token(ATTRIBUTE | 0xE0),
+            "ATTRIBUTE|unknown attribute type 0xE0"
+        );
+        assert_eq!(
+            AbxError::UnknownAttributeType(0xE0).to_string(),
+            "Unknown attribute type: 0xE0"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! ABX to ABX transforms that drop elements from a parsed document
+
+use crate::{AbxDocument, Attribute, Node, Result};
+use std::io::{Read, Seek, Write};
+
+/// What a filter sees of an element
+#[derive(Debug, Clone, Copy)]
+pub struct ElementInfo<'a> {
+    /// Tag name of the element
+    pub name: &'a str,
+    /// Tag names of the enclosing elements, outermost first
+    pub ancestors: &'a [String],
+    /// The element's attributes in document order
+    pub attributes: &'a [Attribute],
+}
+
+/// Whether a filtered element stays in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepOrDrop {
+    Keep,
+    /// Remove the element along with everything inside it
+    Drop,
+}
+
+/// Copy ABX from `reader` to `writer`, leaving out elements the filter drops
+///
+/// The filter is called for each element in document order; descendants of
+/// a dropped element aren't visited.
+///
+/// This isn't a streaming transform: the whole document is read into an
+/// [`AbxDocument`] first, so memory grows with the input, and it is parsed
+/// strictly, so input that only converts in lenient mode is rejected. The
+/// output is re-encoded with [`AbxDocument::write_to`] rather than copied
+/// byte for byte. The interned table is rebuilt to match what remains, and
+/// kept elements are otherwise unchanged.
+///
+/// # Examples
+///
+/// ```no_run
+/// use honeycomb::{KeepOrDrop, filter_elements};
+/// use std::fs::File;
+///
+/// let input = File::open("0.xml").unwrap();
+/// let output = File::create("0.redacted.xml").unwrap();
+/// filter_elements(input, output, &mut |element| {
+///     if element.name == "account" {
+///         KeepOrDrop::Drop
+///     } else {
+///         KeepOrDrop::Keep
+///     }
+/// })
+/// .unwrap();
+/// ```
+pub fn filter_elements<R: Read + Seek, W: Write>(
+    reader: R,
+    writer: W,
+    filter: &mut dyn FnMut(&ElementInfo) -> KeepOrDrop,
+) -> Result<()> {
+    let mut document = AbxDocument::parse(reader)?;
+    let mut ancestors = Vec::new();
+    retain_nodes(&mut document.nodes, &mut ancestors, filter);
+    document.write_to(writer)
+}
+
+fn retain_nodes(
+    nodes: &mut Vec<Node>,
+    ancestors: &mut Vec<String>,
+    filter: &mut dyn FnMut(&ElementInfo) -> KeepOrDrop,
+) {
+    nodes.retain_mut(|node| {
+        let Node::Element(element) = node else {
+            return true;
+        };
+        let info = ElementInfo {
+            name: &element.name,
+            ancestors,
+            attributes: &element.attributes,
+        };
+        if filter(&info) == KeepOrDrop::Drop {
+            return false;
+        }
+        ancestors.push(element.name.clone());
+        retain_nodes(&mut element.children, ancestors, filter);
+        ancestors.pop();
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AbxToXmlConverter;
+    use crate::test_support::AbxFixture;
+    use std::io::Cursor;
+
+    fn profile() -> Vec<u8> {
+        AbxFixture::new()
+            .start_tag("user")
+            .start_tag("accounts")
+            .start_tag("account")
+            .attribute_string("token", "secret")
+            .end_tag("account")
+            .start_tag("account")
+            .attribute_string("token", "other")
+            .end_tag("account")
+            .end_tag("accounts")
+            .start_tag("restrictions")
+            .attribute_bool("no_sms", true)
+            .end_tag("restrictions")
+            .end_tag("user")
+            .build()
+    }
+
+    fn filter(data: &[u8], filter: &mut dyn FnMut(&ElementInfo) -> KeepOrDrop) -> Vec<u8> {
+        let mut output = Vec::new();
+        filter_elements(Cursor::new(data), &mut output, filter).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_filter_drops_elements() {
+        let data = profile();
+        let mut seen = Vec::new();
+        let output = filter(&data, &mut |element| {
+            seen.push((element.ancestors.join("/"), element.name.to_string()));
+            if element.name == "account" {
+                KeepOrDrop::Drop
+            } else {
+                KeepOrDrop::Keep
+            }
+        });
+
+        let xml = AbxToXmlConverter::convert_bytes(&output).unwrap();
+        assert!(xml.ends_with(
+            "<user><accounts></accounts><restrictions no_sms=\"true\"></restrictions></user>"
+        ));
+        assert!(!xml.contains("secret"));
+        assert_eq!(
+            seen[2],
+            ("user/accounts".to_string(), "account".to_string())
+        );
+    }
+
+    #[test]
+    fn test_filter_keeping_everything_is_byte_identical() {
+        let data = profile();
+        assert_eq!(filter(&data, &mut |_| KeepOrDrop::Keep), data);
+    }
+}